@@ -0,0 +1,86 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::collections::HashSet;
+use std::hint::black_box;
+use toy_dns_server::{
+    NameTrie, Type, ZoneConfig, construct_reply, find_record, parse_dns_query,
+};
+
+fn example_config() -> ZoneConfig {
+    ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone")
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let data = std::fs::read("tests/example.query.bin")
+        .expect("Failed to read example query");
+    c.bench_function("parse_dns_query", |b| {
+        b.iter(|| parse_dns_query(black_box(&data)).unwrap());
+    });
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let config = example_config();
+    let data = std::fs::read("tests/example.query.bin")
+        .expect("Failed to read example query");
+    let query = parse_dns_query(&data).unwrap();
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    c.bench_function("serialize_reply", |b| {
+        b.iter(|| black_box(&reply).serialize());
+    });
+}
+
+fn bench_find_record(c: &mut Criterion) {
+    let config = example_config();
+    c.bench_function("find_record", |b| {
+        b.iter(|| {
+            find_record(black_box(&config), black_box("example.com"), Type::A)
+        });
+    });
+    c.bench_function("find_record_missing", |b| {
+        b.iter(|| {
+            find_record(
+                black_box(&config),
+                black_box("nonexistent.example.com"),
+                Type::A,
+            )
+        });
+    });
+}
+
+/// A blocklist-sized set, half names and half wildcards, looked up by
+/// walking the trie and by scanning every entry as a naive list would.
+fn bench_name_trie(c: &mut Criterion) {
+    let entries: Vec<String> = (0..100_000)
+        .map(|i| match i % 2 {
+            0 => format!("host{i}.ads{}.example", i % 1000),
+            _ => format!("*.tracker{i}.example"),
+        })
+        .collect();
+    let mut trie = NameTrie::default();
+    for entry in &entries {
+        trie.insert(entry);
+    }
+    let set: HashSet<&str> = entries.iter().map(String::as_str).collect();
+    let scan = |name: &str| {
+        set.iter().any(|entry| match entry.strip_prefix('*') {
+            Some(suffix) => name.ends_with(suffix),
+            None => *entry == name,
+        })
+    };
+    let names = ["a.b.tracker99999.example", "www.unlisted.example"];
+    c.bench_function("name_trie_matches", |b| {
+        b.iter(|| names.map(|name| trie.matches(black_box(name))));
+    });
+    c.bench_function("hash_set_scan_matches", |b| {
+        b.iter(|| names.map(|name| scan(black_box(name))));
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_serialize,
+    bench_find_record,
+    bench_name_trie
+);
+criterion_main!(benches);
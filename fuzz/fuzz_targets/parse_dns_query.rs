@@ -0,0 +1,15 @@
+#![no_main]
+
+//! Whatever arrives off the network, parsing it and serializing what
+//! parsed must come back with a result rather than a panic.
+//! Run with `cargo +nightly fuzz run parse_dns_query` from the top.
+
+use libfuzzer_sys::fuzz_target;
+use toy_dns_server::parse_dns_query;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = parse_dns_query(data) {
+        let _ = packet.to_string();
+        let _ = packet.try_serialize();
+    }
+});
@@ -0,0 +1,78 @@
+use std::io;
+use std::net;
+
+/// The UDP socket and the TCP listener systemd passed on, if it did.
+/// See sd_listen_fds(3) for the `LISTEN_PID`/`LISTEN_FDS` protocol.
+#[cfg(unix)]
+pub fn inherited_sockets()
+-> io::Result<Option<(net::UdpSocket, net::TcpListener)>> {
+    use std::os::fd::{FromRawFd as _, RawFd};
+
+    // the inherited descriptors follow stdin, stdout and stderr
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .is_ok_and(|pid| pid.parse() == Ok(std::process::id()));
+    let count: RawFd = match std::env::var("LISTEN_FDS") {
+        Ok(count) if for_us => count.parse().map_err(|_| {
+            io::Error::other(format!("Invalid LISTEN_FDS '{}'", count))
+        })?,
+        _ => return Ok(None),
+    };
+
+    let (mut udp, mut tcp) = (None, None);
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: the descriptors were handed to us, each adopted just once
+        match socket_type(fd)? {
+            libc::SOCK_DGRAM if udp.is_none() => {
+                udp = Some(unsafe { net::UdpSocket::from_raw_fd(fd) });
+            }
+            libc::SOCK_STREAM if tcp.is_none() => {
+                tcp = Some(unsafe { net::TcpListener::from_raw_fd(fd) });
+            }
+            _ => {
+                return Err(io::Error::other(format!(
+                    "Unexpected inherited socket {}, \
+                     expected one UDP and one TCP socket",
+                    fd
+                )));
+            }
+        }
+    }
+    let (Some(udp), Some(tcp)) = (udp, tcp) else {
+        return Err(io::Error::other(format!(
+            "Expected one UDP and one TCP socket, got {} descriptors",
+            count
+        )));
+    };
+    udp.set_nonblocking(true)?;
+    tcp.set_nonblocking(true)?;
+    Ok(Some((udp, tcp)))
+}
+
+#[cfg(not(unix))]
+pub fn inherited_sockets()
+-> io::Result<Option<(net::UdpSocket, net::TcpListener)>> {
+    Ok(None)
+}
+
+/// SOCK_DGRAM or SOCK_STREAM, an error if `fd` isn't a socket.
+#[cfg(unix)]
+fn socket_type(fd: std::os::fd::RawFd) -> io::Result<libc::c_int> {
+    let mut socket_type: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the output buffer is an int, just as SO_TYPE needs
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            (&raw mut socket_type).cast(),
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket_type)
+}
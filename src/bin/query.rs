@@ -0,0 +1,53 @@
+use clap::Parser;
+use std::io::{BufRead as _, IsTerminal as _, Write as _};
+use std::net::SocketAddr;
+use std::time::Duration;
+use toy_dns_server::{Resolver, Type};
+
+/// Reads "name [type]" lines, A if the type is left out, and prints
+/// the server's reply to each the way dig would.
+#[derive(Parser)]
+struct Cli {
+    /// Server to query, like 127.0.0.1:53
+    server: SocketAddr,
+    /// Seconds to wait for each reply
+    #[arg(long, default_value_t = 5)]
+    timeout: u64,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let Cli { server, timeout } = Cli::parse();
+    let resolver =
+        Resolver::new(server).with_timeout(Duration::from_secs(timeout));
+    let runtime = tokio::runtime::Runtime::new()?;
+    let stdin = std::io::stdin();
+    // only prompt someone typing, not a pipe
+    let interactive = stdin.is_terminal();
+    let prompt = || {
+        if interactive {
+            eprint!("> ");
+            std::io::stderr().flush().ok();
+        }
+    };
+    prompt();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        if let Some(name) = fields.next() {
+            let qtype = fields.next().map_or(Ok(Type::A), str::parse);
+            match qtype {
+                Ok(qtype) => {
+                    match runtime.block_on(resolver.query(name, qtype)) {
+                        Ok(reply) => println!("{}", reply.to_dig_string()),
+                        Err(e) => {
+                            eprintln!("Query for {name} {qtype} failed: {e}")
+                        }
+                    }
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        prompt();
+    }
+    Ok(())
+}
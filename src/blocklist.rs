@@ -0,0 +1,176 @@
+use crate::NameTrie;
+use crate::packet::dns_name::{canonicalize_name, validate_name};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Names answered with a sinkhole instead of being looked up,
+/// for blocking ads and trackers. A name like "*.example.com" blocks
+/// everything below example.com, but not example.com itself.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "BlocklistHelper")]
+pub struct Blocklist {
+    names: NameTrie,
+    pub sinkhole: Sinkhole,
+}
+
+/// What blocked names are answered with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Sinkhole {
+    /// 0.0.0.0 to A queries and :: to AAAA ones.
+    #[default]
+    Unspecified,
+    /// This address to queries of its type, no data to the other.
+    Address(IpAddr),
+    NxDomain,
+}
+
+impl TryFrom<String> for Sinkhole {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "unspecified" => Ok(Sinkhole::Unspecified),
+            "nxdomain" => Ok(Sinkhole::NxDomain),
+            _ => s.parse().map(Sinkhole::Address).map_err(|_| {
+                format!(
+                    "Invalid sinkhole '{}', expected an address, \
+                     'unspecified' or 'nxdomain'",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BlocklistHelper {
+    #[serde(default)]
+    names: Vec<String>,
+    /// Loaded once at startup, in the "0.0.0.0 name [name...]" format
+    /// that public blocklists are distributed in.
+    #[serde(default)]
+    hosts_files: Vec<PathBuf>,
+    #[serde(default)]
+    sinkhole: Sinkhole,
+}
+
+/// Entries hosts files carry for the machine itself rather than to block.
+const HOSTS_FILE_LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "0.0.0.0",
+];
+
+impl TryFrom<BlocklistHelper> for Blocklist {
+    type Error = String;
+
+    fn try_from(helper: BlocklistHelper) -> Result<Self, Self::Error> {
+        let mut blocklist =
+            Blocklist { sinkhole: helper.sinkhole, ..Blocklist::default() };
+        for name in &helper.names {
+            blocklist.insert(name)?;
+        }
+        for path in &helper.hosts_files {
+            let hosts = std::fs::read_to_string(path).map_err(|e| {
+                format!("Failed to read hosts file {}: {}", path.display(), e)
+            })?;
+            blocklist.extend_from_hosts(&hosts);
+        }
+        Ok(blocklist)
+    }
+}
+
+impl Blocklist {
+    fn insert(&mut self, name: &str) -> Result<(), String> {
+        let name = canonicalize_name(name);
+        validate_name(&name).map_err(|e| format!("Blocklist entry: {e}"))?;
+        self.names.insert(&name);
+        Ok(())
+    }
+
+    /// Takes every name after the address on each line, skipping comments,
+    /// the machine's own names and whatever isn't a valid name.
+    fn extend_from_hosts(&mut self, hosts: &str) {
+        for line in hosts.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for name in line.split_whitespace().skip(1) {
+                if !HOSTS_FILE_LOCAL_NAMES.contains(&name) {
+                    self.insert(name).ok();
+                }
+            }
+        }
+    }
+
+    /// Adds the names blocked by `other`, keeping this sinkhole.
+    pub fn extend(&mut self, other: Blocklist) {
+        self.names.extend(other.names);
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    #[must_use]
+    pub fn blocks(&self, name: &str) -> bool {
+        !self.is_empty() && self.names.matches(&canonicalize_name(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_names_and_wildcards() {
+        let yaml = "
+names: [ads.example.com, '*.tracker.example', Pixel.Example.NET.]
+sinkhole: nxdomain
+";
+        let mut blocklist: Blocklist = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(blocklist.sinkhole, Sinkhole::NxDomain);
+        assert!(blocklist.blocks("ads.example.com"));
+        assert!(blocklist.blocks("ADS.example.com."));
+        assert!(!blocklist.blocks("www.ads.example.com"));
+        assert!(!blocklist.blocks("example.com"));
+        assert!(blocklist.blocks("a.tracker.example"));
+        assert!(blocklist.blocks("a.b.tracker.example"));
+        assert!(!blocklist.blocks("tracker.example"));
+        assert!(blocklist.blocks("pixel.example.net"));
+
+        blocklist.extend_from_hosts(
+            "# a blocklist\n\
+             127.0.0.1 localhost\n\
+             0.0.0.0 0.0.0.0\n\
+             0.0.0.0 banner.example.org metrics.example.org # both\n\
+             0.0.0.0 not_a..name\n",
+        );
+        assert_eq!(blocklist.len(), 5);
+        assert!(blocklist.blocks("banner.example.org"));
+        assert!(blocklist.blocks("metrics.example.org"));
+        assert!(!blocklist.blocks("localhost"));
+    }
+
+    #[test]
+    fn test_sinkhole_parsing() {
+        let parse = |s: &str| Sinkhole::try_from(s.to_string());
+        assert_eq!(parse("unspecified"), Ok(Sinkhole::Unspecified));
+        assert_eq!(parse("NXDOMAIN"), Ok(Sinkhole::NxDomain));
+        assert_eq!(
+            parse("192.0.2.53"),
+            Ok(Sinkhole::Address("192.0.2.53".parse().unwrap()))
+        );
+        assert!(parse("nowhere").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Receive buffers handed out one per datagram and taken back once the
+/// datagram is processed, so a busy server stops allocating after warm-up.
+/// Each buffer belongs to a single datagram until it's dropped,
+/// so nothing can overwrite a datagram that's still being processed.
+#[derive(Debug)]
+pub struct BufferPool {
+    buffer_size: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<Vec<u8>>>,
+    allocated: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Keeps up to `max_idle` returned buffers of `buffer_size` bytes around.
+    #[must_use]
+    pub fn new(buffer_size: usize, max_idle: usize) -> Arc<Self> {
+        Arc::new(Self {
+            buffer_size,
+            max_idle,
+            idle: Mutex::default(),
+            allocated: AtomicUsize::new(0),
+        })
+    }
+
+    /// A buffer of `buffer_size` bytes, reused if one is idle.
+    #[must_use]
+    pub fn take(self: &Arc<Self>) -> PooledBuffer {
+        let reused = self.idle.lock().unwrap().pop();
+        let mut buf = reused.unwrap_or_else(|| {
+            self.allocated.fetch_add(1, Ordering::Relaxed);
+            Vec::with_capacity(self.buffer_size)
+        });
+        buf.resize(self.buffer_size, 0);
+        PooledBuffer { buf, pool: Arc::clone(self) }
+    }
+
+    /// How many buffers were ever allocated, rather than reused.
+    #[must_use]
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+/// A buffer from a `BufferPool`, going back into it on drop.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Shortens the buffer to the `len` bytes actually received.
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.max_idle {
+            idle.push(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new(512, 2);
+        for _ in 0..1000 {
+            let mut buf = pool.take();
+            assert_eq!(buf.len(), 512);
+            buf[0] = 0xff;
+            buf.truncate(1);
+        }
+        assert_eq!(pool.allocated(), 1);
+
+        // three at once, one more than is kept idle
+        let bufs = [pool.take(), pool.take(), pool.take()];
+        assert_eq!(pool.allocated(), 3);
+        assert!(bufs.iter().all(|buf| buf.len() == 512));
+        drop(bufs);
+        let _bufs = [pool.take(), pool.take(), pool.take()];
+        assert_eq!(pool.allocated(), 4);
+    }
+}
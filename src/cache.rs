@@ -0,0 +1,217 @@
+use crate::clock::{Clock, SystemClock};
+use crate::packet::answer::DnsAnswer;
+use crate::packet::dns_name::canonicalize_name;
+use crate::packet::header::RCode;
+use crate::packet::protocol_class::Class;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record_type::Type;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub const DEFAULT_CACHE_MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub name: String,
+    pub qtype: Type,
+    pub qclass: Class,
+}
+
+impl From<&DnsQuestion> for CacheKey {
+    fn from(q: &DnsQuestion) -> Self {
+        Self {
+            name: canonicalize_name(&q.qname),
+            qtype: q.qtype,
+            qclass: q.qclass,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    rcode: RCode,
+    answers: Vec<DnsAnswer>,
+    inserted: Instant,
+    lifetime: u32, // the lowest TTL among the answers
+}
+
+/// Counters since startup, for observing how well the cache works.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    /// Entries pushed out by the size limit, not those that expired.
+    pub evictions: u64,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    entries: LruCache<CacheKey, CacheEntry>,
+    stats: CacheStats,
+}
+
+/// Forwarded answers, served with their TTLs counting down since insertion.
+/// Bounded in size, evicting the least recently used entry when full.
+#[derive(Debug)]
+pub struct AnswerCache {
+    state: Mutex<CacheState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for AnswerCache {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+/// The cache can't be turned off, a zero limit keeps a single entry.
+fn capacity(max_entries: usize) -> NonZeroUsize {
+    NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN)
+}
+
+impl AnswerCache {
+    #[must_use]
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let entries = LruCache::new(capacity(DEFAULT_CACHE_MAX_ENTRIES));
+        let state = CacheState { entries, stats: CacheStats::default() };
+        Self { state: Mutex::new(state), clock }
+    }
+
+    #[must_use]
+    pub fn with_max_entries(self, max_entries: usize) -> Self {
+        self.state.lock().unwrap().entries.resize(capacity(max_entries));
+        self
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Answerless replies carry no TTL to count down, so they aren't cached.
+    pub fn insert(&self, key: CacheKey, rcode: RCode, answers: Vec<DnsAnswer>) {
+        let Some(lifetime) = answers.iter().map(|a| a.ttl).min() else {
+            return;
+        };
+        let inserted = self.clock.now();
+        let entry = CacheEntry { rcode, answers, inserted, lifetime };
+        let mut state = self.state.lock().unwrap();
+        state.stats.insertions += 1;
+        if let Some((evicted, _)) = state.entries.push(key.clone(), entry)
+            && evicted != key
+        {
+            state.stats.evictions += 1;
+        }
+    }
+
+    /// Returns the answers with the elapsed time subtracted from their TTLs.
+    /// Once the lowest TTL hits zero, the entry is dropped for a re-fetch.
+    pub fn get(&self, key: &CacheKey) -> Option<(RCode, Vec<DnsAnswer>)> {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let CacheState { entries, stats } = &mut *state;
+        let Some(entry) = entries.get(key) else {
+            stats.misses += 1;
+            return None;
+        };
+        let elapsed = now.saturating_duration_since(entry.inserted).as_secs();
+        let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+        if elapsed >= entry.lifetime {
+            entries.pop(key);
+            stats.misses += 1;
+            return None;
+        }
+        stats.hits += 1;
+        let answers = entry
+            .answers
+            .iter()
+            .map(|answer| DnsAnswer {
+                ttl: answer.ttl.saturating_sub(elapsed),
+                ..answer.clone()
+            })
+            .collect();
+        Some((entry.rcode, answers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use crate::packet::answer::RData;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn answer(ttl: u32) -> DnsAnswer {
+        DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::A,
+            rclass: Class::IN,
+            ttl,
+            rdata: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        }
+    }
+
+    fn key() -> CacheKey {
+        CacheKey {
+            name: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        }
+    }
+
+    #[test]
+    fn test_ttl_decrements_while_cached() {
+        let clock = Arc::new(FakeClock::default());
+        let cache = AnswerCache::new(clock.clone());
+        cache.insert(key(), RCode::NoError, vec![answer(100)]);
+
+        clock.advance(Duration::from_secs(30));
+        let (rcode, answers) = cache.get(&key()).unwrap();
+        assert_eq!(rcode, RCode::NoError);
+        assert_eq!(answers, vec![answer(70)]);
+    }
+
+    #[test]
+    fn test_entry_expires_exactly_at_ttl() {
+        let clock = Arc::new(FakeClock::default());
+        let cache = AnswerCache::new(clock.clone());
+        cache.insert(key(), RCode::NoError, vec![answer(100), answer(60)]);
+
+        clock.advance(Duration::from_millis(59_999));
+        let (_, answers) = cache.get(&key()).unwrap();
+        assert_eq!(answers, vec![answer(41), answer(1)]);
+
+        clock.advance(Duration::from_millis(1));
+        assert!(cache.get(&key()).is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_entries_are_evicted() {
+        let cache = AnswerCache::default().with_max_entries(2);
+        let key = |name: &str| CacheKey { name: name.to_string(), ..key() };
+        cache.insert(key("a"), RCode::NoError, vec![answer(60)]);
+        cache.insert(key("b"), RCode::NoError, vec![answer(60)]);
+        assert!(cache.get(&key("a")).is_some()); // now "b" is the oldest
+        cache.insert(key("c"), RCode::NoError, vec![answer(60)]);
+        cache.insert(key("c"), RCode::NoError, vec![answer(30)]); // replaced
+
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+        assert_eq!(
+            cache.stats(),
+            CacheStats { hits: 3, misses: 1, insertions: 4, evictions: 1 }
+        );
+    }
+
+    #[test]
+    fn test_answerless_replies_are_not_cached() {
+        let cache = AnswerCache::default();
+        cache.insert(key(), RCode::NXDomain, vec![]);
+        assert!(cache.get(&key()).is_none());
+    }
+}
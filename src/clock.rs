@@ -0,0 +1,79 @@
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The source of time for anything expiring: caches, rate limits, TTLs.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    /// Wall-clock time, for anything scheduled by the time of day.
+    fn system_time(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+#[derive(Debug)]
+pub struct FakeClock {
+    now: Mutex<(Instant, SystemTime)>,
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+}
+
+impl FakeClock {
+    #[must_use]
+    pub fn starting_at(system_time: SystemTime) -> Self {
+        Self { now: Mutex::new((Instant::now(), system_time)) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += by;
+        now.1 += by;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_clock_only_moves_when_advanced() {
+        let clock = FakeClock::default();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(clock.now(), start + Duration::from_secs(30));
+
+        let clock = FakeClock::starting_at(SystemTime::UNIX_EPOCH);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            clock.system_time(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(30)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::packet::edns::Cookie;
+use crate::zone_config::decode_hex;
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
+use std::hash::Hasher as _;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The only server cookie version RFC 9018 defines.
+const VERSION: u8 = 1;
+/// How old a server cookie may be and still be accepted, in seconds.
+const MAX_AGE: u32 = 3600;
+/// How far ahead a server cookie's timestamp may be, for servers sharing
+/// a secret whose clocks disagree a little.
+const MAX_SKEW: u32 = 300;
+
+/// The key server cookies are computed with. Servers behind one address
+/// should share it, so a cookie from one is accepted by the others.
+/// Random unless configured, as 32 hex digits.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CookieSecret([u8; 16]);
+
+impl Default for CookieSecret {
+    fn default() -> Self {
+        CookieSecret(rand::random())
+    }
+}
+
+impl TryFrom<String> for CookieSecret {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        decode_hex(&s)
+            .and_then(|secret| secret.try_into().ok())
+            .map(CookieSecret)
+            .ok_or_else(|| {
+                "Invalid cookie_secret, expected 32 hex digits".to_string()
+            })
+    }
+}
+
+impl std::fmt::Debug for CookieSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CookieSecret(..)") // not for the logs
+    }
+}
+
+impl CookieSecret {
+    /// Whether the server cookie is one this secret made for the client
+    /// cookie and address within the last hour. A client sending one has
+    /// received an earlier reply, so its address isn't spoofed.
+    #[must_use]
+    pub fn validates(&self, cookie: &Cookie, peer: IpAddr) -> bool {
+        self.validates_at(cookie, peer, unix_time())
+    }
+
+    fn validates_at(&self, cookie: &Cookie, peer: IpAddr, now: u32) -> bool {
+        let Ok(server) = <[u8; 16]>::try_from(cookie.server.as_slice()) else {
+            return false;
+        };
+        let timestamp = u32::from_be_bytes(server[4..8].try_into().unwrap());
+        // serial number arithmetic, as the timestamp wraps in 2106
+        let age = now.wrapping_sub(timestamp);
+        let fresh = age <= MAX_AGE || age.wrapping_neg() <= MAX_SKEW;
+        fresh
+            && server[0] == VERSION
+            && self.server_cookie(&cookie.client, peer, timestamp) == server
+    }
+
+    /// The cookie to reply with: the client's own and a fresh server
+    /// cookie, whatever the query had.
+    #[must_use]
+    pub fn reply_cookie(&self, cookie: &Cookie, peer: IpAddr) -> Cookie {
+        let server = self.server_cookie(&cookie.client, peer, unix_time());
+        Cookie { client: cookie.client, server: server.to_vec() }
+    }
+
+    /// The server cookie of RFC 9018: version, three reserved octets,
+    /// timestamp and a SipHash-2-4 over the client cookie, those and
+    /// the client address.
+    fn server_cookie(
+        &self,
+        client: &[u8; 8],
+        peer: IpAddr,
+        timestamp: u32,
+    ) -> [u8; 16] {
+        let mut cookie = [0; 16];
+        cookie[0] = VERSION;
+        cookie[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        let mut hasher = SipHasher24::new_with_key(&self.0);
+        hasher.write(client);
+        hasher.write(&cookie[..8]);
+        match peer {
+            IpAddr::V4(address) => hasher.write(&address.octets()),
+            IpAddr::V6(address) => hasher.write(&address.octets()),
+        }
+        cookie[8..].copy_from_slice(&hasher.finish().to_le_bytes());
+        cookie
+    }
+}
+
+fn unix_time() -> u32 {
+    let since_epoch =
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() as u32 // wrapping, as RFC 9018 has it
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_cookie() {
+        let secret = CookieSecret::try_from(
+            "e5e973e5a6b2a43f48e7dc849e37bfcf".to_string(),
+        )
+        .unwrap();
+        let peer = "198.51.100.100".parse().unwrap();
+        let client = [0x24, 0x64, 0xc4, 0xab, 0xcf, 0x10, 0xc9, 0x57];
+        // the first example of RFC 9018 Appendix A.1
+        let server = secret.server_cookie(&client, peer, 1_559_731_985);
+        assert_eq!(
+            server,
+            [
+                0x01, 0x00, 0x00, 0x00, 0x5c, 0xf7, 0x9f, 0x11, 0x1f, 0x81,
+                0x30, 0xc3, 0xee, 0xe2, 0x94, 0x80,
+            ]
+        );
+
+        let now = 1_559_731_985 + 60;
+        let cookie = Cookie { client, server: server.to_vec() };
+        assert!(secret.validates_at(&cookie, peer, now));
+        assert!(!secret.validates_at(&cookie, peer, now + MAX_AGE));
+        let elsewhere = "198.51.100.101".parse().unwrap();
+        assert!(!secret.validates_at(&cookie, elsewhere, now));
+        let other_client = Cookie { client: [0; 8], ..cookie.clone() };
+        assert!(!secret.validates_at(&other_client, peer, now));
+        assert!(!CookieSecret::default().validates_at(&cookie, peer, now));
+        let bare = Cookie { client, server: Vec::new() };
+        assert!(!secret.validates_at(&bare, peer, now));
+
+        assert!(CookieSecret::try_from("e5e973".to_string()).is_err());
+    }
+}
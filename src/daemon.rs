@@ -0,0 +1,84 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Detaches from the terminal the traditional way: forking twice with a
+/// new session in between, and pointing stdin and stdout at /dev/null.
+/// Stderr is kept for the log, to be redirected by whoever starts it.
+/// Has to happen before any threads are started, the runtime's included.
+#[cfg(unix)]
+pub fn daemonize() -> io::Result<()> {
+    fork_and_exit_parent()?;
+    // SAFETY: plain syscalls without pointers to keep alive
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // no longer a session leader, so it can't acquire a terminal again
+    fork_and_exit_parent()?;
+    let dev_null =
+        std::fs::File::options().read(true).write(true).open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO] {
+        use std::os::fd::AsRawFd as _;
+        // SAFETY: both descriptors are open, dev_null until it's dropped
+        if unsafe { libc::dup2(dev_null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> io::Result<()> {
+    Err(io::Error::other("Daemonizing needs a Unix"))
+}
+
+/// Forks, carrying on in the child only.
+#[cfg(unix)]
+fn fork_and_exit_parent() -> io::Result<()> {
+    // SAFETY: single-threaded at this point, so the child is whole
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+/// A file holding the process id, removed again on drop.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<PidFile> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::write(&path, format!("{}\n", std::process::id()))?;
+        Ok(PidFile { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // may fail once privileges are dropped, worth a mention only
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            eprintln!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Resolves once the process is asked to stop, by SIGTERM as init
+/// systems do or by SIGINT from the terminal.
+pub async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
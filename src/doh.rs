@@ -0,0 +1,207 @@
+use crate::tcp_limit::ConnectionSlot;
+use crate::{AnswerCache, DnsPacket, QueryLog, ZoneConfig, tls};
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::Bytes;
+use http_body_util::{BodyExt as _, Full, Limited};
+use hyper::body::Incoming;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::slice;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsAcceptor;
+
+/// The media type of DNS messages carried over HTTP.
+pub const DNS_MESSAGE: &str = "application/dns-message";
+/// Where queries are taken, the path RFC 8484 uses in its examples.
+pub const DOH_PATH: &str = "/dns-query";
+
+/// DNS over HTTPS (RFC 8484), with a certificate and addresses of its own
+/// as it usually faces browsers rather than the resolvers DoT serves.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DohConfig {
+    /// PEM file with the certificate chain, the server's own first.
+    pub certificate: PathBuf,
+    /// PEM file with the certificate's private key.
+    pub key: PathBuf,
+    /// Addresses to listen on, port 443 on every interface by default.
+    #[serde(default = "default_listen")]
+    pub listen: Vec<String>,
+}
+
+fn default_listen() -> Vec<String> {
+    vec!["[::]:443".to_string()]
+}
+
+impl DohConfig {
+    /// Loads the certificate and key, which happens before privileges
+    /// are dropped, so the key may be readable by root only.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, io::Error> {
+        let mut config = tls::server_config(&self.certificate, &self.key)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()]; // no HTTP/2
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Serves HTTP/1.1 requests on a connection whose TLS handshake is done,
+/// until the client closes it or leaves it idle for the TCP idle timeout.
+pub(crate) async fn process_https<S>(
+    config: Arc<ZoneConfig>,
+    caches: Arc<[AnswerCache]>,
+    query_log: Arc<QueryLog>,
+    stream: S,
+    peer: SocketAddr,
+    _slot: ConnectionSlot, // released when the connection closes
+) -> Result<(), io::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let idle_timeout = Duration::from_secs(config.tcp_idle_timeout);
+    let service = hyper::service::service_fn(move |request| {
+        let config = Arc::clone(&config);
+        let caches = Arc::clone(&caches);
+        let query_log = Arc::clone(&query_log);
+        async move {
+            let response =
+                respond(&config, &caches, &query_log, request, peer).await;
+            Ok::<_, Infallible>(response)
+        }
+    });
+    let connection = hyper::server::conn::http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(idle_timeout)
+        .serve_connection(TokioIo::new(stream), service);
+    // a client misbehaving only loses its own connection
+    if let Err(e) = connection.await {
+        eprintln!("HTTPS connection from {peer} failed: {e}");
+    }
+    Ok(())
+}
+
+async fn respond(
+    config: &ZoneConfig,
+    caches: &[AnswerCache],
+    query_log: &QueryLog,
+    request: Request<Incoming>,
+    peer: SocketAddr,
+) -> Response<Full<Bytes>> {
+    if request.uri().path() != DOH_PATH {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    let query = match *request.method() {
+        Method::GET => query_from_uri(request.uri().query()),
+        Method::POST => query_from_body(request).await,
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    let query = match query {
+        Ok(query) => query,
+        Err(status) => return status_response(status),
+    };
+    let Some(reply) = answer(config, caches, query_log, &query, peer).await
+    else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let max_age = reply.answers.iter().map(|answer| answer.ttl).min();
+    let Some(reply_bytes) = crate::encode(reply) else {
+        return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    eprintln!("Sent {} bytes back to {peer} (HTTPS)", reply_bytes.len());
+    let mut response = Response::builder().header(CONTENT_TYPE, DNS_MESSAGE);
+    // cacheable for as long as the shortest lived answer (RFC 8484 5.1)
+    if let Some(max_age) = max_age {
+        response = response.header(CACHE_CONTROL, format!("max-age={max_age}"));
+    }
+    response.body(Full::new(Bytes::from(reply_bytes))).unwrap()
+}
+
+/// Answers a query over HTTPS, where a reply needn't fit a datagram.
+/// None for one that doesn't even have a DNS header.
+async fn answer(
+    config: &ZoneConfig,
+    caches: &[AnswerCache],
+    query_log: &QueryLog,
+    data: &[u8],
+    peer: SocketAddr,
+) -> Option<DnsPacket> {
+    let query = crate::parse_dns_query(data);
+    let reply = match &query {
+        Ok(packet) => {
+            eprintln!("Received query: {packet}");
+            crate::reply_to(config, caches, packet, peer.ip()).await?
+        }
+        Err(e) => {
+            eprintln!("Malformed query from {peer}: {e}");
+            crate::formerr_reply(data)?
+        }
+    };
+    let replies = slice::from_ref(&reply);
+    crate::log_query(query_log, peer, query.as_ref().ok(), replies, "HTTPS");
+    eprintln!("Sending back reply: {reply}");
+    Some(reply)
+}
+
+/// The query a GET carries base64url-encoded in its `dns` parameter.
+fn query_from_uri(query_string: Option<&str>) -> Result<Vec<u8>, StatusCode> {
+    let encoded = query_string
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("dns="))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    // unpadded as RFC 8484 has it, but padding is easy enough to forgive
+    URL_SAFE_NO_PAD
+        .decode(encoded.trim_end_matches('='))
+        .map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+/// The query a POST carries as its body, no longer than a DNS message.
+async fn query_from_body(
+    request: Request<Incoming>,
+) -> Result<Vec<u8>, StatusCode> {
+    let content_type = request.headers().get(CONTENT_TYPE);
+    if content_type.is_none_or(|content_type| content_type != DNS_MESSAGE) {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let body = Limited::new(request.into_body(), usize::from(u16::MAX));
+    let body =
+        body.collect().await.map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    Ok(body.to_bytes().to_vec())
+}
+
+fn status_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_from_uri() {
+        // the example query of RFC 8484 4.1.1, for www.example.com A
+        let query = query_from_uri(Some(
+            "dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB",
+        ))
+        .unwrap();
+        let packet = crate::parse_dns_query(&query).unwrap();
+        assert_eq!(packet.questions[0].qname, "www.example.com");
+        assert_eq!(
+            query_from_uri(Some("ct=x&dns=AAABAAAAAAAAAAAAAA==")),
+            Ok(vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(query_from_uri(None), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            query_from_uri(Some("dns=not+base64url")),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{AnswerCache, CacheStats, ZoneConfig};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::header::{CONTENT_TYPE, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::net::TcpListener;
+
+/// Answers liveness and readiness probes over plain HTTP, as container
+/// orchestrators send them: `/livez` succeeds as long as the server runs,
+/// `/readyz` only once `ready` is set, after the sockets are bound.
+/// `/metrics` has the counters of the answer caches for Prometheus.
+pub(crate) async fn serve_probes(
+    listener: TcpListener,
+    ready: Arc<AtomicBool>,
+    config: Arc<ZoneConfig>,
+    caches: Arc<[AnswerCache]>,
+) -> Result<(), io::Error> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let ready = Arc::clone(&ready);
+        let config = Arc::clone(&config);
+        let caches = Arc::clone(&caches);
+        let service = hyper::service::service_fn(move |request| {
+            let response = if request.uri().path() == "/metrics" {
+                let body = metrics(&config, &caches);
+                let mut response = Response::new(Full::from(body));
+                let content_type = HeaderValue::from_static(METRICS_TYPE);
+                response.headers_mut().insert(CONTENT_TYPE, content_type);
+                response
+            } else {
+                let ready = ready.load(Ordering::Relaxed);
+                let mut response = Response::new(Full::<Bytes>::default());
+                *response.status_mut() = probe_status(&request, ready);
+                response
+            };
+            async move { Ok::<_, Infallible>(response) }
+        });
+        tokio::spawn(async move {
+            // probes aren't worth a log line, failing or not
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .ok();
+        });
+    }
+}
+
+fn probe_status<B>(request: &Request<B>, ready: bool) -> StatusCode {
+    match request.uri().path() {
+        "/livez" => StatusCode::OK,
+        "/readyz" if ready => StatusCode::OK,
+        "/readyz" => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+/// The Prometheus text exposition format.
+const METRICS_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The counters of each view's answer cache, the top level's being
+/// labeled `_default` as it's unnamed.
+fn metrics(config: &ZoneConfig, caches: &[AnswerCache]) -> String {
+    let views = std::iter::once("_default")
+        .chain(config.views.iter().map(|view| view.name.as_str()));
+    let stats: Vec<(&str, CacheStats)> =
+        views.zip(caches.iter().map(AnswerCache::stats)).collect();
+    type Counter = fn(&CacheStats) -> u64;
+    let counters: [(&str, &str, Counter); 4] = [
+        ("hits", "Forwarded answers served from the cache", |s| s.hits),
+        ("misses", "Lookups the cache had no live entry for", |s| s.misses),
+        ("insertions", "Forwarded answers cached", |s| s.insertions),
+        ("evictions", "Entries pushed out by the size limit", |s| s.evictions),
+    ];
+    // writing to a String can't fail
+    let mut out = String::new();
+    for (name, help, counter) in counters {
+        let metric = format!("toy_dns_server_cache_{name}_total");
+        let _ = writeln!(out, "# HELP {metric} {help}.");
+        let _ = writeln!(out, "# TYPE {metric} counter");
+        for (view, stats) in &stats {
+            let _ =
+                writeln!(out, "{metric}{{view={view:?}}} {}", counter(stats));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CacheKey, Class, DnsAnswer, RCode, RData, Type};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_probe_status() {
+        let request = |path| Request::get(path).body(()).unwrap();
+        assert_eq!(probe_status(&request("/livez"), false), StatusCode::OK);
+        assert_eq!(
+            probe_status(&request("/readyz"), false),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(probe_status(&request("/readyz"), true), StatusCode::OK);
+        assert_eq!(
+            probe_status(&request("/nowhere"), true),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_metrics() {
+        let config: ZoneConfig = "
+views:
+- name: internal
+  match_clients: [10.0.0.0/8]
+"
+        .parse()
+        .unwrap();
+        let caches = [AnswerCache::default(), AnswerCache::default()];
+        let key = CacheKey {
+            name: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        };
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::A,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        };
+        caches[1].insert(key.clone(), RCode::NoError, vec![answer]);
+        caches[1].get(&key);
+        caches[0].get(&key);
+
+        let metrics = metrics(&config, &caches);
+        for line in [
+            "# TYPE toy_dns_server_cache_hits_total counter",
+            "toy_dns_server_cache_hits_total{view=\"_default\"} 0",
+            "toy_dns_server_cache_hits_total{view=\"internal\"} 1",
+            "toy_dns_server_cache_misses_total{view=\"_default\"} 1",
+            "toy_dns_server_cache_insertions_total{view=\"internal\"} 1",
+            "toy_dns_server_cache_evictions_total{view=\"internal\"} 0",
+        ] {
+            assert!(metrics.lines().any(|l| l == line), "{line}\n{metrics}");
+        }
+    }
+}
@@ -1,19 +1,77 @@
+use rand::seq::SliceRandom as _;
 use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::slice;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{Semaphore, mpsc, watch};
 use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
 
+mod activation;
+mod blocklist;
+mod buffer_pool;
+mod cache;
+mod cidr;
+mod clock;
+mod cookie;
+mod daemon;
+mod doh;
+mod health;
+mod name_trie;
 mod packet;
+mod privileges;
+mod query_log;
+mod rate_limit;
+mod resolver;
+mod socket_buffers;
+mod tcp_limit;
+mod tls;
 mod zone_config;
+pub use blocklist::{Blocklist, Sinkhole};
+pub use buffer_pool::{BufferPool, PooledBuffer};
+pub use cache::{AnswerCache, CacheKey, CacheStats};
+pub use cidr::Cidr;
+pub use clock::{Clock, FakeClock, SystemClock};
+pub use daemon::{PidFile, daemonize, shutdown_signal};
+pub use doh::{DNS_MESSAGE, DOH_PATH, DohConfig};
+pub use name_trie::NameTrie;
 use packet::ParseError;
-pub use packet::answer::{DnsAnswer, RData};
+pub use packet::SerializeError;
+pub use packet::answer::{DnsAnswer, RData, clamp_ttl};
+pub use packet::canonical_name::CanonicalName;
+pub use packet::dns_name::canonicalize_name;
+pub use packet::edns::{
+    CLIENT_SUBNET_OPTION, COOKIE_OPTION, ClientSubnet, Cookie, DO_FLAG,
+    EXTENDED_ERROR_OPTION, EdnsOpt, ExtendedError, TCP_KEEPALIVE_OPTION,
+    keepalive_option,
+};
+use packet::header::parse_dns_header;
 pub use packet::header::{DnsHeader, OpCode, RCode};
 pub use packet::protocol_class::Class;
 pub use packet::question::DnsQuestion;
-pub use packet::record_type::Type;
-pub use packet::{DnsPacket, parse_dns_query};
-pub use zone_config::{Record, Zone, ZoneConfig, find_record};
+pub use packet::record_type::{AXFR_TYPE, Type};
+pub use packet::{
+    DnsPacket, DnsPacketBuilder, SectionOffsets, parse_dns_message,
+    parse_dns_query, parse_dns_query_detailed, parse_dns_query_strict,
+};
+pub use query_log::QueryLog;
+use rate_limit::{RateLimiter, Verdict};
+pub use resolver::Resolver;
+use tcp_limit::{ConnectionSlot, ConnectionTracker};
+pub use tls::TlsConfig;
+use zone_config::absolute_name;
+pub use zone_config::{
+    AnswerOrder, Delegation, MissingNames, OutOfZone, Record, RegexRule,
+    TimeWindow, View, Zone, ZoneConfig, ds_parent_zone, find_delegation,
+    find_dname, find_ds_record, find_record, find_zone, matching_records,
+    name_exists, reverse_name,
+};
 
 impl From<ParseError> for io::Error {
     fn from(e: ParseError) -> Self {
@@ -21,76 +79,1085 @@ impl From<ParseError> for io::Error {
     }
 }
 
+static CANARY_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+fn is_canary(config: &ZoneConfig, qname: &str) -> bool {
+    config.canary_name.as_deref().is_some_and(|canary| {
+        canonicalize_name(canary) == canonicalize_name(qname)
+    })
+}
+
+fn canary_answer(q: &DnsQuestion) -> DnsAnswer {
+    let sequence = CANARY_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    DnsAnswer {
+        name: q.qname.clone(),
+        rtype: Type::TXT,
+        rclass: q.qclass,
+        ttl: 0, // must never be cached
+        rdata: RData::TXT(vec![
+            format!("seq={sequence}"),
+            format!("time={timestamp}"),
+        ]),
+    }
+}
+
+/// The text for the CHAOS-class TXT names that identify a server.
+fn chaos_text(config: &ZoneConfig, qname: &str) -> Option<String> {
+    match canonicalize_name(qname).as_str() {
+        "version.bind" | "version.server" => Some(config.version.clone()),
+        "hostname.bind" | "id.server" => hostname(),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf
+    let result =
+        unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if result != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    None
+}
+
+/// Something that went wrong on the server's side while answering,
+/// rather than with the query, which the client gets a ServFail for.
+#[derive(Debug)]
+pub struct ReplyError {
+    message: String,
+}
+
+impl ReplyError {
+    #[must_use]
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl std::fmt::Display for ReplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ReplyError {}
+
+impl From<SerializeError> for ReplyError {
+    fn from(e: SerializeError) -> Self {
+        Self::new(format!("Reply doesn't fit the wire format: {e}"))
+    }
+}
+
+/// When a lookup has to be given up on, as judged by the clock it's done by.
+/// Lookups await nothing the query timeout could cut them short at,
+/// so they check this between their steps instead.
+struct Deadline<'a> {
+    clock: &'a dyn Clock,
+    at: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    fn after(clock: &'a dyn Clock, timeout: Duration) -> Self {
+        Self { clock, at: clock.now() + timeout }
+    }
+
+    fn check(&self) -> Result<(), ReplyError> {
+        if self.clock.now() >= self.at {
+            return Err(ReplyError::new("Lookup took too long".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn order_answers(
+    config: &ZoneConfig,
+    q: &DnsQuestion,
+    answers: &mut [DnsAnswer],
+) {
+    match config.answer_order {
+        AnswerOrder::Zone => {}
+        AnswerOrder::Rotate if answers.is_empty() => {}
+        AnswerOrder::Rotate => {
+            let records = answers.iter().map(|a| a.rdata.clone()).collect();
+            let turn = config.rotation.next_turn(q.qtype, records);
+            answers.rotate_left(turn % answers.len());
+        }
+        AnswerOrder::Shuffle => answers.shuffle(&mut rand::rng()),
+        AnswerOrder::Canonical => {
+            answers.sort_by(|a, b| a.rdata.cmp(&b.rdata));
+        }
+    }
+}
+
+/// Alias chains longer than this are cut short, loops included.
+const MAX_ALIAS_HOPS: usize = 8;
+
+/// Like CNAME chasing, but for alias-mode (priority 0) SVCB/HTTPS records:
+/// appends the records found at the target, following further aliases.
+fn chase_svcb_aliases(
+    config: &ZoneConfig,
+    q: &DnsQuestion,
+    deadline: &Deadline,
+    answers: &mut Vec<DnsAnswer>,
+) -> Result<(), ReplyError> {
+    let mut seen = vec![canonicalize_name(&q.qname)];
+    let mut latest = 0; // where the records of the last name looked up start
+    for _ in 0..MAX_ALIAS_HOPS {
+        deadline.check()?;
+        let Some(target) =
+            answers[latest..].iter().find_map(|answer| match &answer.rdata {
+                RData::Svcb { priority: 0, target, .. } => {
+                    Some(target.trim_end_matches('.').to_string())
+                }
+                _ => None,
+            })
+        else {
+            return Ok(());
+        };
+        if target.is_empty() || seen.contains(&canonicalize_name(&target)) {
+            return Ok(()); // "." means the service doesn't exist
+        }
+        let records = find_record(config, &target, q.qtype);
+        latest = answers.len();
+        answers.extend(records.into_iter().map(|(record, ttl)| DnsAnswer {
+            name: target.clone(),
+            rclass: q.qclass,
+            rtype: q.qtype,
+            ttl,
+            rdata: record.rdata,
+        }));
+        seen.push(canonicalize_name(&target));
+    }
+    Ok(())
+}
+
+/// The DNAME itself, followed by a CNAME from the queried name
+/// to the same name with the DNAME owner suffix replaced by the target.
+fn synthesize_from_dname(
+    q: &DnsQuestion,
+    owner: String,
+    target: String,
+    ttl: u32,
+) -> [DnsAnswer; 2] {
+    let prefix = &q.qname[..q.qname.len() - owner.len()];
+    let synthesized = format!("{prefix}{}", target.trim_end_matches('.'));
+    [
+        DnsAnswer {
+            name: owner,
+            rtype: Type::DNAME,
+            rclass: q.qclass,
+            ttl,
+            rdata: RData::DNAME(target),
+        },
+        DnsAnswer {
+            name: q.qname.clone(),
+            rtype: Type::CNAME,
+            rclass: q.qclass,
+            ttl,
+            rdata: RData::CNAME(synthesized),
+        },
+    ]
+}
+
+fn rejects_edns_flags(config: &ZoneConfig, query: &DnsPacket) -> bool {
+    config.strict_edns
+        && query.edns.as_ref().is_some_and(|edns| edns.reserved_flags() != 0)
+}
+
+/// The query's client subnet, an error if the option is malformed,
+/// which RFC 7871 answers with FormErr.
+fn client_subnet(
+    query: &DnsPacket,
+) -> Result<Option<ClientSubnet>, ParseError> {
+    query.edns.as_ref().map_or(Ok(None), EdnsOpt::client_subnet)
+}
+
+/// The query's cookie, an error if the option is malformed,
+/// which RFC 7873 answers with FormErr.
+fn query_cookie(query: &DnsPacket) -> Result<Option<Cookie>, ParseError> {
+    query.edns.as_ref().map_or(Ok(None), EdnsOpt::cookie)
+}
+
+/// The OPT record for a reply if the query had one, echoing the DO bit
+/// and the client subnet.
+fn reply_edns(
+    query: &DnsPacket,
+    client_subnet: Option<ClientSubnet>,
+) -> Option<EdnsOpt> {
+    let edns = query.edns.as_ref()?;
+    Some(EdnsOpt {
+        flags: edns.flags & DO_FLAG,
+        options: client_subnet.iter().map(ClientSubnet::to_option).collect(),
+        ..EdnsOpt::default()
+    })
+}
+
+/// The reply to a query, None if it's a response itself.
+/// An error only for failures of the server's own, see `servfail_reply`.
 pub fn construct_reply(
     config: &ZoneConfig,
     query: &DnsPacket,
-) -> Option<DnsPacket> {
+) -> Result<Option<DnsPacket>, ReplyError> {
+    construct_reply_with_clock(config, query, &SystemClock)
+}
+
+/// `construct_reply` with records limited to a time of day judged by `clock`,
+/// which also judges whether the lookup outlasts the query timeout.
+pub fn construct_reply_with_clock(
+    config: &ZoneConfig,
+    query: &DnsPacket,
+    clock: &dyn Clock,
+) -> Result<Option<DnsPacket>, ReplyError> {
     let DnsPacket { header, questions, .. } = query;
     if header.response {
-        return None;
+        return Ok(None);
     }
+    let deadline =
+        Deadline::after(clock, Duration::from_secs(config.query_timeout));
 
     let mut answers = Vec::new();
-    let rcode = if questions.len() == 1 {
+    let mut authorities = Vec::new();
+    let mut additionals = Vec::new();
+    let mut authoritative = false;
+    let client_subnet = client_subnet(query);
+    let mut echoed_subnet = client_subnet.as_ref().ok().copied().flatten();
+    let rcode = if rejects_edns_flags(config, query) {
+        RCode::FormErr
+    } else if let Err(e) = &client_subnet {
+        eprintln!("Malformed EDNS option: {e}");
+        RCode::FormErr
+    } else if let Err(e) = query_cookie(query) {
+        eprintln!("Malformed EDNS option: {e}");
+        RCode::FormErr
+    } else if header.opcode == OpCode::NOTIFY {
+        // acknowledged, though being primary there's nothing to refresh
+        authoritative =
+            questions.len() == 1 && config.zone(&questions[0].qname).is_some();
+        if authoritative { RCode::NoError } else { RCode::Refused }
+    } else if header.opcode != OpCode::QUERY {
+        RCode::NotImp // IQUERY is obsoleted by RFC 3425, STATUS undefined
+    } else if questions.is_empty() {
+        RCode::FormErr // nothing asked
+    } else if questions.len() == 1 {
         let q = &questions[0];
 
-        if q.qclass == Class::IN {
-            let (records, ttl) = find_record(config, &q.qname, q.qtype);
-            if records.is_empty() {
-                RCode::NXDomain
-            } else {
-                answers.extend(records.into_iter().map(|record| DnsAnswer {
-                    name: q.qname.clone(),
-                    rclass: q.qclass,
-                    rtype: q.qtype,
-                    ttl,
-                    rdata: record.rdata,
-                }));
-                RCode::NoError
+        if is_canary(config, &q.qname) {
+            if q.qtype == Type::TXT {
+                answers.push(canary_answer(q));
             }
+            RCode::NoError
+        } else if q.qtype == Type::Other(AXFR_TYPE) {
+            RCode::NotImp // transfers only go over TCP, see `transfer_replies`
+        } else if matches!(q.qclass, Class::IN | Class::ANY)
+            && config.blocklist.blocks(&q.qname)
+        {
+            eprintln!("Sinkholing blocked {}", q.qname);
+            answer_blocked(config, q, &mut answers)
+        } else if find_zone(config, &q.qname)
+            .is_some_and(|(_, zone)| zone.refuse_types.contains(&q.qtype))
+        {
+            RCode::Refused // a policy fence, not a missing record
         } else {
-            RCode::Refused
+            match q.qclass {
+                Class::IN | Class::ANY => {
+                    // IN is all there is to ANY here, so records keep it
+                    let q = &DnsQuestion { qclass: Class::IN, ..q.clone() };
+                    let (rcode, mut aa) =
+                        if let Some(delegation) = referral_for(config, q) {
+                            eprintln!(
+                                "Referring {} to {}",
+                                q.qname, delegation.name
+                            );
+                            authorities = referral_name_servers(&delegation);
+                            additionals = glue(config, &authorities);
+                            (RCode::NoError, false)
+                        } else {
+                            answer_internet(
+                                config,
+                                q,
+                                &deadline,
+                                echoed_subnet.as_mut(),
+                                &mut answers,
+                            )?
+                        };
+                    if answers.is_empty()
+                        && rcode == RCode::NoError
+                        && q.qtype == Type::DS
+                        && let Some((zone_name, _)) =
+                            ds_parent_zone(config, &q.qname)
+                    {
+                        // NODATA from the parent, which it's authoritative
+                        // for, with the SOA to cache that by (RFC 2308)
+                        authorities = zone_soa(config, zone_name);
+                        aa = true;
+                    }
+                    if !answers.is_empty() && !config.minimal_responses {
+                        authorities = zone_name_servers(config, q);
+                        // not repeated when they're what was asked for
+                        authorities.retain(|ns| {
+                            !answers.iter().any(|answer| {
+                                answer.rtype == Type::NS
+                                    && answer.name == ns.name
+                            })
+                        });
+                        additionals = glue(config, &authorities);
+                    }
+                    deadline.check()?;
+                    let records = answers
+                        .iter_mut()
+                        .chain(&mut authorities)
+                        .chain(&mut additionals);
+                    for record in records {
+                        record.ttl = config.bound_ttl(record.ttl);
+                    }
+                    authoritative = aa;
+                    rcode
+                }
+                Class::CH => answer_chaos(config, q, &mut answers),
+                Class::HS => {
+                    eprintln!("Refusing {}: no Hesiod data served", q.qname);
+                    RCode::Refused
+                }
+                Class::NONE | Class::Other(_) => {
+                    eprintln!("Refusing {}: class {}", q.qname, q.qclass);
+                    RCode::Refused
+                }
+            }
         }
     } else {
         RCode::NotImp
     };
 
-    Some(DnsPacket {
-        header: DnsHeader {
-            transaction_id: header.transaction_id,
-            response: true,
-            opcode: header.opcode,
-            authoritative_answer: false,
-            truncation: false,
-            recursion_desired: header.recursion_desired,
-            recursion_available: false,
-            _reserved: false,
-            authenticated_data: false,
-            checking_disabled: false,
-            rcode,
-            qd_count: questions.len().try_into().unwrap_or(u16::MAX),
-            an_count: answers.len().try_into().unwrap_or(u16::MAX),
-            ns_count: 0, // No authority records
-            ar_count: 0, // No additional records
-        },
-        questions: questions.clone(),
-        answers,
-        unparsed: Vec::new(),
+    Ok(Some(
+        DnsPacket::builder()
+            .header(DnsHeader::response_to(header))
+            .authoritative_answer(authoritative)
+            .recursion_available(config.recursion_available())
+            .rcode(rcode)
+            .questions(questions.clone())
+            .answers(answers)
+            .authorities(authorities)
+            .additionals(additionals)
+            .edns(reply_edns(query, echoed_subnet))
+            .build(),
+    ))
+}
+
+/// The delegation to refer `q` to, if it's for a name at or below a zone
+/// cut, whatever the parent zone has there besides, as that's occluded.
+/// DS records at the cut itself are the parent's to answer.
+fn referral_for(config: &ZoneConfig, q: &DnsQuestion) -> Option<Delegation> {
+    find_delegation(config, &q.qname).filter(|delegation| {
+        q.qtype != Type::DS || canonicalize_name(&q.qname) != delegation.name
+    })
+}
+
+/// The NS records of a referral, for its authority section.
+fn referral_name_servers(delegation: &Delegation) -> Vec<DnsAnswer> {
+    delegation
+        .name_servers
+        .iter()
+        .map(|(record, ttl)| name_server_answer(&delegation.name, record, *ttl))
+        .collect()
+}
+
+/// An NS record for the authority section, its target in the form
+/// names are read off the wire in, without the trailing dot.
+fn name_server_answer(owner: &str, record: &Record, ttl: u32) -> DnsAnswer {
+    let rdata = match &record.rdata {
+        RData::NS(target) => RData::NS(target.trim_end_matches('.').into()),
+        rdata => rdata.clone(),
+    };
+    DnsAnswer {
+        name: owner.to_string(),
+        rtype: Type::NS,
+        rclass: Class::IN,
+        ttl,
+        rdata,
+    }
+}
+
+/// The NS records at the apex of the zone answering `q`, for the
+/// authority section of a positive answer. That's the parent's for
+/// DS records at a zone cut.
+fn zone_name_servers(config: &ZoneConfig, q: &DnsQuestion) -> Vec<DnsAnswer> {
+    let parent = match q.qtype {
+        Type::DS => ds_parent_zone(config, &q.qname),
+        _ => None,
+    };
+    let Some((zone_name, _)) = parent.or_else(|| find_zone(config, &q.qname))
+    else {
+        return Vec::new();
+    };
+    matching_records(config, zone_name, Type::NS)
+        .map(|(record, ttl)| name_server_answer(zone_name, record, ttl))
+        .collect()
+}
+
+/// The SOA record of a zone for the authority section of a negative
+/// answer, its TTL capped by the negative caching one (RFC 2308 3)
+/// and its names without the trailing dot, as read off the wire.
+fn zone_soa(config: &ZoneConfig, zone_name: &str) -> Vec<DnsAnswer> {
+    matching_records(config, zone_name, Type::SOA)
+        .map(|(record, mut ttl)| {
+            let mut rdata = record.rdata.clone();
+            if let RData::SOA { mname, rname, minimum, .. } = &mut rdata {
+                mname.truncate(mname.trim_end_matches('.').len());
+                rname.truncate(rname.trim_end_matches('.').len());
+                ttl = ttl.min(*minimum);
+            }
+            DnsAnswer {
+                name: zone_name.to_string(),
+                rtype: Type::SOA,
+                rclass: Class::IN,
+                ttl,
+                rdata,
+            }
+        })
+        .collect()
+}
+
+/// The addresses the zones have for `name_servers`, for the additional
+/// section. For those below a zone cut, a resolver has no other way
+/// to find them.
+fn glue(config: &ZoneConfig, name_servers: &[DnsAnswer]) -> Vec<DnsAnswer> {
+    let mut glue = Vec::new();
+    for record in name_servers {
+        let RData::NS(name_server) = &record.rdata else {
+            continue;
+        };
+        let name_server = name_server.trim_end_matches('.');
+        for record_type in [Type::A, Type::AAAA] {
+            let addresses = matching_records(config, name_server, record_type);
+            glue.extend(addresses.map(|(record, ttl)| DnsAnswer {
+                name: name_server.to_string(),
+                rtype: record_type,
+                rclass: Class::IN,
+                ttl,
+                rdata: record.rdata.clone(),
+            }));
+        }
+    }
+    glue
+}
+
+/// Answers for a blocked name, whatever the zones have for it.
+fn answer_blocked(
+    config: &ZoneConfig,
+    q: &DnsQuestion,
+    answers: &mut Vec<DnsAnswer>,
+) -> RCode {
+    let rdata = match (config.blocklist.sinkhole, q.qtype) {
+        (Sinkhole::NxDomain, _) => return RCode::NXDomain,
+        (Sinkhole::Unspecified, Type::A) => RData::A(Ipv4Addr::UNSPECIFIED),
+        (Sinkhole::Unspecified, Type::AAAA) => {
+            RData::AAAA(Ipv6Addr::UNSPECIFIED)
+        }
+        (Sinkhole::Address(IpAddr::V4(address)), Type::A) => RData::A(address),
+        (Sinkhole::Address(IpAddr::V6(address)), Type::AAAA) => {
+            RData::AAAA(address)
+        }
+        _ => return RCode::NoError, // no data of this type
+    };
+    answers.push(DnsAnswer {
+        name: q.qname.clone(),
+        rtype: q.qtype,
+        rclass: Class::IN,
+        ttl: clamp_ttl(config.default_ttl),
+        rdata,
+    });
+    RCode::NoError
+}
+
+/// Narrows subnet-tagged records down to those for the client's subnet,
+/// or to the untagged ones if none is for it.
+fn select_for_subnet(
+    records: &mut Vec<(Record, u32)>,
+    client_subnet: Option<&mut ClientSubnet>,
+) {
+    if records.iter().all(|(record, _)| record.subnet.is_none()) {
+        return;
+    }
+    let address = client_subnet.as_ref().map(|subnet| subnet.address);
+    let for_client = |(record, _): &(Record, u32)| {
+        record
+            .subnet
+            .zip(address)
+            .is_some_and(|(block, address)| block.contains(address))
+    };
+    if records.iter().any(for_client) {
+        records.retain(for_client);
+    } else {
+        records.retain(|(record, _)| record.subnet.is_none());
+    }
+    if let Some(subnet) = client_subnet {
+        subnet.scope_prefix = subnet.source_prefix;
+    }
+}
+
+/// Keeps the first of records with the same data, as an RRset holds each
+/// record once (RFC 2181 5) however many times a zone happens to list it.
+fn drop_repeated_records(records: &mut Vec<(Record, u32)>) {
+    let mut i = 1;
+    while i < records.len() {
+        let rdata = &records[i].0.rdata;
+        if records[..i].iter().any(|(earlier, _)| earlier.rdata == *rdata) {
+            records.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Looks `q` up in the zones and rules, returning the RCODE and whether the
+/// answer is authoritative. The scope of `client_subnet` is set if the
+/// answer depended on it.
+fn answer_internet(
+    config: &ZoneConfig,
+    q: &DnsQuestion,
+    deadline: &Deadline,
+    client_subnet: Option<&mut ClientSubnet>,
+    answers: &mut Vec<DnsAnswer>,
+) -> Result<(RCode, bool), ReplyError> {
+    if let Some((owner, target, ttl)) = find_dname(config, &q.qname) {
+        answers.extend(synthesize_from_dname(q, owner, target, ttl));
+        return Ok((RCode::NoError, false));
+    }
+    let mut records = if q.qtype == Type::DS {
+        find_ds_record(config, &q.qname)
+    } else {
+        find_record(config, &q.qname, q.qtype)
+    };
+    deadline.check()?;
+    let now = deadline.clock.system_time();
+    records.retain(|(record, _)| {
+        record.active.is_none_or(|window| window.contains(now))
+    });
+    select_for_subnet(&mut records, client_subnet);
+    drop_repeated_records(&mut records);
+    // answered by the parent, not referred to the child
+    let authoritative = q.qtype == Type::DS && !records.is_empty();
+    if records.is_empty() {
+        return Ok((missing_rcode(config, &q.qname), authoritative));
+    }
+    answers.extend(records.into_iter().map(|(record, ttl)| DnsAnswer {
+        name: q.qname.clone(),
+        rclass: q.qclass,
+        rtype: q.qtype,
+        ttl,
+        rdata: record.rdata,
+    }));
+    order_answers(config, q, answers);
+    if matches!(q.qtype, Type::SVCB | Type::HTTPS) {
+        chase_svcb_aliases(config, q, deadline, answers)?;
+    }
+    Ok((RCode::NoError, authoritative))
+}
+
+/// NoError for a name that exists without records of the type asked for,
+/// NXDomain for one that doesn't unless its zone is open to every name.
+/// Names outside every zone are answered as `out_of_zone` says.
+fn missing_rcode(config: &ZoneConfig, qname: &str) -> RCode {
+    if name_exists(config, qname) {
+        return RCode::NoError;
+    }
+    match find_zone(config, qname) {
+        Some((_, zone)) if zone.missing_names == MissingNames::NoData => {
+            RCode::NoError
+        }
+        Some(_) => RCode::NXDomain,
+        None if config.out_of_zone == OutOfZone::NxDomain => RCode::NXDomain,
+        None => {
+            eprintln!("Refusing {qname}: not in any zone");
+            RCode::Refused
+        }
+    }
+}
+
+/// Only answers the names identifying the server, see `chaos_text`.
+fn answer_chaos(
+    config: &ZoneConfig,
+    q: &DnsQuestion,
+    answers: &mut Vec<DnsAnswer>,
+) -> RCode {
+    let Some(text) = chaos_text(config, &q.qname) else {
+        eprintln!("Refusing {}: not a CHAOS name we know", q.qname);
+        return RCode::Refused;
+    };
+    if q.qtype == Type::TXT {
+        answers.push(DnsAnswer {
+            name: q.qname.clone(),
+            rtype: Type::TXT,
+            rclass: Class::CH,
+            ttl: 0,
+            rdata: RData::TXT(vec![text]),
+        });
+    }
+    RCode::NoError
+}
+
+/// All records of a zone as answers in canonical order, for a zone transfer.
+pub fn axfr_answers(
+    config: &ZoneConfig,
+    zone_name: &str,
+) -> Option<Vec<DnsAnswer>> {
+    let (zone_name, zone) = config.zone(zone_name)?;
+    let answers = zone
+        .canonical_records(zone_name)
+        .into_iter()
+        .map(|record| DnsAnswer {
+            name: absolute_name(&record.name, zone_name),
+            rtype: record.record_type,
+            rclass: Class::IN,
+            ttl: zone.ttl_of(record, config.default_ttl),
+            rdata: record.rdata.clone(),
+        })
+        .collect();
+    Some(answers)
+}
+
+/// Roughly how many octets of records go in each message of a transfer.
+const TRANSFER_MESSAGE_SIZE: usize = 16384;
+
+/// Whether `query` asks for a zone transfer, which only TCP can carry.
+#[must_use]
+pub fn is_transfer(query: &DnsPacket) -> bool {
+    !query.header.response
+        && query.header.opcode == OpCode::QUERY
+        && query.questions.len() == 1
+        && query.questions[0].qtype == Type::Other(AXFR_TYPE)
+}
+
+/// The messages answering an AXFR query from `peer`: the zone's SOA, its
+/// other records and the SOA again, spread over as many messages as it
+/// takes. A single Refused if `peer` isn't allowed transfers, a single
+/// NotAuth if the zone isn't ours.
+#[must_use]
+pub fn transfer_replies(
+    config: &ZoneConfig,
+    query: &DnsPacket,
+    peer: IpAddr,
+) -> Vec<DnsPacket> {
+    let q = &query.questions[0];
+    if !config.allows_transfer(peer) {
+        eprintln!("Refusing transfer of {} to {peer}", q.qname);
+        let ede = ExtendedError::new(ExtendedError::PROHIBITED, "");
+        return refused_reply(query, ede).into_iter().collect();
+    }
+    let reply = |questions: Vec<DnsQuestion>, rcode, answers| {
+        DnsPacket::builder()
+            .header(DnsHeader::response_to(&query.header))
+            .authoritative_answer(rcode == RCode::NoError)
+            .rcode(rcode)
+            .questions(questions)
+            .answers(answers)
+            .edns(reply_edns(query, None))
+            .build()
+    };
+    let Some(records) = axfr_answers(config, &q.qname) else {
+        return vec![reply(
+            query.questions.clone(),
+            RCode::NotAuth,
+            Vec::new(),
+        )];
+    };
+    let (soa, others): (Vec<DnsAnswer>, Vec<DnsAnswer>) =
+        records.into_iter().partition(|answer| answer.rtype == Type::SOA);
+    let Some(soa) = soa.into_iter().next() else {
+        eprintln!("Not transferring {}: no SOA record", q.qname);
+        return vec![reply(
+            query.questions.clone(),
+            RCode::ServFail,
+            Vec::new(),
+        )];
+    };
+
+    let mut batches = vec![Vec::new()];
+    let mut batch_size = 0;
+    let records = std::iter::once(soa.clone()).chain(others).chain([soa]);
+    for answer in records {
+        let size = answer.try_serialize().map_or(0, |bytes| bytes.len());
+        if batch_size + size > TRANSFER_MESSAGE_SIZE && batch_size > 0 {
+            batches.push(Vec::new());
+            batch_size = 0;
+        }
+        batch_size += size;
+        batches.last_mut().unwrap().push(answer);
+    }
+    // only the first message repeats the question (RFC 5936 2.2)
+    batches
+        .into_iter()
+        .enumerate()
+        .map(|(i, answers)| {
+            let questions = if i == 0 { vec![q.clone()] } else { Vec::new() };
+            reply(questions, RCode::NoError, answers)
+        })
+        .collect()
+}
+
+/// The single A record every echo-mode reply carries.
+pub const ECHO_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+/// A minimal reply echoing the question with a fixed answer, no lookups.
+#[must_use]
+pub fn echo_reply(query: &DnsPacket) -> Option<DnsPacket> {
+    if query.header.response {
+        return None;
+    }
+    let mut reply = DnsPacket::builder()
+        .header(DnsHeader::response_to(&query.header))
+        .questions(query.questions.clone());
+    if let Some(q) = query.questions.first() {
+        reply = reply.add_answer(DnsAnswer {
+            name: q.qname.clone(),
+            rtype: Type::A,
+            rclass: Class::IN,
+            ttl: 0,
+            rdata: RData::A(ECHO_ADDRESS),
+        });
+    }
+    Some(reply.build())
+}
+
+/// The question to send upstream: a single IN-class one for a name
+/// outside all our zones and rules, if forwarders are configured.
+fn forwardable_question<'a>(
+    config: &ZoneConfig,
+    query: &'a DnsPacket,
+) -> Option<&'a DnsQuestion> {
+    if config.forwarders.is_empty()
+        || query.header.response
+        || query.header.opcode != OpCode::QUERY
+        || rejects_edns_flags(config, query)
+        || client_subnet(query).is_err()
+    {
+        return None;
+    }
+    let [q] = &query.questions[..] else {
+        return None;
+    };
+    let answered_locally = q.qclass != Class::IN
+        || is_canary(config, &q.qname)
+        || config.blocklist.blocks(&q.qname)
+        || find_zone(config, &q.qname).is_some()
+        || config.rules.iter().any(|rule| rule.pattern.is_match(&q.qname));
+    (!answered_locally).then_some(q)
+}
+
+async fn forward(
+    config: &ZoneConfig,
+    cache: &AnswerCache,
+    q: &DnsQuestion,
+) -> Result<(RCode, Vec<DnsAnswer>), io::Error> {
+    let key = CacheKey::from(q);
+    if let Some(cached) = cache.get(&key) {
+        return Ok(cached);
+    }
+    let mut error = io::Error::other("No forwarders configured");
+    for forwarder in &config.forwarders {
+        match Resolver::new(*forwarder).query(&q.qname, q.qtype).await {
+            Ok(reply) => {
+                let rcode = reply.header.rcode;
+                cache.insert(key, rcode, reply.answers.clone());
+                return Ok((rcode, reply.answers));
+            }
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Refused, with the reason spelled out to clients speaking EDNS.
+fn refused_reply(query: &DnsPacket, ede: ExtendedError) -> Option<DnsPacket> {
+    if query.header.response {
+        return None;
+    }
+    Some(
+        DnsPacket::builder()
+            .header(DnsHeader::response_to(&query.header))
+            .rcode(RCode::Refused)
+            .questions(query.questions.clone())
+            .edns(reply_edns(query, None))
+            .ede(Some(ede))
+            .build(),
+    )
+}
+
+/// `construct_reply` for a client at `peer` as its view has it, except
+/// for queries that get forwarded upstream. Clients sending a cookie get
+/// a fresh server cookie back (RFC 7873 5.2), whether theirs was valid
+/// or not. Answers taking longer than the query timeout are given up on,
+/// with a ServFail.
+async fn reply_to(
+    config: &ZoneConfig,
+    caches: &[AnswerCache],
+    query: &DnsPacket,
+    peer: IpAddr,
+) -> Option<DnsPacket> {
+    reply_to_with_clock(config, caches, query, peer, &SystemClock).await
+}
+
+/// `reply_to` with records limited to a time of day judged by `clock`.
+async fn reply_to_with_clock(
+    root: &ZoneConfig,
+    caches: &[AnswerCache],
+    query: &DnsPacket,
+    peer: IpAddr,
+    clock: &dyn Clock,
+) -> Option<DnsPacket> {
+    let (config, cache) = select_view(root, caches, peer);
+    let query_timeout = Duration::from_secs(config.query_timeout);
+    let answering = reply_without_cookie(config, cache, query, peer, clock);
+    let mut reply = match tokio::time::timeout(query_timeout, answering).await {
+        Ok(reply) => reply?,
+        Err(_) => {
+            eprintln!("Giving up on answering {peer} after {query_timeout:?}");
+            servfail_reply(query)
+        }
+    };
+    if let Ok(Some(cookie)) = query_cookie(query)
+        && let Some(edns) = &mut reply.edns
+    {
+        // a valid one is as good as a fresh one, which renews it
+        if !cookie.server.is_empty()
+            && !config.cookie_secret.validates(&cookie, peer)
+        {
+            eprintln!("Replacing invalid server cookie from {peer}");
+        }
+        let cookie = config.cookie_secret.reply_cookie(&cookie, peer);
+        edns.options.push(cookie.to_option());
+    }
+    Some(reply)
+}
+
+/// `reply_to` without the cookie.
+async fn reply_without_cookie(
+    config: &ZoneConfig,
+    cache: &AnswerCache,
+    query: &DnsPacket,
+    peer: IpAddr,
+    clock: &dyn Clock,
+) -> Option<DnsPacket> {
+    if !config.allows_query(peer) {
+        eprintln!("Refusing query from {peer}: not in allow_query");
+        let ede = ExtendedError::new(ExtendedError::PROHIBITED, "");
+        return refused_reply(query, ede);
+    }
+    if config.echo_mode {
+        return echo_reply(query);
+    }
+    let Some(q) = forwardable_question(config, query) else {
+        return answer_locally(config, query, peer, clock);
+    };
+    let (rcode, answers, ede) = match forward(config, cache, q).await {
+        Ok((rcode, answers)) => (rcode, answers, None),
+        Err(e) => {
+            eprintln!("Forwarding query for {} failed: {e}", q.qname);
+            let ede = ExtendedError::new(
+                ExtendedError::NETWORK_ERROR,
+                "No forwarder answered",
+            );
+            (RCode::ServFail, Vec::new(), Some(ede))
+        }
+    };
+    Some(
+        DnsPacket::builder()
+            .header(DnsHeader::response_to(&query.header))
+            .recursion_available(config.recursion_available())
+            .rcode(rcode)
+            .add_question(q.clone())
+            .answers(answers)
+            .edns(reply_edns(query, client_subnet(query).ok().flatten()))
+            .ede(ede)
+            .build(),
+    )
+}
+
+/// `construct_reply` for queries answered from the zones, with a ServFail
+/// for anything going wrong on the server's side, a slow lookup included.
+fn answer_locally(
+    config: &ZoneConfig,
+    query: &DnsPacket,
+    peer: IpAddr,
+    clock: &dyn Clock,
+) -> Option<DnsPacket> {
+    construct_reply_with_clock(config, query, clock).unwrap_or_else(|e| {
+        eprintln!("Failed to answer {peer}: {e}");
+        Some(servfail_reply(query))
     })
 }
 
+/// The config serving clients at `peer` and its forwarding cache,
+/// kept apart per view so views never see each other's answers.
+fn select_view<'a>(
+    config: &'a ZoneConfig,
+    caches: &'a [AnswerCache],
+    peer: IpAddr,
+) -> (&'a ZoneConfig, &'a AnswerCache) {
+    match config.view_index(peer) {
+        Some(index) => (&config.views[index].config, &caches[index + 1]),
+        None => (config, &caches[0]),
+    }
+}
+
+/// FormErr for a query whose header parses but whose body doesn't.
+/// Anything shorter than a header isn't worth answering.
+fn formerr_reply(data: &[u8]) -> Option<DnsPacket> {
+    let header = parse_dns_header(&mut &data[..]).ok()?;
+    if header.response {
+        return None;
+    }
+    Some(
+        DnsPacket::builder()
+            .header(DnsHeader::response_to(&header))
+            .rcode(RCode::FormErr)
+            .build(),
+    )
+}
+
+/// ServFail for a query or a reply the server failed at, keeping
+/// the question and the OPT record but none of the answers.
+#[must_use]
+pub fn servfail_reply(packet: &DnsPacket) -> DnsPacket {
+    let DnsPacket { header, questions, edns, .. } = packet;
+    DnsPacket::builder()
+        .header(DnsHeader::response_to(header))
+        .rcode(RCode::ServFail)
+        .questions(questions.clone())
+        .edns(edns.as_ref().map(|edns| EdnsOpt {
+            flags: edns.flags & DO_FLAG,
+            ..EdnsOpt::default()
+        }))
+        .build()
+}
+
+/// The reply without its answers and with TC set, which sends
+/// the client to TCP for them.
+fn truncated_reply(reply: DnsPacket) -> DnsPacket {
+    let DnsPacket { header, questions, edns, .. } = reply;
+    DnsPacket::builder()
+        .header(DnsHeader::response_to(&header))
+        .authoritative_answer(header.authoritative_answer)
+        .truncation(true)
+        .recursion_available(header.recursion_available)
+        .rcode(header.rcode)
+        .questions(questions)
+        .edns(edns)
+        .build()
+}
+
+/// Serializes a reply, replacing one that doesn't fit the wire format
+/// with a ServFail instead of sending it corrupt, or dropping it if even
+/// that doesn't fit.
+fn encode(reply: DnsPacket) -> Option<Vec<u8>> {
+    reply
+        .try_serialize()
+        .or_else(|e| {
+            eprintln!("Sending ServFail instead: {}", ReplyError::from(e));
+            servfail_reply(&reply).try_serialize()
+        })
+        .inspect_err(|e| {
+            eprintln!("Not sending a reply that can't be encoded: {e}")
+        })
+        .ok()
+}
+
+/// Notes a query in the query log along with the rcode of its reply and
+/// the answers, counted over all the messages of a zone transfer.
+fn log_query(
+    query_log: &QueryLog,
+    peer: std::net::SocketAddr,
+    query: Option<&DnsPacket>,
+    replies: &[DnsPacket],
+    transport: &str,
+) {
+    let Some(first) = replies.first() else {
+        return;
+    };
+    query_log.record(
+        peer.ip(),
+        query.and_then(|query| query.questions.first()),
+        first.header.rcode,
+        replies.iter().map(|reply| reply.answers.len()).sum(),
+        transport,
+    );
+}
+
 async fn process_udp(
     config: Arc<ZoneConfig>,
+    caches: Arc<[AnswerCache]>,
+    query_log: Arc<QueryLog>,
+    rate_limiter: Arc<RateLimiter>,
     socket: Arc<UdpSocket>,
-    data: Vec<u8>,
+    data: PooledBuffer,
     peer: std::net::SocketAddr,
 ) -> Result<(), io::Error> {
-    let packet = parse_dns_query(&data)?;
+    let packet = match parse_dns_query(&data) {
+        Ok(packet) => packet,
+        Err(e) => {
+            eprintln!("Malformed query from {peer}: {e}");
+            if let Some(reply) = formerr_reply(&data) {
+                log_query(
+                    &query_log,
+                    peer,
+                    None,
+                    slice::from_ref(&reply),
+                    "UDP",
+                );
+                if let Some(reply_bytes) = encode(reply) {
+                    socket.send_to(&reply_bytes, &peer).await?;
+                }
+            }
+            return Ok(());
+        }
+    };
     eprintln!("Received query: {packet}");
 
-    if let Some(reply) = construct_reply(&config, &packet) {
+    if let Some(mut reply) =
+        reply_to(&config, &caches, &packet, peer.ip()).await
+    {
+        let config = config.view_for(peer.ip());
+        // a valid server cookie shows the address isn't spoofed
+        let has_valid_cookie =
+            query_cookie(&packet).ok().flatten().is_some_and(|cookie| {
+                config.cookie_secret.validates(&cookie, peer.ip())
+            });
+        if !has_valid_cookie {
+            match rate_limiter.check(peer.ip(), &reply) {
+                Verdict::Send => {}
+                Verdict::Truncate => {
+                    eprintln!("Rate limiting {peer}: truncating");
+                    reply = truncated_reply(reply);
+                }
+                Verdict::Drop => {
+                    eprintln!("Rate limiting {peer}: dropping");
+                    return Ok(());
+                }
+            }
+        }
+        log_query(
+            &query_log,
+            peer,
+            Some(&packet),
+            slice::from_ref(&reply),
+            "UDP",
+        );
         eprintln!("Sending back reply: {reply}");
-        let sent = socket.send_to(&reply.serialize(), &peer).await?;
+        let Some(reply_bytes) = encode(reply) else {
+            return Ok(());
+        };
+        let sent = socket.send_to(&reply_bytes, &peer).await?;
         eprintln!("Sent {sent} bytes back to {peer}");
     } else {
         eprintln!("Not answering that query");
@@ -98,74 +1165,582 @@ async fn process_udp(
     Ok(())
 }
 
-async fn process_tcp(
+/// Queries of a TCP connection answered at once. Reading stops while
+/// this many are, so a client can't queue up tasks and replies unbounded.
+pub const MAX_PIPELINED_QUERIES: usize = 16;
+
+/// Reads the queries a client sends, answering each in a task of its own,
+/// so that a slow one doesn't hold up those pipelined behind it, up to
+/// `MAX_PIPELINED_QUERIES` at a time.
+/// Replies go out as they're ready, not necessarily in order (RFC 7766).
+/// The stream is either a plain TCP one or a TLS one on top of it,
+/// as `transport` tells the query log.
+async fn process_tcp<S>(
     config: Arc<ZoneConfig>,
-    mut stream: TcpStream,
+    caches: Arc<[AnswerCache]>,
+    query_log: Arc<QueryLog>,
+    transport: &'static str,
+    stream: S,
     peer: std::net::SocketAddr,
-) -> Result<(), io::Error> {
+    _slot: ConnectionSlot, // released when the connection closes
+) -> Result<(), io::Error>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let idle_timeout = Duration::from_secs(config.tcp_idle_timeout);
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (replies_tx, replies_rx) = mpsc::channel(MAX_PIPELINED_QUERIES);
+    let writing = tokio::spawn(write_replies(writer, replies_rx, peer));
+    let in_flight = Arc::new(Semaphore::new(MAX_PIPELINED_QUERIES));
     loop {
+        // never closed, so it only waits for a query to be answered
+        let permit = Arc::clone(&in_flight)
+            .acquire_owned()
+            .await
+            .map_err(io::Error::other)?;
         // length prefix
-        let length = match stream.read_u16().await {
-            Ok(len) => len,
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+        let length = match timeout(idle_timeout, reader.read_u16()).await {
+            Ok(Ok(len)) => len,
+            Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
                 eprintln!("TCP connection closed by {peer}");
-                return Ok(());
+                break;
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                eprintln!("Closing idle TCP connection from {peer}");
+                break;
             }
-            Err(e) => return Err(e),
         };
 
         let mut data = vec![0u8; length as usize];
-        stream.read_exact(&mut data).await?;
+        reader.read_exact(&mut data).await?;
         eprintln!("Received {length} bytes from {peer} (TCP)");
+        let answering = answer_tcp(
+            Arc::clone(&config),
+            Arc::clone(&caches),
+            Arc::clone(&query_log),
+            transport,
+            data,
+            peer,
+            replies_tx.clone(),
+        );
+        tokio::spawn(async move {
+            answering.await;
+            drop(permit); // the reply's handed over, so read the next query
+        });
+    }
+    // the writer finishes once the queries still in flight are answered
+    drop(replies_tx);
+    writing.await.map_err(io::Error::other)?
+}
+
+/// Answers one query read from a TCP connection, handing the replies over
+/// to the connection's writer. Only over TCP do clients learn the idle
+/// timeout from the EDNS keepalive option (RFC 7828), and only over TCP
+/// are zones transferred, in a run of messages kept together.
+async fn answer_tcp(
+    config: Arc<ZoneConfig>,
+    caches: Arc<[AnswerCache]>,
+    query_log: Arc<QueryLog>,
+    transport: &'static str,
+    data: Vec<u8>,
+    peer: std::net::SocketAddr,
+    replies: mpsc::Sender<Vec<Vec<u8>>>,
+) {
+    let query = parse_dns_query(&data);
+    let (mut messages, wants_keepalive) = match &query {
+        Ok(packet) => {
+            eprintln!("Received query: {packet}");
+            let wants_keepalive =
+                packet.edns.as_ref().is_some_and(EdnsOpt::requests_keepalive);
+            let messages = if is_transfer(packet) {
+                transfer_replies(config.view_for(peer.ip()), packet, peer.ip())
+            } else {
+                let reply = reply_to(&config, &caches, packet, peer.ip()).await;
+                reply.into_iter().collect()
+            };
+            (messages, wants_keepalive)
+        }
+        Err(e) => {
+            eprintln!("Malformed query from {peer}: {e}");
+            (formerr_reply(&data).into_iter().collect(), false)
+        }
+    };
+    if messages.is_empty() {
+        eprintln!("Not answering that query");
+        return;
+    }
+    log_query(&query_log, peer, query.as_ref().ok(), &messages, transport);
+    if wants_keepalive && let Some(reply_edns) = &mut messages[0].edns {
+        let idle_timeout = Duration::from_secs(config.tcp_idle_timeout);
+        reply_edns.options.push(keepalive_option(idle_timeout));
+    }
+    let mut encoded = Vec::with_capacity(messages.len());
+    for reply in messages {
+        eprintln!("Sending back reply: {reply}");
+        let Some(reply_bytes) = encode(reply) else {
+            return; // a partial transfer is no use either
+        };
+        encoded.push(reply_bytes);
+    }
+    // fails only if the writer gave up on the connection
+    replies.send(encoded).await.ok();
+}
+
+/// Serves a connection the way its listener's transport calls for,
+/// completing the TLS handshake first for DNS over TLS or HTTPS.
+/// A client failing the handshake only loses its own connection.
+async fn process_connection(
+    config: Arc<ZoneConfig>,
+    caches: Arc<[AnswerCache]>,
+    query_log: Arc<QueryLog>,
+    stream: TcpStream,
+    peer: std::net::SocketAddr,
+    slot: ConnectionSlot,
+    transport: Transport,
+) -> Result<(), io::Error> {
+    let acceptor = match &transport {
+        Transport::Tcp => {
+            return process_tcp(
+                config,
+                caches,
+                query_log,
+                transport.name(),
+                stream,
+                peer,
+                slot,
+            )
+            .await;
+        }
+        Transport::Tls(acceptor) | Transport::Https(acceptor) => acceptor,
+    };
+    let idle_timeout = Duration::from_secs(config.tcp_idle_timeout);
+    let stream = match timeout(idle_timeout, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            eprintln!("TLS handshake with {peer} failed: {e}");
+            return Ok(());
+        }
+        Err(_) => {
+            eprintln!("TLS handshake with {peer} timed out");
+            return Ok(());
+        }
+    };
+    if let Transport::Https(_) = transport {
+        doh::process_https(config, caches, query_log, stream, peer, slot).await
+    } else {
+        let transport = transport.name();
+        process_tcp(config, caches, query_log, transport, stream, peer, slot)
+            .await
+    }
+}
 
-        let packet = parse_dns_query(&data)?;
-        eprintln!("Received query: {packet}");
-        if let Some(reply) = construct_reply(&config, &packet) {
-            eprintln!("Sending back reply: {reply}");
-            let reply_bytes = reply.serialize();
+async fn write_replies(
+    mut writer: impl AsyncWrite + Unpin,
+    mut replies: mpsc::Receiver<Vec<Vec<u8>>>,
+    peer: std::net::SocketAddr,
+) -> Result<(), io::Error> {
+    while let Some(messages) = replies.recv().await {
+        for reply_bytes in messages {
             let reply_len = reply_bytes.len() as u16;
-            stream.write_u16(reply_len).await?; // length prefix
-            stream.write_all(&reply_bytes).await?;
-            stream.flush().await?;
+            writer.write_u16(reply_len).await?; // length prefix
+            writer.write_all(&reply_bytes).await?;
             eprintln!("Sent {} bytes back to {peer} (TCP)", reply_len);
-        } else {
-            eprintln!("Not answering that query");
         }
+        writer.flush().await?;
     }
+    Ok(())
+}
+
+/// What the listening tasks hand over to the serve loop.
+enum Incoming {
+    Datagram(Arc<UdpSocket>, PooledBuffer, std::net::SocketAddr),
+    Connection(TcpStream, std::net::SocketAddr, Transport),
 }
 
-pub async fn serve(config: &ZoneConfig, listen: &str) -> Result<(), io::Error> {
-    let udp_socket = UdpSocket::bind(listen).await?;
-    let tcp_listener = TcpListener::bind(listen).await?;
+/// What a listener's connections carry DNS over.
+#[derive(Clone)]
+enum Transport {
+    Tcp,
+    /// DNS over TLS, with the acceptor to wrap connections in.
+    Tls(TlsAcceptor),
+    /// DNS over HTTPS.
+    Https(TlsAcceptor),
+}
 
-    eprintln!("Listening on {} (UDP)...", udp_socket.local_addr()?);
-    eprintln!("Listening on {} (TCP)...", tcp_listener.local_addr()?);
+impl Transport {
+    fn name(&self) -> &'static str {
+        match self {
+            Transport::Tcp => "TCP",
+            Transport::Tls(_) => "TLS",
+            Transport::Https(_) => "HTTPS",
+        }
+    }
+}
 
-    let udp_socket = Arc::new(udp_socket);
-    let config = Arc::new(config.clone());
+async fn receive_datagrams(
+    socket: Arc<UdpSocket>,
+    buffers: Arc<BufferPool>,
+    incoming: mpsc::Sender<Incoming>,
+) -> Result<(), io::Error> {
+    loop {
+        // a buffer of its own, so it's never overwritten while processed
+        let mut recv_buf = buffers.take();
+        let (size, peer) = socket.recv_from(&mut recv_buf).await?;
+        eprintln!("Received {size} bytes from {peer} (UDP)");
+        recv_buf.truncate(size);
+        let datagram = Incoming::Datagram(Arc::clone(&socket), recv_buf, peer);
+        if incoming.send(datagram).await.is_err() {
+            return Ok(()); // the serve loop is gone
+        }
+    }
+}
 
+async fn accept_connections(
+    listener: TcpListener,
+    transport: Transport,
+    incoming: mpsc::Sender<Incoming>,
+) -> Result<(), io::Error> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let connection = Incoming::Connection(stream, peer, transport.clone());
+        if incoming.send(connection).await.is_err() {
+            return Ok(()); // the serve loop is gone
+        }
+    }
+}
+
+/// Binds UDP sockets and a TCP listener to `listen`. Unless `ipv6_only` is
+/// None, it decides whether IPv6 sockets take IPv4-mapped traffic as well.
+/// Asked for port 0, TCP gets the port UDP got, as a DNS server's sockets
+/// usually share one, or a port of its own if that one's taken for TCP.
+/// More than one UDP socket share the address with SO_REUSEPORT, for the
+/// kernel to spread datagrams over them.
+async fn bind(
+    listen: &str,
+    ipv6_only: Option<bool>,
+    udp_sockets: NonZeroUsize,
+) -> Result<(Vec<UdpSocket>, TcpListener), io::Error> {
+    let reuse_port = udp_sockets.get() > 1;
+    if ipv6_only.is_none() && !reuse_port {
+        let udp = UdpSocket::bind(listen).await?;
+        if listen.rsplit_once(':').is_some_and(|(_, port)| port == "0") {
+            let shared = udp.local_addr()?;
+            match TcpListener::bind(shared).await {
+                Ok(tcp) => return Ok((vec![udp], tcp)),
+                Err(e) => log_port_not_shared(shared, &e),
+            }
+        }
+        return Ok((vec![udp], TcpListener::bind(listen).await?));
+    }
+    let addr =
+        tokio::net::lookup_host(listen).await?.next().ok_or_else(|| {
+            io::Error::other(format!(
+                "No address to listen on for '{}'",
+                listen
+            ))
+        })?;
+    let socket = |socket_type, protocol| {
+        let socket = socket2::Socket::new(
+            socket2::Domain::for_address(addr),
+            socket_type,
+            Some(protocol),
+        )?;
+        if let Some(ipv6_only) = ipv6_only
+            && addr.is_ipv6()
+        {
+            socket.set_only_v6(ipv6_only)?; // has to happen before bind
+        }
+        socket.set_nonblocking(true)?;
+        Ok::<_, io::Error>(socket)
+    };
+    let udp_bound_to = |addr: std::net::SocketAddr| {
+        let udp = socket(socket2::Type::DGRAM, socket2::Protocol::UDP)?;
+        if reuse_port {
+            set_reuse_port(&udp)?; // every socket sharing it needs it
+        }
+        udp.bind(&addr.into())?;
+        UdpSocket::from_std(udp.into())
+    };
+    let tcp_bound_to = |addr: std::net::SocketAddr| {
+        let tcp = socket(socket2::Type::STREAM, socket2::Protocol::TCP)?;
+        tcp.set_reuse_address(true)?; // as TcpListener::bind does
+        tcp.bind(&addr.into())?;
+        tcp.listen(1024)?;
+        Ok::<_, io::Error>(tcp)
+    };
+
+    let udp = udp_bound_to(addr)?;
+    let shared = udp.local_addr()?;
+    let mut udp = vec![udp];
+    for _ in 1..udp_sockets.get() {
+        udp.push(udp_bound_to(shared)?);
+    }
+    let tcp = if addr.port() == 0 {
+        tcp_bound_to(shared).or_else(|e| {
+            log_port_not_shared(shared, &e);
+            tcp_bound_to(addr)
+        })?
+    } else {
+        tcp_bound_to(addr)?
+    };
+    Ok((udp, TcpListener::from_std(tcp.into())?))
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &socket2::Socket) -> Result<(), io::Error> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &socket2::Socket) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Several UDP sockets per address need SO_REUSEPORT",
+    ))
+}
+
+fn log_port_not_shared(udp_addr: std::net::SocketAddr, e: &io::Error) {
+    eprintln!(
+        "Can't listen on {udp_addr} over TCP too ({e}), using another port"
+    );
+}
+
+/// Serves on each of the `listen` addresses, or on the sockets passed by
+/// systemd if there are any, and over TLS and HTTPS too if configured.
+pub async fn serve(
+    config: Arc<ZoneConfig>,
+    listen: &[String],
+) -> Result<(), io::Error> {
+    let (_reloads, configs) = watch::channel(config);
+    serve_reloading(configs, listen).await
+}
+
+/// The forwarding caches for `config`: one for the top level, then one
+/// per view.
+fn answer_caches(config: &ZoneConfig) -> Arc<[AnswerCache]> {
+    (0..=config.views.len())
+        .map(|_| {
+            AnswerCache::default().with_max_entries(config.cache_max_entries)
+        })
+        .collect()
+}
+
+/// `serve`, switching to every config sent over `configs` for the
+/// queries coming in after it, with fresh caches. Listening, limits,
+/// privileges and the health endpoint stay as the first config set them.
+pub async fn serve_reloading(
+    mut configs: watch::Receiver<Arc<ZoneConfig>>,
+    listen: &[String],
+) -> Result<(), io::Error> {
+    let mut config = configs.borrow_and_update().clone();
     let mut tasks = JoinSet::new();
-    let mut recv_buf = vec![0; 65535];
+    // up first, to tell orchestrators the server is alive but not ready
+    let ready = Arc::new(AtomicBool::new(false));
+    let mut caches = answer_caches(&config);
+    if let Some(health_listen) = &config.health_listen {
+        let listener = TcpListener::bind(health_listen).await?;
+        eprintln!("Listening on {} (HTTP)...", listener.local_addr()?);
+        tasks.spawn(health::serve_probes(
+            listener,
+            Arc::clone(&ready),
+            Arc::clone(&config),
+            Arc::clone(&caches),
+        ));
+    }
+    let mut sockets = Vec::new();
+    if let Some((udp, tcp)) = activation::inherited_sockets()? {
+        eprintln!("Using the sockets passed by systemd");
+        sockets.push((
+            vec![UdpSocket::from_std(udp)?],
+            TcpListener::from_std(tcp)?,
+        ));
+    } else {
+        for listen in listen {
+            sockets.push(
+                bind(listen, config.ipv6_only, config.udp_sockets).await?,
+            );
+        }
+    }
+    if sockets.is_empty() {
+        return Err(io::Error::other("No addresses to listen on"));
+    }
+    let mut secure_listeners = Vec::new();
+    if let Some(tls) = &config.tls {
+        let transport = Transport::Tls(tls.acceptor()?);
+        for listen in &tls.listen {
+            let listener = TcpListener::bind(listen).await?;
+            secure_listeners.push((listener, transport.clone()));
+        }
+    }
+    if let Some(doh) = &config.doh {
+        let transport = Transport::Https(doh.acceptor()?);
+        for listen in &doh.listen {
+            let listener = TcpListener::bind(listen).await?;
+            secure_listeners.push((listener, transport.clone()));
+        }
+    }
+    for udp_socket in sockets.iter().flat_map(|(udp_sockets, _)| udp_sockets) {
+        socket_buffers::set_buffer_sizes(
+            udp_socket,
+            config.udp_recv_buffer,
+            config.udp_send_buffer,
+        )?;
+    }
+    // opened while it may still need the privileges
+    let query_log = Arc::new(match &config.query_log {
+        Some(path) => QueryLog::open(path)?,
+        None => QueryLog::default(),
+    });
+    if config.query_log.is_some() {
+        tasks.spawn(Arc::clone(&query_log).flush_periodically());
+    }
+    if config.user.is_some() || config.group.is_some() {
+        privileges::drop_privileges(
+            config.user.as_deref(),
+            config.group.as_deref(),
+        )?;
+        eprintln!("Dropped privileges");
+    }
+    ready.store(true, Ordering::Relaxed);
+
+    let tcp_connections =
+        Arc::new(ConnectionTracker::new(config.max_tcp_conns_per_ip));
+    let rate_limiter = Arc::new(RateLimiter::new(
+        config.rate_limit.clone(),
+        Arc::new(SystemClock),
+    ));
+
+    let (incoming_tx, mut incoming_rx) = mpsc::channel(64);
+    let buffers = BufferPool::new(65535, 64);
+    for (udp_sockets, tcp_listener) in sockets {
+        eprintln!("Listening on {} (UDP)...", udp_sockets[0].local_addr()?);
+        eprintln!("Listening on {} (TCP)...", tcp_listener.local_addr()?);
+        // each with a receive loop of its own
+        for udp_socket in udp_sockets {
+            tasks.spawn(receive_datagrams(
+                Arc::new(udp_socket),
+                Arc::clone(&buffers),
+                incoming_tx.clone(),
+            ));
+        }
+        tasks.spawn(accept_connections(
+            tcp_listener,
+            Transport::Tcp,
+            incoming_tx.clone(),
+        ));
+    }
+    for (listener, transport) in secure_listeners {
+        let addr = listener.local_addr()?;
+        eprintln!("Listening on {addr} ({})...", transport.name());
+        tasks.spawn(accept_connections(
+            listener,
+            transport,
+            incoming_tx.clone(),
+        ));
+    }
 
     loop {
         tokio::select! {
             // return on errors (may be a weird decision, but I was curious)
             Some(result) = tasks.join_next() => { result.unwrap()?; }
-            // process UDP datagrams
-            recv_result = udp_socket.recv_from(&mut recv_buf) => {
-                let (size, peer) = recv_result?;
-                eprintln!("Received {size} bytes from {peer} (UDP)");
-                tasks.spawn(process_udp(Arc::clone(&config),
-                                        Arc::clone(&udp_socket),
-                                        recv_buf[..size].to_vec(),
-                                        peer));
+            Ok(()) = configs.changed() => {
+                config = configs.borrow_and_update().clone();
+                caches = answer_caches(&config);
+                eprintln!("Serving the reloaded config");
             }
-            // accept TCP connections
-            accept_result = tcp_listener.accept() => {
-                let (stream, peer) = accept_result?;
-                eprintln!("Accepted TCP connection from {peer}");
-                tasks.spawn(process_tcp(Arc::clone(&config), stream, peer));
+            Some(incoming) = incoming_rx.recv() => match incoming {
+                // process UDP datagrams
+                Incoming::Datagram(socket, data, peer) => {
+                    tasks.spawn(process_udp(Arc::clone(&config),
+                                            Arc::clone(&caches),
+                                            Arc::clone(&query_log),
+                                            Arc::clone(&rate_limiter),
+                                            socket,
+                                            data,
+                                            peer));
+                }
+                // accept TCP connections
+                Incoming::Connection(stream, peer, transport) => {
+                    if let Some(slot) = tcp_connections.try_open(peer.ip()) {
+                        eprintln!("Accepted {} connection from {peer}",
+                                  transport.name());
+                        tasks.spawn(process_connection(Arc::clone(&config),
+                                                       Arc::clone(&caches),
+                                                       Arc::clone(&query_log),
+                                                       stream,
+                                                       peer,
+                                                       slot,
+                                                       transport));
+                    } else {
+                        let open = tcp_connections.open_connections(peer.ip());
+                        eprintln!("Refusing TCP connection from {peer}: \
+                                   {open} already open");
+                        drop(stream);
+                    }
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that moves on by `step` every time the time of day is
+    /// read, as if each lookup reading it took that long.
+    #[derive(Debug, Default)]
+    struct SlowClock {
+        clock: FakeClock,
+        step: Duration,
+    }
+
+    impl Clock for SlowClock {
+        fn now(&self) -> Instant {
+            self.clock.now()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            self.clock.advance(self.step);
+            self.clock.system_time()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_lookup_times_out() {
+        let config: ZoneConfig = "
+query_timeout: 1
+example.com:
+  records:
+  - {name: '', type: A, address: 192.0.2.1}
+"
+        .parse()
+        .unwrap();
+        let caches = [AnswerCache::default()];
+        let query = DnsPacket::builder()
+            .transaction_id(0x5107)
+            .add_question(DnsQuestion {
+                qname: "example.com".to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let fast = SlowClock::default();
+        let reply = reply_to_with_clock(&config, &caches, &query, peer, &fast)
+            .await
+            .unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.answers.len(), 1);
+
+        let slow = SlowClock { step: Duration::from_secs(1), ..fast };
+        let reply = reply_to_with_clock(&config, &caches, &query, peer, &slow)
+            .await
+            .unwrap();
+        assert_eq!(reply.header.transaction_id, 0x5107);
+        assert_eq!(reply.header.rcode, RCode::ServFail);
+        assert!(reply.answers.is_empty());
+    }
+}
@@ -1,22 +1,253 @@
 use clap::Parser;
-use toy_dns_server::{ZoneConfig, serve};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::watch;
+use toy_dns_server::{
+    PidFile, ZoneConfig, daemonize, serve_reloading, shutdown_signal,
+};
 
 #[derive(Parser)]
 struct Cli {
+    /// Address to listen on over UDP and TCP, can be repeated
     #[arg(long, default_value = "[::]:53")]
-    listen: String,
+    listen: Vec<String>,
+    /// Config file, or a directory of them, can be repeated to merge zones
     #[arg(long, default_value = "tests/example_zone.yaml")]
-    config: String,
+    config: Vec<PathBuf>,
+    /// Name answering TXT queries with a sequence number and timestamp
+    #[arg(long)]
+    canary_name: Option<String>,
+    /// Answer every query with a fixed A record, skipping all lookups
+    #[arg(long)]
+    echo_mode: bool,
+    /// Close new TCP connections from an address with this many open
+    #[arg(long)]
+    max_tcp_conns_per_ip: Option<usize>,
+    /// Answer FormErr to queries setting reserved EDNS flags
+    #[arg(long)]
+    strict_edns: bool,
+    /// Forwarded answers to cache before evicting the least recently used
+    #[arg(long)]
+    cache_max_entries: Option<usize>,
+    /// Seconds to keep a TCP connection without queries open
+    #[arg(long)]
+    tcp_idle_timeout: Option<u64>,
+    /// Seconds to spend answering a query before giving up with ServFail
+    #[arg(long)]
+    query_timeout: Option<u64>,
+    /// UDP sockets per address sharing it with SO_REUSEPORT, for throughput
+    #[arg(long)]
+    udp_sockets: Option<NonZeroUsize>,
+    /// Receive buffer size for UDP sockets in bytes, the system's if unset
+    #[arg(long)]
+    udp_recv_buffer: Option<usize>,
+    /// Send buffer size for UDP sockets in bytes, the system's if unset
+    #[arg(long)]
+    udp_send_buffer: Option<usize>,
+    /// File to log answered queries to, one tab-separated line each
+    #[arg(long)]
+    query_log: Option<PathBuf>,
+    /// Address to answer HTTP health probes on, /livez and /readyz
+    #[arg(long)]
+    health_listen: Option<String>,
+    /// Whether IPv6 addresses refuse IPv4 clients, the system decides if unset
+    #[arg(long)]
+    ipv6_only: Option<bool>,
+    /// Switch to this user (name or uid) once the sockets are bound
+    #[arg(long)]
+    user: Option<String>,
+    /// Switch to this group (name or gid), by default the user's own
+    #[arg(long)]
+    group: Option<String>,
+    /// Write the process id here, removing it again on shutdown
+    #[arg(long)]
+    pidfile: Option<PathBuf>,
+    /// Fork into the background, detached from the terminal (Unix only)
+    #[arg(long)]
+    daemon: bool,
+    /// Validate the config and list its zones, then exit without serving
+    #[arg(long)]
+    check_config: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let Cli { listen, config } = Cli::parse();
+/// Loads and merges the config files, taking the YAML and JSON files
+/// of a directory in name order.
+fn load_configs(
+    paths: &[PathBuf],
+) -> Result<ZoneConfig, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<_, _>>()?;
+            entries.retain(|entry| is_config(entry));
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    let mut merged: Option<ZoneConfig> = None;
+    for file in files {
+        let config = ZoneConfig::from_file(&file)?;
+        merged = Some(match merged {
+            Some(merged) => merged
+                .merge(config)
+                .map_err(|e| format!("{}: {}", file.display(), e))?,
+            None => config,
+        });
+    }
+    merged.ok_or_else(|| "No config files found".into())
+}
+
+/// Each zone with its number of records, views' zones after the rest.
+fn print_summary(zone_config: &ZoneConfig) {
+    let print_zones = |config: &ZoneConfig, prefix: &str| {
+        let mut zones: Vec<_> = config.zones.iter().collect();
+        zones.sort_by_key(|(zone_name, _)| *zone_name);
+        for (zone_name, zone) in zones {
+            println!("{prefix}{zone_name}: {} records", zone.records.len());
+        }
+    };
+    print_zones(zone_config, "");
+    for view in &zone_config.views {
+        print_zones(&view.config, &format!("view {}: ", view.name));
+    }
+    println!("Config OK");
+}
+
+fn is_config(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension == "yaml" || extension == "yml" || extension == "json"
+    })
+}
+
+/// The config the files and command line make up together, checked.
+/// Run at startup and again for every reload.
+fn load_zone_config(
+    cli: &Cli,
+) -> Result<ZoneConfig, Box<dyn std::error::Error>> {
+    let mut zone_config = load_configs(&cli.config)?;
+    if cli.canary_name.is_some() {
+        zone_config.canary_name.clone_from(&cli.canary_name);
+    }
+    zone_config.echo_mode |= cli.echo_mode;
+    zone_config.strict_edns |= cli.strict_edns;
+    for view in &mut zone_config.views {
+        if cli.canary_name.is_some() {
+            view.config.canary_name.clone_from(&cli.canary_name);
+        }
+        view.config.echo_mode |= cli.echo_mode;
+        view.config.strict_edns |= cli.strict_edns;
+    }
+    if let Some(cache_max_entries) = cli.cache_max_entries {
+        zone_config.cache_max_entries = cache_max_entries;
+    }
+    if let Some(tcp_idle_timeout) = cli.tcp_idle_timeout {
+        zone_config.tcp_idle_timeout = tcp_idle_timeout;
+    }
+    if let Some(query_timeout) = cli.query_timeout {
+        zone_config.query_timeout = query_timeout;
+        for view in &mut zone_config.views {
+            view.config.query_timeout = query_timeout;
+        }
+    }
+    if cli.max_tcp_conns_per_ip.is_some() {
+        zone_config.max_tcp_conns_per_ip = cli.max_tcp_conns_per_ip;
+    }
+    if let Some(udp_sockets) = cli.udp_sockets {
+        zone_config.udp_sockets = udp_sockets;
+    }
+    if cli.udp_recv_buffer.is_some() {
+        zone_config.udp_recv_buffer = cli.udp_recv_buffer;
+    }
+    if cli.udp_send_buffer.is_some() {
+        zone_config.udp_send_buffer = cli.udp_send_buffer;
+    }
+    if cli.query_log.is_some() {
+        zone_config.query_log.clone_from(&cli.query_log);
+    }
+    if cli.health_listen.is_some() {
+        zone_config.health_listen.clone_from(&cli.health_listen);
+    }
+    if cli.ipv6_only.is_some() {
+        zone_config.ipv6_only = cli.ipv6_only;
+    }
+    if cli.user.is_some() {
+        zone_config.user.clone_from(&cli.user);
+    }
+    if cli.group.is_some() {
+        zone_config.group.clone_from(&cli.group);
+    }
+    zone_config.add_reverse_records();
+    if let Err(problems) = zone_config.validate() {
+        for problem in &problems {
+            eprintln!("Invalid zone: {problem}");
+        }
+        return Err("The config failed validation".into());
+    }
+    Ok(zone_config)
+}
+
+/// Loads the config again on every SIGHUP and hands it to `serve`,
+/// carrying the SOA serials over from the one it replaces. A config
+/// failing to load leaves the running one in place.
+async fn reload_on_hangup(
+    cli: &Cli,
+    reloads: &watch::Sender<Arc<ZoneConfig>>,
+) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut hangup = signal(SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            eprintln!("Reloading the config");
+            match load_zone_config(cli) {
+                Ok(mut zone_config) => {
+                    zone_config.carry_serials_from(&reloads.borrow());
+                    reloads.send_replace(Arc::new(zone_config));
+                }
+                Err(e) => eprintln!("Keeping the running config: {e}"),
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (cli, reloads);
+        std::future::pending().await
+    }
+}
 
-    let yaml = std::fs::read_to_string(&config)?;
-    let zone_config: ZoneConfig = serde_yaml::from_str(&yaml)?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let zone_config = load_zone_config(&cli)?;
+    if cli.check_config {
+        print_summary(&zone_config);
+        return Ok(());
+    }
 
-    eprintln!("Toy DNS server will now attempt to listen on {listen}");
-    serve(&zone_config, &listen).await?;
+    // before the runtime starts any threads, which a fork would lose
+    if cli.daemon {
+        daemonize()?;
+    }
+    let _pidfile = cli.pidfile.clone().map(PidFile::create).transpose()?;
+    eprintln!(
+        "Toy DNS server will now attempt to listen on {}",
+        cli.listen.join(", ")
+    );
+    let (reloads, configs) = watch::channel(Arc::new(zone_config));
+    tokio::runtime::Runtime::new()?.block_on(async {
+        tokio::select! {
+            result = serve_reloading(configs, &cli.listen) => result,
+            result = reload_on_hangup(&cli, &reloads) => result,
+            result = shutdown_signal() => {
+                eprintln!("Shutting down");
+                result
+            }
+        }
+    })?;
     Ok(())
 }
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+/// A set of domain names keyed label by label from the right, so that
+/// a lookup takes one step per label of the name looked up, however many
+/// names are in the set. Besides names themselves it holds wildcards
+/// like "*.example.com", matching every name below example.com but not
+/// example.com itself. Names are expected in canonical form.
+#[derive(Debug, Clone, Default)]
+pub struct NameTrie {
+    root: Node,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    children: HashMap<Box<str>, Node>,
+    /// The name ending here is in the set.
+    exact: bool,
+    /// Every name below the one ending here is, as a wildcard put it.
+    subtree: bool,
+}
+
+/// Example: "www.example.com" -> "com", "example", "www"
+fn labels_from_right(name: &str) -> impl Iterator<Item = &str> {
+    name.rsplit('.').filter(|label| !label.is_empty())
+}
+
+impl NameTrie {
+    /// Adds a name, or a wildcard if it starts with "*.".
+    /// False if it was there already.
+    pub fn insert(&mut self, name: &str) -> bool {
+        let (name, subtree) = match name.strip_prefix("*.") {
+            Some(parent) => (parent, true),
+            None => (name, false),
+        };
+        let mut node = &mut self.root;
+        for label in labels_from_right(name) {
+            node = node.children.entry(label.into()).or_default();
+        }
+        let flag = if subtree { &mut node.subtree } else { &mut node.exact };
+        let added = !*flag;
+        *flag = true;
+        self.len += usize::from(added);
+        added
+    }
+
+    /// Whether the set has `name`, or a wildcard above it.
+    #[must_use]
+    pub fn matches(&self, name: &str) -> bool {
+        let mut node = &self.root;
+        let mut labels = labels_from_right(name).peekable();
+        while let Some(label) = labels.next() {
+            match node.children.get(label) {
+                Some(child) => node = child,
+                None => return false,
+            }
+            if node.subtree && labels.peek().is_some() {
+                return true;
+            }
+        }
+        node.exact
+    }
+
+    /// Adds everything in `other`.
+    pub fn extend(&mut self, other: NameTrie) {
+        self.len += merge(&mut self.root, other.root);
+    }
+
+    /// Names and wildcards alike.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Merges `from` into `into`, returning how many entries were new.
+fn merge(into: &mut Node, from: Node) -> usize {
+    let mut added = usize::from(from.exact && !into.exact)
+        + usize::from(from.subtree && !into.subtree);
+    into.exact |= from.exact;
+    into.subtree |= from.subtree;
+    for (label, child) in from.children {
+        added += merge(into.children.entry(label).or_default(), child);
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_and_suffix_matches() {
+        let mut trie = NameTrie::default();
+        assert!(trie.insert("ads.example.com"));
+        assert!(trie.insert("*.tracker.example"));
+        assert!(!trie.insert("ads.example.com"));
+        assert_eq!(trie.len(), 2);
+
+        // exact hits, and only exact ones
+        assert!(trie.matches("ads.example.com"));
+        assert!(!trie.matches("www.ads.example.com"));
+        assert!(!trie.matches("example.com"));
+
+        // suffix hits anywhere below the wildcard, but not at it
+        assert!(trie.matches("a.tracker.example"));
+        assert!(trie.matches("a.b.tracker.example"));
+        assert!(!trie.matches("tracker.example"));
+
+        // names sharing labels or characters with entries don't match
+        assert!(!trie.matches("ads.example.org"));
+        assert!(!trie.matches("bads.example.com"));
+        assert!(!trie.matches("a.nottracker.example"));
+        assert!(!trie.matches(""));
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut trie = NameTrie::default();
+        trie.insert("example.com");
+        let mut other = NameTrie::default();
+        other.insert("example.com");
+        other.insert("*.example.com");
+        other.insert("example.net");
+        trie.extend(other);
+        assert_eq!(trie.len(), 3);
+        assert!(trie.matches("example.com"));
+        assert!(trie.matches("www.example.com"));
+        assert!(trie.matches("example.net"));
+    }
+}
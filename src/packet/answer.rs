@@ -1,29 +1,155 @@
 use super::dns_name::{parse_dns_name, serialize_dns_name};
-use super::error::ParseError;
+use super::edns::OPT_TYPE;
+use super::error::{ParseError, SerializeError};
 use super::protocol_class::Class;
 use super::record_type::Type;
+use super::svcb::format_svc_param;
 use bytes::{Buf as _, BufMut as _};
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-#[derive(Debug, Clone, PartialEq)]
+/// Ordered by type, then field by field: addresses by their octets,
+/// names and strings lexicographically, numbers by value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum RData {
     A(Ipv4Addr),
     AAAA(Ipv6Addr),
     NS(String),
     CNAME(String),
+    DNAME(String),
+    PTR(String),
+    SOA {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    TXT(Vec<String>),
+    HINFO {
+        cpu: String,
+        os: String,
+    },
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    /// SVCB and HTTPS. Priority 0 is alias mode, pointing at `target`.
+    /// `params` are (SvcParamKey, wire value) pairs in ascending key order.
+    Svcb {
+        priority: u16,
+        target: String,
+        params: Vec<(u16, Vec<u8>)>,
+    },
     Other(Vec<u8>),
 }
 
 impl RData {
-    #[must_use]
-    pub fn serialize(&self) -> Vec<u8> {
-        match self {
+    pub fn try_serialize(&self) -> Result<Vec<u8>, SerializeError> {
+        Ok(match self {
             RData::A(ip) => Vec::from(ip.octets()),
             RData::AAAA(ip) => Vec::from(ip.octets()),
-            RData::NS(name) | RData::CNAME(name) => serialize_dns_name(name),
+            RData::NS(name)
+            | RData::CNAME(name)
+            | RData::DNAME(name)
+            | RData::PTR(name) => serialize_dns_name(name)?,
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                let mut buf = serialize_dns_name(mname)?;
+                buf.put_slice(&serialize_dns_name(rname)?);
+                buf.put_u32(*serial);
+                buf.put_u32(*refresh);
+                buf.put_u32(*retry);
+                buf.put_u32(*expire);
+                buf.put_u32(*minimum);
+                buf
+            }
+            RData::MX { preference, exchange } => {
+                let mut buf = Vec::new();
+                buf.put_u16(*preference);
+                buf.put_slice(&serialize_dns_name(exchange)?);
+                buf
+            }
+            RData::TXT(strings) => {
+                let mut buf = Vec::new();
+                for string in strings {
+                    put_character_string(Type::TXT, string, &mut buf)?;
+                }
+                buf
+            }
+            RData::HINFO { cpu, os } => {
+                let mut buf = Vec::new();
+                put_character_string(Type::HINFO, cpu, &mut buf)?;
+                put_character_string(Type::HINFO, os, &mut buf)?;
+                buf
+            }
+            RData::DS { key_tag, algorithm, digest_type, digest } => {
+                let mut buf = Vec::new();
+                buf.put_u16(*key_tag);
+                buf.put_u8(*algorithm);
+                buf.put_u8(*digest_type);
+                buf.put_slice(digest);
+                buf
+            }
+            RData::Svcb { priority, target, params } => {
+                let mut buf = Vec::new();
+                buf.put_u16(*priority);
+                buf.put_slice(&serialize_dns_name(target)?);
+                for (key, value) in params {
+                    let len = u16::try_from(value.len()).map_err(|_| {
+                        SerializeError::new(format!(
+                            "SvcParam {} of {} octets is longer than 65535",
+                            key,
+                            value.len()
+                        ))
+                    })?;
+                    buf.put_u16(*key);
+                    buf.put_u16(len);
+                    buf.put_slice(value);
+                }
+                buf
+            }
             RData::Other(data) => data.clone(),
-        }
+        })
     }
+
+    /// Like `try_serialize`, panicking on data that doesn't fit.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        self.try_serialize().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// A length octet followed by at most 255 octets of text.
+fn put_character_string(
+    rtype: Type,
+    string: &str,
+    buf: &mut Vec<u8>,
+) -> Result<(), SerializeError> {
+    let len = u8::try_from(string.len()).map_err(|_| {
+        SerializeError::new(format!(
+            "{} string of {} octets is longer than 255",
+            rtype,
+            string.len()
+        ))
+    })?;
+    buf.put_u8(len);
+    buf.put_slice(string.as_bytes());
+    Ok(())
 }
 
 impl std::fmt::Display for RData {
@@ -33,6 +159,41 @@ impl std::fmt::Display for RData {
             RData::AAAA(ip) => write!(f, "{}", ip),
             RData::NS(name) => write!(f, "{}", name),
             RData::CNAME(name) => write!(f, "{}", name),
+            RData::DNAME(name) => write!(f, "{}", name),
+            RData::PTR(name) => write!(f, "{}", name),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RData::MX { preference, exchange } => {
+                write!(f, "{} {}", preference, exchange)
+            }
+            RData::TXT(strings) => {
+                let quoted: Vec<String> =
+                    strings.iter().map(|s| format!("{:?}", s)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RData::HINFO { cpu, os } => write!(f, "{:?} {:?}", cpu, os),
+            RData::DS { key_tag, algorithm, digest_type, digest } => {
+                write!(f, "{} {} {} ", key_tag, algorithm, digest_type)?;
+                digest.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+            }
+            RData::Svcb { priority, target, params } => {
+                write!(f, "{} {}", priority, target)?;
+                for (key, value) in params {
+                    write!(f, " {}", format_svc_param(*key, value))?;
+                }
+                Ok(())
+            }
             RData::Other(data) => write!(f, "{:x?}", data),
         }
     }
@@ -57,6 +218,40 @@ impl std::fmt::Display for DnsAnswer {
     }
 }
 
+/// A name inside RDATA, which has to end before the RDATA does.
+fn parse_rdata_name(
+    rtype: Type,
+    data: &mut &[u8],
+) -> Result<String, ParseError> {
+    parse_dns_name(data).map_err(|e| {
+        ParseError::new(format!("Name in {} RDATA overruns it: {}", rtype, e))
+    })
+}
+
+/// A length octet and that much UTF-8, which has to end before the RDATA.
+fn parse_character_string(
+    rtype: Type,
+    data: &mut &[u8],
+) -> Result<String, ParseError> {
+    if !data.has_remaining() {
+        return Err(ParseError::new(format!("{} RDATA ends early", rtype)));
+    }
+    let len = data.get_u8() as usize;
+    if data.remaining() < len {
+        return Err(ParseError::new(format!(
+            "{} string length {} exceeds remaining RDATA {}",
+            rtype,
+            len,
+            data.remaining()
+        )));
+    }
+    let string = String::from_utf8(data[..len].to_vec()).map_err(|e| {
+        ParseError::new(format!("Invalid UTF-8 in {}: {}", rtype, e))
+    })?;
+    data.advance(len);
+    Ok(string)
+}
+
 fn parse_rdata(
     rtype: Type,
     rdlength: u16,
@@ -69,6 +264,10 @@ fn parse_rdata(
             rdlength
         )));
     }
+    // nothing may be read past RDLENGTH, names included
+    let (mut data, rest) = buf.split_at(rdlength as usize);
+    *buf = rest;
+    let buf = &mut data;
 
     match rtype {
         Type::A => {
@@ -95,8 +294,119 @@ fn parse_rdata(
             buf.copy_to_slice(&mut octets);
             Ok(RData::AAAA(Ipv6Addr::from(octets)))
         }
-        Type::NS => Ok(RData::NS(parse_dns_name(buf)?)),
-        Type::CNAME => Ok(RData::CNAME(parse_dns_name(buf)?)),
+        Type::NS | Type::CNAME | Type::DNAME | Type::PTR => {
+            let name = parse_rdata_name(rtype, buf)?;
+            if buf.has_remaining() {
+                return Err(ParseError::new(format!(
+                    "{} RDATA has {} bytes after the name",
+                    rtype,
+                    buf.remaining()
+                )));
+            }
+            Ok(match rtype {
+                Type::NS => RData::NS(name),
+                Type::CNAME => RData::CNAME(name),
+                Type::PTR => RData::PTR(name),
+                _ => RData::DNAME(name),
+            })
+        }
+        Type::SOA => {
+            let mname = parse_rdata_name(rtype, buf)?;
+            let rname = parse_rdata_name(rtype, buf)?;
+            if buf.remaining() < 20 {
+                return Err(ParseError::new(format!(
+                    "Not enough bytes for SOA counters: {} < 20",
+                    buf.remaining()
+                )));
+            }
+            Ok(RData::SOA {
+                mname,
+                rname,
+                serial: buf.get_u32(),
+                refresh: buf.get_u32(),
+                retry: buf.get_u32(),
+                expire: buf.get_u32(),
+                minimum: buf.get_u32(),
+            })
+        }
+        Type::MX => {
+            if rdlength < 3 {
+                return Err(ParseError::new(format!(
+                    "Invalid MX record length: {}",
+                    rdlength
+                )));
+            }
+            let preference = buf.get_u16();
+            let exchange = parse_rdata_name(rtype, buf)?;
+            Ok(RData::MX { preference, exchange })
+        }
+        Type::TXT => {
+            let mut strings = Vec::new();
+            while buf.has_remaining() {
+                strings.push(parse_character_string(rtype, buf)?);
+            }
+            Ok(RData::TXT(strings))
+        }
+        Type::HINFO => {
+            let cpu = parse_character_string(rtype, buf)?;
+            let os = parse_character_string(rtype, buf)?;
+            if buf.has_remaining() {
+                return Err(ParseError::new(format!(
+                    "HINFO RDATA has {} bytes after the strings",
+                    buf.remaining()
+                )));
+            }
+            Ok(RData::HINFO { cpu, os })
+        }
+        Type::DS => {
+            if rdlength < 4 {
+                return Err(ParseError::new(format!(
+                    "Invalid DS record length: {}",
+                    rdlength
+                )));
+            }
+            let key_tag = buf.get_u16();
+            let algorithm = buf.get_u8();
+            let digest_type = buf.get_u8();
+            let mut digest = vec![0u8; rdlength as usize - 4];
+            buf.copy_to_slice(&mut digest);
+            Ok(RData::DS { key_tag, algorithm, digest_type, digest })
+        }
+        Type::SVCB | Type::HTTPS => {
+            let data = buf;
+            if data.remaining() < 3 {
+                return Err(ParseError::new(format!(
+                    "Invalid SVCB record length: {}",
+                    rdlength
+                )));
+            }
+            let priority = data.get_u16();
+            let mut target = parse_rdata_name(rtype, data)?;
+            if target.is_empty() {
+                target = ".".to_string(); // as in the presentation format
+            }
+            let mut params = Vec::new();
+            while data.has_remaining() {
+                if data.remaining() < 4 {
+                    return Err(ParseError::new(format!(
+                        "Truncated SvcParam header: {} < 4",
+                        data.remaining()
+                    )));
+                }
+                let key = data.get_u16();
+                let len = data.get_u16() as usize;
+                if data.remaining() < len {
+                    return Err(ParseError::new(format!(
+                        "SvcParam length {} exceeds remaining RDATA {}",
+                        len,
+                        data.remaining()
+                    )));
+                }
+                params.push((key, data[..len].to_vec()));
+                data.advance(len);
+            }
+            Ok(RData::Svcb { priority, target, params })
+        }
         Type::Other(_) => {
             let mut data = vec![0u8; rdlength as usize];
             buf.copy_to_slice(&mut data);
@@ -106,20 +416,39 @@ fn parse_rdata(
 }
 
 impl DnsAnswer {
-    #[must_use]
-    pub fn serialize(&self) -> Vec<u8> {
-        let rdata_bytes = self.rdata.serialize();
+    /// RDLENGTH is counted off the RDATA as emitted, never estimated from
+    /// the names in it, so it stays right once names get compressed.
+    pub fn try_serialize(&self) -> Result<Vec<u8>, SerializeError> {
+        let rdata_bytes = self.rdata.try_serialize()?;
+        let rdlength = u16::try_from(rdata_bytes.len()).map_err(|_| {
+            SerializeError::new(format!(
+                "RDATA of {} octets is longer than 65535",
+                rdata_bytes.len()
+            ))
+        })?;
         let mut buf = Vec::with_capacity(
             1 + self.name.len() + 2 * 3 + 4 + rdata_bytes.len(),
         );
-        buf.put_slice(&serialize_dns_name(&self.name));
+        buf.put_slice(&serialize_dns_name(&self.name)?);
         buf.put_u16(self.rtype.into());
         buf.put_u16(self.rclass.into());
         buf.put_u32(self.ttl);
-        buf.put_u16(rdata_bytes.len() as u16);
+        buf.put_u16(rdlength);
         buf.put_slice(&rdata_bytes);
-        buf
+        Ok(buf)
     }
+
+    /// Like `try_serialize`, panicking on records that don't fit.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        self.try_serialize().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// TTLs with the top bit set are to be treated as zero (RFC 2181 8).
+#[must_use]
+pub fn clamp_ttl(ttl: u32) -> u32 {
+    if ttl > i32::MAX as u32 { 0 } else { ttl }
 }
 
 pub fn parse_dns_answer(buf: &mut &[u8]) -> Result<DnsAnswer, ParseError> {
@@ -134,7 +463,10 @@ pub fn parse_dns_answer(buf: &mut &[u8]) -> Result<DnsAnswer, ParseError> {
 
     let rtype = Type::parse(buf.get_u16());
     let rclass = Class::parse(buf.get_u16());
-    let ttl = buf.get_u32();
+    let ttl = match rtype {
+        Type::Other(OPT_TYPE) => buf.get_u32(), // EDNS fields, not a TTL
+        _ => clamp_ttl(buf.get_u32()),
+    };
     let rdlength = buf.get_u16();
 
     let rdata = parse_rdata(rtype, rdlength, buf)?;
@@ -146,6 +478,20 @@ pub fn parse_dns_answer(buf: &mut &[u8]) -> Result<DnsAnswer, ParseError> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_ttl_with_top_bit_is_zero() {
+        let mut buf: &[u8] = b"\x00\x00\x01\x00\x01\x80\x00\x00\x00\
+                               \x00\x04\xc0\x00\x02\x01";
+        assert_eq!(parse_dns_answer(&mut buf).unwrap().ttl, 0);
+        let mut buf: &[u8] = b"\x00\x00\x01\x00\x01\x7f\xff\xff\xff\
+                               \x00\x04\xc0\x00\x02\x01";
+        assert_eq!(parse_dns_answer(&mut buf).unwrap().ttl, 0x7fff_ffff);
+
+        // OPT keeps the extended RCODE and flags in there
+        let mut buf: &[u8] = b"\x00\x00\x29\x10\x00\xff\x00\x80\x00\x00\x00";
+        assert_eq!(parse_dns_answer(&mut buf).unwrap().ttl, 0xff00_8000);
+    }
+
     #[test]
     fn test_parse_a_record() {
         let mut buf: &[u8] = b"\x07example\x03com\x00\x00\x01\x00\x01\x00\x00\
@@ -158,6 +504,241 @@ mod tests {
         assert_eq!(answer.rdata, RData::A(Ipv4Addr::new(93, 184, 216, 34)));
     }
 
+    #[test]
+    fn test_ns_name_overrunning_rdlength() {
+        // RDLENGTH 3 cuts "\x02ns\x00" short, and the terminator that
+        // follows belongs to the next record
+        let mut buf: &[u8] = b"\x00\x00\x02\x00\x01\x00\x00\x00\x3c\x00\x03\
+                               \x02ns\x00\x00\x00\x01\x00\x01";
+        let err = parse_dns_answer(&mut buf).unwrap_err();
+        assert!(err.to_string().starts_with("Name in NS RDATA overruns it"));
+
+        let mut buf: &[u8] = b"\x00\x00\x02\x00\x01\x00\x00\x00\x3c\x00\x05\
+                               \x02ns\x00\x00";
+        let err = parse_dns_answer(&mut buf).unwrap_err();
+        assert_eq!(err.to_string(), "NS RDATA has 1 bytes after the name");
+
+        let mut buf: &[u8] = b"\x00\x00\x02\x00\x01\x00\x00\x00\x3c\x00\x04\
+                               \x02ns\x00";
+        let answer = parse_dns_answer(&mut buf).unwrap();
+        assert_eq!(answer.rdata, RData::NS("ns".to_string()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_compressed_ns_rdata_is_bounded_by_rdlength() {
+        // RDLENGTH 2 for a pointer to offset 12, shorter than the name it
+        // stands for; pointers aren't followed, but what follows the RDATA
+        // is left for the next record all the same
+        let mut buf: &[u8] = b"\x00\x00\x02\x00\x01\x00\x00\x00\x3c\x00\x02\
+                               \xc0\x0c\x00\x00\x01\x00\x01";
+        let err = parse_dns_answer(&mut buf).unwrap_err();
+        assert!(err.to_string().ends_with("compression not supported"));
+        assert_eq!(buf, b"\x00\x00\x01\x00\x01");
+
+        let answer = DnsAnswer {
+            name: String::new(),
+            rtype: Type::NS,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::NS("ns.example.com".to_string()),
+        };
+        let bytes = answer.serialize();
+        let rdlength = u16::from_be_bytes([bytes[9], bytes[10]]);
+        assert_eq!(usize::from(rdlength), bytes.len() - 11);
+    }
+
+    #[test]
+    fn test_txt_record_roundtrip() {
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::TXT,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::TXT(vec!["hello".to_string(), "world".to_string()]),
+        };
+        let buf = answer.serialize();
+        assert_eq!(
+            buf,
+            b"\x07example\x03com\x00\x00\x10\x00\x01\x00\x00\x00\x3c\x00\
+              \x0c\x05hello\x05world"
+        );
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(answer.rdata.to_string(), "\"hello\" \"world\"");
+    }
+
+    #[test]
+    fn test_serialize_oversized_records() {
+        let answer = |rdata| DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::Other(65280),
+            rclass: Class::IN,
+            ttl: 60,
+            rdata,
+        };
+        let fits = answer(RData::Other(vec![0; 65535]));
+        assert_eq!(fits.try_serialize().unwrap().len(), 13 + 10 + 65535);
+        let err =
+            answer(RData::Other(vec![0; 65536])).try_serialize().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "RDATA of 65536 octets is longer than 65535"
+        );
+
+        assert!(
+            answer(RData::TXT(vec!["a".repeat(256)])).try_serialize().is_err()
+        );
+        assert!(
+            answer(RData::TXT(vec!["a".repeat(255)])).try_serialize().is_ok()
+        );
+        assert!(answer(RData::CNAME("a".repeat(64))).try_serialize().is_err());
+
+        let mut long_name = fits.clone();
+        long_name.name = format!("{}.com", "a".repeat(64));
+        assert!(long_name.try_serialize().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "longer than 65535")]
+    fn test_serialize_panics_on_oversized_rdata() {
+        let _ = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::Other(65280),
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::Other(vec![0; 65536]),
+        }
+        .serialize();
+    }
+
+    #[test]
+    fn test_soa_record_roundtrip() {
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::SOA,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::SOA {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 300,
+            },
+        };
+        let buf = answer.serialize();
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(
+            answer.rdata.to_string(),
+            "ns1.example.com hostmaster.example.com 2024010101 7200 3600 \
+             1209600 300"
+        );
+    }
+
+    #[test]
+    fn test_mx_record_roundtrip() {
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::MX,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::MX {
+                preference: 10,
+                exchange: "mail.example.com".to_string(),
+            },
+        };
+        let buf = answer.serialize();
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(answer.rdata.to_string(), "10 mail.example.com");
+    }
+
+    #[test]
+    fn test_hinfo_record_roundtrip() {
+        let answer = DnsAnswer {
+            name: "host.example.com".to_string(),
+            rtype: Type::HINFO,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::HINFO {
+                cpu: "RISC-V".to_string(),
+                os: "Linux".to_string(),
+            },
+        };
+        let buf = answer.serialize();
+        assert!(buf.ends_with(b"\x06RISC-V\x05Linux"));
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(answer.rdata.to_string(), "\"RISC-V\" \"Linux\"");
+
+        // both strings are required, nothing may follow them
+        for rdata in [&b"\x06RISC-V"[..], b"\x01a\x01b\x00"] {
+            let mut record = b"\x00\x00\x0d\x00\x01\x00\x00\x00\x3c".to_vec();
+            record.extend((rdata.len() as u16).to_be_bytes());
+            record.extend(rdata);
+            assert!(parse_dns_answer(&mut record.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_ds_record_roundtrip() {
+        let answer = DnsAnswer {
+            name: "sub.example.com".to_string(),
+            rtype: Type::DS,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::DS {
+                key_tag: 60485,
+                algorithm: 5,
+                digest_type: 1,
+                digest: vec![0x2b, 0xb1, 0x83, 0xaf],
+            },
+        };
+        let buf = answer.serialize();
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(answer.rdata.to_string(), "60485 5 1 2BB183AF");
+    }
+
+    #[test]
+    fn test_svcb_record_roundtrip() {
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::HTTPS,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::Svcb {
+                priority: 1,
+                target: "svc.example.com".to_string(),
+                params: vec![(3, vec![0x01, 0xbb])],
+            },
+        };
+        let buf = answer.serialize();
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(answer.rdata.to_string(), "1 svc.example.com port=443");
+    }
+
+    #[test]
+    fn test_https_record_minimal_roundtrip() {
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::HTTPS,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::Svcb {
+                priority: 1,
+                target: ".".to_string(),
+                params: vec![(1, b"\x02h2\x02h3".to_vec())],
+            },
+        };
+        let buf = answer.serialize();
+        assert_eq!(
+            buf[buf.len() - 15..],
+            *b"\x00\x0d\x00\x01\x00\x00\x01\x00\x06\x02h2\x02h3"
+        );
+        assert_eq!(parse_dns_answer(&mut buf.as_slice()).unwrap(), answer);
+        assert_eq!(answer.rdata.to_string(), "1 . alpn=h2,h3");
+    }
+
     #[test]
     fn test_serialize_a_record() {
         let answer = DnsAnswer {
@@ -0,0 +1,110 @@
+use super::dns_name::canonicalize_name;
+use std::cmp::Ordering;
+
+/// A name in the form names are compared in, see `canonicalize_name`,
+/// ordered the way RFC 4034 6.1 orders names: label by label from the
+/// right, so a zone's names sort together, right after its apex.
+/// Example: "example.com" < "a.example.com" < "z.example.com" < "a.org"
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CanonicalName(String);
+
+impl From<&str> for CanonicalName {
+    fn from(name: &str) -> Self {
+        CanonicalName(canonicalize_name(name))
+    }
+}
+
+impl CanonicalName {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Example: "www.example.com" -> "com", "example", "www"
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.0.rsplit('.').filter(|label| !label.is_empty())
+    }
+
+    /// 0 for the root, 1 for a TLD.
+    #[must_use]
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// Whether this is `ancestor` or a name below it.
+    /// Example: ("host.example.com", "example.com") -> true
+    /// Example: ("host.badexample.com", "example.com") -> false
+    #[must_use]
+    pub fn is_within(&self, ancestor: &CanonicalName) -> bool {
+        let mut labels = self.labels();
+        ancestor.labels().all(|label| labels.next() == Some(label))
+    }
+}
+
+impl Ord for CanonicalName {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.labels().cmp(other.labels())
+    }
+}
+
+impl PartialOrd for CanonicalName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for CanonicalName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordering() {
+        let name = CanonicalName::from;
+        assert_eq!(name("WWW.Example.COM."), name("www.example.com"));
+        assert_eq!(name("Example.COM.").as_str(), "example.com");
+
+        // the example of RFC 4034 6.1, lowercase and without escapes
+        let sorted = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "z.a.example",
+            "zabc.a.example",
+            "z.example",
+            "*.z.example",
+        ];
+        let mut names: Vec<CanonicalName> =
+            sorted.iter().rev().map(|s| name(s)).collect();
+        names.sort();
+        let names: Vec<&str> =
+            names.iter().map(CanonicalName::as_str).collect();
+        assert_eq!(names, sorted);
+        assert!(name("") < name("com"));
+        assert!(name("z.com") < name("a.org"));
+        assert!(name("example.com") < name("a.example.com"));
+    }
+
+    #[test]
+    fn test_suffix_relationships() {
+        let name = CanonicalName::from;
+        let apex = name("example.com");
+        assert!(name("example.com").is_within(&apex));
+        assert!(name("a.b.Example.com.").is_within(&apex));
+        assert!(!name("badexample.com").is_within(&apex));
+        assert!(!name("com").is_within(&apex));
+        assert!(!name("example.org").is_within(&apex));
+        // everything is within the root
+        assert!(apex.is_within(&name(".")));
+        assert_eq!(name(".").label_count(), 0);
+        assert_eq!(
+            name("www.example.com").labels().collect::<Vec<_>>(),
+            ["com", "example", "www"]
+        );
+    }
+}
@@ -1,21 +1,91 @@
-use super::error::ParseError;
+use super::error::{ParseError, SerializeError};
 use bytes::{Buf as _, BufMut as _};
 
 /// Example: "example.com" -> \x07example\x03com\x00
-#[must_use]
-pub fn serialize_dns_name(name: &str) -> Vec<u8> {
+/// The root is "" or ".", and a trailing dot is optional.
+/// Only the label and name lengths are checked, so anything
+/// not from the wire should go through `validate_name` first.
+pub fn serialize_dns_name(name: &str) -> Result<Vec<u8>, SerializeError> {
     let mut buf = Vec::new();
-    for label in name.split('.') {
-        buf.put_u8(label.len() as u8);
-        buf.put_slice(label.as_bytes());
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.len() > 63 {
+                return Err(SerializeError::new(format!(
+                    "Label '{}' is longer than 63 octets",
+                    label
+                )));
+            }
+            buf.put_u8(label.len() as u8);
+            buf.put_slice(label.as_bytes());
+        }
     }
     buf.put_u8(0);
-    buf
+    if buf.len() > 255 {
+        return Err(SerializeError::new(format!(
+            "Name '{}' is longer than 255 octets",
+            name
+        )));
+    }
+    Ok(buf)
+}
+
+/// The form names are compared in: lowercase, without a trailing dot.
+/// Names are case-insensitive (RFC 4343), so zones, cache and blocklist
+/// all match on this, while replies keep the case the client sent, which
+/// resolvers randomizing it (DNS 0x20) check.
+/// Example: "ExAmPle.CoM." -> "example.com"
+#[must_use]
+pub fn canonicalize_name(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+}
+
+/// Checks that `name` can be encoded: no empty labels other than the root,
+/// labels of letters, digits, '-' and '_' (or a lone '*' for a wildcard),
+/// up to 63 octets each and 255 octets in all.
+pub fn validate_name(name: &str) -> Result<(), ParseError> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        return Ok(());
+    }
+    if name.len() + 2 > 255 {
+        return Err(ParseError::new(format!(
+            "Name '{}' is longer than 255 octets",
+            name
+        )));
+    }
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err(ParseError::new(format!(
+                "Name '{}' has an empty label",
+                name
+            )));
+        }
+        if label.len() > 63 {
+            return Err(ParseError::new(format!(
+                "Label '{}' is longer than 63 octets",
+                label
+            )));
+        }
+        let legal = label == "*"
+            || label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !legal {
+            return Err(ParseError::new(format!(
+                "Label '{}' has characters other than letters, digits, '-' \
+                 and '_'",
+                label
+            )));
+        }
+    }
+    Ok(())
 }
 
 /// Example: \x07example\x03com\x00 -> "example.com"
+/// The root comes out as "".
 pub fn parse_dns_name(buf: &mut &[u8]) -> Result<String, ParseError> {
-    let mut labels = Vec::new();
+    let mut name = String::new();
 
     loop {
         if buf.is_empty() {
@@ -45,17 +115,17 @@ pub fn parse_dns_name(buf: &mut &[u8]) -> Result<String, ParseError> {
             )));
         }
 
-        let mut label = vec![0; len as usize];
-        buf.copy_to_slice(&mut label);
-
-        let label_str = String::from_utf8(label).map_err(|e| {
+        let label = std::str::from_utf8(&buf[..len as usize]).map_err(|e| {
             ParseError::new(format!("Invalid UTF-8 in DNS label: {}", e))
         })?;
-
-        labels.push(label_str);
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(label);
+        buf.advance(len as usize);
     }
 
-    Ok(labels.join("."))
+    Ok(name)
 }
 
 #[cfg(test)]
@@ -68,9 +138,58 @@ mod tests {
         assert_eq!(parse_dns_name(&mut buf).unwrap(), "example.com");
     }
 
+    #[test]
+    fn test_canonicalize_name() {
+        assert_eq!(canonicalize_name("ExAmPle.CoM."), "example.com");
+        assert_eq!(canonicalize_name("example.com"), "example.com");
+        assert_eq!(canonicalize_name("."), "");
+    }
+
     #[test]
     fn test_serialize_dns_name() {
-        let buf = serialize_dns_name("example.com");
+        let buf = serialize_dns_name("example.com").unwrap();
         assert_eq!(buf, b"\x07example\x03com\x00");
     }
+
+    #[test]
+    fn test_serialize_oversized_names() {
+        let long_label = "a".repeat(64);
+        assert!(serialize_dns_name(&long_label).is_err());
+        assert!(serialize_dns_name(&"a".repeat(63)).is_ok());
+        assert!(serialize_dns_name(&["a"; 128].join(".")).is_err());
+        assert!(serialize_dns_name(&["a"; 127].join(".")).is_ok());
+    }
+
+    #[test]
+    fn test_validate_name() {
+        for name in
+            ["", ".", "com", "example.com.", "_dmarc.example.com", "*.a"]
+        {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["foo..bar", ".com", "a b.com", "ex@mple.com", "..", "a.*b"]
+        {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+        assert!(validate_name(&"a".repeat(64)).is_err());
+        assert!(validate_name(&["a"; 128].join(".")).is_err());
+    }
+
+    #[test]
+    fn test_root_name_roundtrip() {
+        let mut buf: &[u8] = b"\x00";
+        let name = parse_dns_name(&mut buf).unwrap();
+        assert_eq!(name, "");
+        assert_eq!(serialize_dns_name(&name).unwrap(), b"\x00");
+        assert_eq!(serialize_dns_name(".").unwrap(), b"\x00");
+    }
+
+    #[test]
+    fn test_single_label_roundtrip() {
+        let mut buf: &[u8] = b"\x03com\x00";
+        let name = parse_dns_name(&mut buf).unwrap();
+        assert_eq!(name, "com");
+        assert_eq!(serialize_dns_name(&name).unwrap(), b"\x03com\x00");
+        assert_eq!(serialize_dns_name("com.").unwrap(), b"\x03com\x00");
+    }
 }
@@ -0,0 +1,516 @@
+use super::answer::{DnsAnswer, RData};
+use super::error::{ParseError, SerializeError};
+use super::record_type::Type;
+use bytes::{Buf as _, BufMut as _};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+pub const OPT_TYPE: u16 = 41;
+
+/// EDNS Client Subnet (RFC 7871).
+pub const CLIENT_SUBNET_OPTION: u16 = 8;
+
+/// DNS Cookies (RFC 7873), a client cookie alone or with a server cookie.
+pub const COOKIE_OPTION: u16 = 10;
+
+/// edns-tcp-keepalive (RFC 7828), empty in queries and carrying the idle
+/// timeout in units of 100 milliseconds in replies.
+pub const TCP_KEEPALIVE_OPTION: u16 = 11;
+
+/// Extended DNS Error (RFC 8914).
+pub const EXTENDED_ERROR_OPTION: u16 = 15;
+
+/// The DNSSEC OK bit, the only EDNS flag defined so far (RFC 3225).
+pub const DO_FLAG: u16 = 0x8000;
+
+/// The OPT pseudo-record from the additional section (RFC 6891).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOpt {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub flags: u16,
+    pub options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Default for EdnsOpt {
+    fn default() -> Self {
+        Self {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl EdnsOpt {
+    #[must_use]
+    pub fn dnssec_ok(&self) -> bool {
+        self.flags & DO_FLAG != 0
+    }
+
+    /// Flags without a defined meaning, to be ignored unless strict.
+    #[must_use]
+    pub fn reserved_flags(&self) -> u16 {
+        self.flags & !DO_FLAG
+    }
+
+    /// The client subnet option if there's one, an error if it's malformed.
+    pub fn client_subnet(&self) -> Result<Option<ClientSubnet>, ParseError> {
+        self.options
+            .iter()
+            .find(|(code, _)| *code == CLIENT_SUBNET_OPTION)
+            .map(|(_, data)| ClientSubnet::parse(data))
+            .transpose()
+    }
+
+    /// The cookie option if there's one, an error if it's malformed.
+    pub fn cookie(&self) -> Result<Option<Cookie>, ParseError> {
+        self.options
+            .iter()
+            .find(|(code, _)| *code == COOKIE_OPTION)
+            .map(|(_, data)| Cookie::parse(data))
+            .transpose()
+    }
+
+    /// Whether the client asks how long idle TCP connections are kept open.
+    #[must_use]
+    pub fn requests_keepalive(&self) -> bool {
+        self.options.iter().any(|(code, _)| *code == TCP_KEEPALIVE_OPTION)
+    }
+
+    /// The idle timeout a server announced, if it did.
+    #[must_use]
+    pub fn keepalive_timeout(&self) -> Option<Duration> {
+        let (_, data) = self
+            .options
+            .iter()
+            .find(|(code, _)| *code == TCP_KEEPALIVE_OPTION)?;
+        let units = u16::from_be_bytes(data.as_slice().try_into().ok()?);
+        Some(Duration::from_millis(u64::from(units) * 100))
+    }
+
+    /// The extended error if there's one, an error if it's malformed.
+    pub fn extended_error(&self) -> Result<Option<ExtendedError>, ParseError> {
+        self.options
+            .iter()
+            .find(|(code, _)| *code == EXTENDED_ERROR_OPTION)
+            .map(|(_, data)| ExtendedError::parse(data))
+            .transpose()
+    }
+
+    /// The OPT record carries its fields in the CLASS and TTL of a record.
+    pub fn from_record(record: &DnsAnswer) -> Result<EdnsOpt, ParseError> {
+        if !record.name.is_empty() {
+            return Err(ParseError::new(format!(
+                "OPT record owner must be the root, not '{}'",
+                record.name
+            )));
+        }
+        let RData::Other(data) = &record.rdata else {
+            return Err(ParseError::new("OPT RDATA not kept raw".to_string()));
+        };
+        let mut data = data.as_slice();
+        let mut options = Vec::new();
+        while data.has_remaining() {
+            if data.remaining() < 4 {
+                return Err(ParseError::new(format!(
+                    "Truncated EDNS option header: {} < 4",
+                    data.remaining()
+                )));
+            }
+            let code = data.get_u16();
+            let len = data.get_u16() as usize;
+            if data.remaining() < len {
+                return Err(ParseError::new(format!(
+                    "EDNS option length {} exceeds remaining RDATA {}",
+                    len,
+                    data.remaining()
+                )));
+            }
+            options.push((code, data[..len].to_vec()));
+            data.advance(len);
+        }
+        let [extended_rcode, version, flags_hi, flags_lo] =
+            record.ttl.to_be_bytes();
+        Ok(EdnsOpt {
+            udp_payload_size: record.rclass.into(),
+            extended_rcode,
+            version,
+            flags: u16::from_be_bytes([flags_hi, flags_lo]),
+            options,
+        })
+    }
+
+    pub fn try_serialize(&self) -> Result<Vec<u8>, SerializeError> {
+        let mut rdata = Vec::new();
+        for (code, value) in &self.options {
+            let len = u16::try_from(value.len()).map_err(|_| {
+                SerializeError::new(format!(
+                    "EDNS option {} of {} octets is longer than 65535",
+                    code,
+                    value.len()
+                ))
+            })?;
+            rdata.put_u16(*code);
+            rdata.put_u16(len);
+            rdata.put_slice(value);
+        }
+        let rdlength = u16::try_from(rdata.len()).map_err(|_| {
+            SerializeError::new(format!(
+                "OPT RDATA of {} octets is longer than 65535",
+                rdata.len()
+            ))
+        })?;
+        let mut buf = Vec::with_capacity(11 + rdata.len());
+        buf.put_u8(0); // root
+        buf.put_u16(OPT_TYPE);
+        buf.put_u16(self.udp_payload_size);
+        buf.put_u8(self.extended_rcode);
+        buf.put_u8(self.version);
+        buf.put_u16(self.flags);
+        buf.put_u16(rdlength);
+        buf.put_slice(&rdata);
+        Ok(buf)
+    }
+
+    /// Like `try_serialize`, panicking on options that don't fit.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        self.try_serialize().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// The keepalive option announcing `timeout`, rounded down to 100 ms
+/// and capped at what the option can carry.
+#[must_use]
+pub fn keepalive_option(timeout: Duration) -> (u16, Vec<u8>) {
+    let units = u16::try_from(timeout.as_millis() / 100).unwrap_or(u16::MAX);
+    (TCP_KEEPALIVE_OPTION, units.to_be_bytes().to_vec())
+}
+
+/// The network a query was sent on behalf of, as told by a resolver.
+/// In replies, `scope_prefix` tells how much of it the answer depended on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSubnet {
+    pub address: IpAddr,
+    pub source_prefix: u8,
+    pub scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    const FAMILY_IPV4: u16 = 1;
+    const FAMILY_IPV6: u16 = 2;
+
+    /// Parses the option data: family, both prefix lengths and just as many
+    /// address octets as the source prefix needs.
+    pub fn parse(mut data: &[u8]) -> Result<ClientSubnet, ParseError> {
+        if data.remaining() < 4 {
+            return Err(ParseError::new(format!(
+                "Truncated client subnet option: {} < 4",
+                data.remaining()
+            )));
+        }
+        let family = data.get_u16();
+        let source_prefix = data.get_u8();
+        let scope_prefix = data.get_u8();
+        let max_prefix = match family {
+            Self::FAMILY_IPV4 => 32,
+            Self::FAMILY_IPV6 => 128,
+            _ => {
+                return Err(ParseError::new(format!(
+                    "Unknown client subnet address family {family}"
+                )));
+            }
+        };
+        if source_prefix > max_prefix || scope_prefix > max_prefix {
+            return Err(ParseError::new(format!(
+                "Client subnet prefix {source_prefix}/{scope_prefix} \
+                 exceeds {max_prefix} bits"
+            )));
+        }
+        if data.remaining() != usize::from(source_prefix.div_ceil(8)) {
+            return Err(ParseError::new(format!(
+                "Client subnet address of {} octets for a /{source_prefix}",
+                data.remaining()
+            )));
+        }
+        let mut octets = [0u8; 16];
+        octets[..data.remaining()].copy_from_slice(data);
+        let address = if family == Self::FAMILY_IPV4 {
+            IpAddr::V4(Ipv4Addr::new(
+                octets[0], octets[1], octets[2], octets[3],
+            ))
+        } else {
+            IpAddr::V6(Ipv6Addr::from(octets))
+        };
+        Ok(ClientSubnet { address, source_prefix, scope_prefix })
+    }
+
+    /// The option as it goes into an OPT record.
+    #[must_use]
+    pub fn to_option(&self) -> (u16, Vec<u8>) {
+        let (family, octets) = match self.address {
+            IpAddr::V4(address) => {
+                (Self::FAMILY_IPV4, address.octets().to_vec())
+            }
+            IpAddr::V6(address) => {
+                (Self::FAMILY_IPV6, address.octets().to_vec())
+            }
+        };
+        let mut data = Vec::with_capacity(4 + octets.len());
+        data.put_u16(family);
+        data.put_u8(self.source_prefix);
+        data.put_u8(self.scope_prefix);
+        data.put_slice(&octets[..usize::from(self.source_prefix.div_ceil(8))]);
+        (CLIENT_SUBNET_OPTION, data)
+    }
+}
+
+/// A client cookie, with the server cookie the server gave that client
+/// last time if it has one. `server` is empty in a client's first query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub client: [u8; 8],
+    pub server: Vec<u8>,
+}
+
+impl Cookie {
+    /// Parses the option data: 8 octets of client cookie and either
+    /// nothing or 8 to 32 octets of server cookie.
+    pub fn parse(data: &[u8]) -> Result<Cookie, ParseError> {
+        let len = data.len();
+        if len != 8 && !(16..=40).contains(&len) {
+            return Err(ParseError::new(format!(
+                "Cookie option of {len} octets, not 8 or 16 to 40"
+            )));
+        }
+        let (client, server) = data.split_at(8);
+        Ok(Cookie {
+            client: client.try_into().unwrap(),
+            server: server.to_vec(),
+        })
+    }
+
+    /// The option as it goes into an OPT record.
+    #[must_use]
+    pub fn to_option(&self) -> (u16, Vec<u8>) {
+        (COOKIE_OPTION, [&self.client[..], &self.server].concat())
+    }
+}
+
+/// A machine-readable reason for an error RCODE, with optional text
+/// for humans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    pub info_code: u16,
+    pub text: String,
+}
+
+impl ExtendedError {
+    pub const OTHER: u16 = 0;
+    pub const BLOCKED: u16 = 15;
+    pub const CENSORED: u16 = 16;
+    pub const FILTERED: u16 = 17;
+    pub const PROHIBITED: u16 = 18;
+    pub const NOT_AUTHORITATIVE: u16 = 20;
+    pub const NOT_SUPPORTED: u16 = 21;
+    pub const NO_REACHABLE_AUTHORITY: u16 = 22;
+    pub const NETWORK_ERROR: u16 = 23;
+
+    #[must_use]
+    pub fn new(info_code: u16, text: impl Into<String>) -> Self {
+        Self { info_code, text: text.into() }
+    }
+
+    pub fn parse(mut data: &[u8]) -> Result<ExtendedError, ParseError> {
+        if data.remaining() < 2 {
+            return Err(ParseError::new(format!(
+                "Truncated extended error option: {} < 2",
+                data.remaining()
+            )));
+        }
+        let info_code = data.get_u16();
+        let text = std::str::from_utf8(data).map_err(|e| {
+            ParseError::new(format!("Extended error text isn't UTF-8: {e}"))
+        })?;
+        Ok(ExtendedError { info_code, text: text.to_string() })
+    }
+
+    /// The option as it goes into an OPT record.
+    #[must_use]
+    pub fn to_option(&self) -> (u16, Vec<u8>) {
+        let mut data = Vec::with_capacity(2 + self.text.len());
+        data.put_u16(self.info_code);
+        data.put_slice(self.text.as_bytes());
+        (EXTENDED_ERROR_OPTION, data)
+    }
+}
+
+impl std::fmt::Display for EdnsOpt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "EDNS {{ Version: {}, UDP size: {}, Flags: {:#06x}, Options: {} }}",
+            self.version,
+            self.udp_payload_size,
+            self.flags,
+            self.options.len()
+        )
+    }
+}
+
+/// Splits off the OPT record if it's the last record in `rest`, which holds
+/// the authority and additional sections. Anything unexpected is left be.
+pub fn split_trailing_opt(
+    rest: &[u8],
+    records: usize,
+) -> Option<(&[u8], EdnsOpt)> {
+    let mut buf = rest;
+    let mut last_start = 0;
+    let mut last = None;
+    for _ in 0..records {
+        last_start = rest.len() - buf.len();
+        last = Some(super::answer::parse_dns_answer(&mut buf).ok()?);
+    }
+    let last = last?;
+    if !buf.is_empty() || last.rtype != Type::Other(OPT_TYPE) {
+        return None;
+    }
+    let opt = EdnsOpt::from_record(&last).ok()?;
+    Some((&rest[..last_start], opt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opt_roundtrip() {
+        let opt = EdnsOpt {
+            udp_payload_size: 4096,
+            extended_rcode: 0,
+            version: 0,
+            flags: DO_FLAG | 0x0001,
+            options: vec![(10, vec![1, 2, 3, 4, 5, 6, 7, 8])],
+        };
+        let buf = opt.serialize();
+        assert_eq!(
+            buf,
+            b"\x00\x00\x29\x10\x00\x00\x00\x80\x01\x00\x0c\
+              \x00\x0a\x00\x08\x01\x02\x03\x04\x05\x06\x07\x08"
+        );
+        let (before, parsed) = split_trailing_opt(&buf, 1).unwrap();
+        assert!(before.is_empty());
+        assert_eq!(parsed, opt);
+        assert!(parsed.dnssec_ok());
+        assert_eq!(parsed.reserved_flags(), 0x0001);
+    }
+
+    #[test]
+    fn test_client_subnet_option() {
+        let opt = EdnsOpt {
+            options: vec![(8, b"\x00\x01\x14\x00\xc6\x33\x60".to_vec())],
+            ..EdnsOpt::default()
+        };
+        let subnet = opt.client_subnet().unwrap().unwrap();
+        assert_eq!(
+            subnet,
+            ClientSubnet {
+                address: "198.51.96.0".parse().unwrap(),
+                source_prefix: 20,
+                scope_prefix: 0,
+            }
+        );
+        assert_eq!(subnet.to_option(), opt.options[0]);
+        assert_eq!(EdnsOpt::default().client_subnet().unwrap(), None);
+
+        let subnet = ClientSubnet {
+            address: "2001:db8::".parse().unwrap(),
+            source_prefix: 56,
+            scope_prefix: 48,
+        };
+        let (code, data) = subnet.to_option();
+        assert_eq!(code, CLIENT_SUBNET_OPTION);
+        assert_eq!(data, b"\x00\x02\x38\x30\x20\x01\x0d\xb8\x00\x00\x00");
+        assert_eq!(ClientSubnet::parse(&data).unwrap(), subnet);
+
+        for malformed in [
+            &b"\x00\x01\x18"[..],                    // truncated
+            b"\x00\x03\x00\x00",                     // unknown family
+            b"\x00\x01\x21\x00\x01\x02\x03\x04\x05", // /33
+            b"\x00\x01\x18\x00\x01\x02",             // too few octets
+            b"\x00\x01\x08\x00\x01\x02",             // too many octets
+        ] {
+            assert!(ClientSubnet::parse(malformed).is_err());
+        }
+    }
+
+    #[test]
+    fn test_extended_error_option() {
+        let ede = ExtendedError::new(ExtendedError::PROHIBITED, "go away");
+        let (code, data) = ede.to_option();
+        assert_eq!(code, EXTENDED_ERROR_OPTION);
+        assert_eq!(data, b"\x00\x12go away");
+        let opt = EdnsOpt { options: vec![(code, data)], ..EdnsOpt::default() };
+        assert_eq!(opt.extended_error().unwrap(), Some(ede));
+
+        let bare = ExtendedError::parse(b"\x00\x17").unwrap();
+        assert_eq!(bare, ExtendedError::new(ExtendedError::NETWORK_ERROR, ""));
+        assert!(ExtendedError::parse(b"\x00").is_err());
+        assert!(ExtendedError::parse(b"\x00\x00\xff").is_err());
+    }
+
+    #[test]
+    fn test_cookie_option() {
+        let opt = EdnsOpt {
+            options: vec![(
+                COOKIE_OPTION,
+                b"\x01\x02\x03\x04\x05\x06\x07\x08".to_vec(),
+            )],
+            ..EdnsOpt::default()
+        };
+        let cookie = opt.cookie().unwrap().unwrap();
+        assert_eq!(cookie.client, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(cookie.server.is_empty());
+        assert_eq!(cookie.to_option(), opt.options[0]);
+        assert_eq!(EdnsOpt::default().cookie().unwrap(), None);
+
+        let with_server = Cookie::parse(&[7; 24]).unwrap();
+        assert_eq!(with_server.client, [7; 8]);
+        assert_eq!(with_server.server, vec![7; 16]);
+        for len in [0, 7, 9, 15, 41] {
+            assert!(Cookie::parse(&vec![0; len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_keepalive_option() {
+        let mut edns = EdnsOpt::default();
+        assert!(!edns.requests_keepalive());
+        edns.options.push((TCP_KEEPALIVE_OPTION, Vec::new()));
+        assert!(edns.requests_keepalive());
+        assert_eq!(edns.keepalive_timeout(), None);
+
+        let option = keepalive_option(Duration::from_millis(12_345));
+        assert_eq!(option, (TCP_KEEPALIVE_OPTION, vec![0x00, 0x7b]));
+        edns.options = vec![option];
+        assert_eq!(
+            edns.keepalive_timeout(),
+            Some(Duration::from_millis(12_300))
+        );
+
+        let capped = keepalive_option(Duration::from_secs(100_000));
+        assert_eq!(capped.1, vec![0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_opt_must_be_last() {
+        let mut buf = EdnsOpt::default().serialize();
+        buf.push(0xff);
+        assert!(split_trailing_opt(&buf, 1).is_none());
+        assert!(split_trailing_opt(&buf[..buf.len() - 1], 2).is_none());
+    }
+}
@@ -17,3 +17,23 @@ impl std::fmt::Display for ParseError {
 }
 
 impl std::error::Error for ParseError {}
+
+/// Something that doesn't fit the wire format, like a label over 63 octets.
+#[derive(Debug)]
+pub struct SerializeError {
+    message: String,
+}
+
+impl SerializeError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl std::fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SerializeError {}
@@ -6,7 +6,10 @@ pub enum OpCode {
     QUERY,
     IQUERY,
     STATUS,
-    RESERVED,
+    NOTIFY,
+    UPDATE,
+    /// Unassigned, kept as is so it survives a round-trip.
+    Other(u8),
 }
 
 fn parse_opcode(opcode: u8) -> OpCode {
@@ -14,7 +17,9 @@ fn parse_opcode(opcode: u8) -> OpCode {
         0 => OpCode::QUERY,
         1 => OpCode::IQUERY,
         2 => OpCode::STATUS,
-        _ => OpCode::RESERVED,
+        4 => OpCode::NOTIFY, // RFC 1996
+        5 => OpCode::UPDATE, // RFC 2136
+        n => OpCode::Other(n),
     }
 }
 
@@ -24,27 +29,27 @@ impl OpCode {
             OpCode::QUERY => 0,
             OpCode::IQUERY => 1,
             OpCode::STATUS => 2,
-            OpCode::RESERVED => 3,
+            OpCode::NOTIFY => 4,
+            OpCode::UPDATE => 5,
+            OpCode::Other(n) => n & 0b1111,
         }
     }
 }
 
 impl std::fmt::Display for OpCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                OpCode::QUERY => "QUERY",
-                OpCode::IQUERY => "IQUERY",
-                OpCode::STATUS => "STATUS",
-                OpCode::RESERVED => "RESERVED",
-            }
-        )
+        match self {
+            OpCode::QUERY => write!(f, "QUERY"),
+            OpCode::IQUERY => write!(f, "IQUERY"),
+            OpCode::STATUS => write!(f, "STATUS"),
+            OpCode::NOTIFY => write!(f, "NOTIFY"),
+            OpCode::UPDATE => write!(f, "UPDATE"),
+            OpCode::Other(n) => write!(f, "OpCode({})", n),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RCode {
     NoError,
     FormErr,
@@ -52,10 +57,18 @@ pub enum RCode {
     NXDomain,
     NotImp,
     Refused,
+    YXDomain,
+    YXRRSet,
+    NXRRSet,
+    NotAuth,
+    NotZone,
+    /// Needs the extended RCODE bits from the OPT record, like all above 15.
+    BADVERS,
     RESERVED,
 }
 
-fn parse_rcode(rcode: u8) -> RCode {
+/// Takes the 12-bit extended RCODE, of which the header holds the low 4 bits.
+pub fn parse_rcode(rcode: u16) -> RCode {
     match rcode {
         0 => RCode::NoError,
         1 => RCode::FormErr,
@@ -63,12 +76,19 @@ fn parse_rcode(rcode: u8) -> RCode {
         3 => RCode::NXDomain,
         4 => RCode::NotImp,
         5 => RCode::Refused,
+        6 => RCode::YXDomain,
+        7 => RCode::YXRRSet,
+        8 => RCode::NXRRSet,
+        9 => RCode::NotAuth,
+        10 => RCode::NotZone,
+        16 => RCode::BADVERS,
         _ => RCode::RESERVED,
     }
 }
 
 impl RCode {
-    fn to_u8(self) -> u8 {
+    #[must_use]
+    pub fn to_u16(self) -> u16 {
         match self {
             RCode::NoError => 0,
             RCode::FormErr => 1,
@@ -76,9 +96,27 @@ impl RCode {
             RCode::NXDomain => 3,
             RCode::NotImp => 4,
             RCode::Refused => 5,
+            RCode::YXDomain => 6,
+            RCode::YXRRSet => 7,
+            RCode::NXRRSet => 8,
+            RCode::NotAuth => 9,
+            RCode::NotZone => 10,
             RCode::RESERVED => 15,
+            RCode::BADVERS => 16,
         }
     }
+
+    /// The lower 4 bits that go into the header.
+    #[must_use]
+    pub fn header_bits(self) -> u8 {
+        (self.to_u16() & 0b1111) as u8
+    }
+
+    /// The upper 8 bits that go into the OPT record's extended RCODE.
+    #[must_use]
+    pub fn extended_bits(self) -> u8 {
+        (self.to_u16() >> 4) as u8
+    }
 }
 
 impl std::fmt::Display for RCode {
@@ -93,6 +131,12 @@ impl std::fmt::Display for RCode {
                 RCode::NXDomain => "NXDomain",
                 RCode::NotImp => "NotImp",
                 RCode::Refused => "Refused",
+                RCode::YXDomain => "YXDomain",
+                RCode::YXRRSet => "YXRRSet",
+                RCode::NXRRSet => "NXRRSet",
+                RCode::NotAuth => "NotAuth",
+                RCode::NotZone => "NotZone",
+                RCode::BADVERS => "BADVERS",
                 RCode::RESERVED => "RESERVED",
             }
         )
@@ -119,6 +163,41 @@ pub struct DnsHeader {
 }
 
 impl DnsHeader {
+    /// A standard query with every flag clear and no sections counted.
+    #[must_use]
+    pub fn query(transaction_id: u16) -> DnsHeader {
+        DnsHeader {
+            transaction_id,
+            response: false,
+            opcode: OpCode::QUERY,
+            authoritative_answer: false,
+            truncation: false,
+            recursion_desired: false,
+            recursion_available: false,
+            _reserved: false,
+            authenticated_data: false,
+            checking_disabled: false,
+            rcode: RCode::NoError,
+            qd_count: 0,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        }
+    }
+
+    /// The header of a reply to `query`, taking over what replies echo:
+    /// the id, the opcode and the RD and CD flags.
+    #[must_use]
+    pub fn response_to(query: &DnsHeader) -> DnsHeader {
+        DnsHeader {
+            response: true,
+            opcode: query.opcode,
+            recursion_desired: query.recursion_desired,
+            checking_disabled: query.checking_disabled,
+            ..DnsHeader::query(query.transaction_id)
+        }
+    }
+
     #[must_use]
     pub fn serialize(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(12);
@@ -133,7 +212,7 @@ impl DnsHeader {
             | ((self._reserved as u8) << 6)
             | ((self.authenticated_data as u8) << 5)
             | ((self.checking_disabled as u8) << 4)
-            | self.rcode.to_u8();
+            | self.rcode.header_bits();
         buf.put_u8(byte3);
         buf.put_u16(self.qd_count);
         buf.put_u16(self.an_count);
@@ -184,7 +263,7 @@ pub fn parse_dns_header(buf: &mut &[u8]) -> Result<DnsHeader, ParseError> {
     let ns_count = buf.get_u16();
     let ar_count = buf.get_u16();
 
-    if (byte3 >> 4) & 1 == 1 {
+    if (byte3 >> 6) & 1 == 1 {
         return Err(ParseError::new("Z bit must be 0, got 1".to_string()));
     }
 
@@ -195,14 +274,107 @@ pub fn parse_dns_header(buf: &mut &[u8]) -> Result<DnsHeader, ParseError> {
         authoritative_answer: (byte2 >> 2) & 1 == 1,
         truncation: (byte2 >> 1) & 1 == 1,
         recursion_desired: byte2 & 1 == 1,
-        recursion_available: (byte3 >> 7) == 1,
-        _reserved: (byte3 >> 6) == 1,
-        authenticated_data: (byte3 >> 5) == 1,
-        checking_disabled: (byte3 >> 4) == 1,
-        rcode: parse_rcode(byte3 & 0b1111),
+        recursion_available: (byte3 >> 7) & 1 == 1,
+        _reserved: (byte3 >> 6) & 1 == 1,
+        authenticated_data: (byte3 >> 5) & 1 == 1,
+        checking_disabled: (byte3 >> 4) & 1 == 1,
+        rcode: parse_rcode(u16::from(byte3 & 0b1111)),
         qd_count,
         an_count,
         ns_count,
         ar_count,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rcode_roundtrip() {
+        for value in 0..=10 {
+            let rcode = parse_rcode(value);
+            assert_ne!(rcode, RCode::RESERVED);
+            assert_eq!(rcode.to_u16(), value);
+
+            let header =
+                DnsHeader { response: true, rcode, ..DnsHeader::query(0x1234) };
+            let mut buf = &header.serialize()[..];
+            assert_eq!(parse_dns_header(&mut buf).unwrap(), header);
+        }
+        assert_eq!(parse_rcode(16), RCode::BADVERS);
+        assert_eq!(RCode::BADVERS.extended_bits(), 1);
+        assert_eq!(parse_rcode(11), RCode::RESERVED);
+    }
+
+    #[test]
+    fn test_response_to() {
+        let query = DnsHeader {
+            opcode: OpCode::NOTIFY,
+            recursion_desired: true,
+            checking_disabled: true,
+            authenticated_data: true,
+            qd_count: 1,
+            ar_count: 1,
+            ..DnsHeader::query(0xbeef)
+        };
+        let hand_built = DnsHeader {
+            transaction_id: 0xbeef,
+            response: true,
+            opcode: OpCode::NOTIFY,
+            authoritative_answer: false,
+            truncation: false,
+            recursion_desired: true,
+            recursion_available: false,
+            _reserved: false,
+            authenticated_data: false,
+            checking_disabled: true,
+            rcode: RCode::NoError,
+            qd_count: 0,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        };
+        assert_eq!(DnsHeader::response_to(&query), hand_built);
+        let mut buf: &[u8] = b"\x12\x34\x00\x00\0\0\0\0\0\0\0\0";
+        assert_eq!(
+            parse_dns_header(&mut buf).unwrap(),
+            DnsHeader::query(0x1234)
+        );
+    }
+
+    #[test]
+    fn test_opcode_roundtrip() {
+        // flags set around the opcode bits to catch any spill into them
+        let mut buf: &[u8] = b"\xab\xcd\xaf\x80\0\0\0\0\0\0\0\0";
+        let header = parse_dns_header(&mut buf).unwrap();
+        assert_eq!(header.opcode, OpCode::UPDATE);
+        assert!(header.response);
+        assert!(header.authoritative_answer);
+        assert!(header.truncation);
+        assert!(header.recursion_desired);
+        assert_eq!(&header.serialize()[..4], b"\xab\xcd\xaf\x80");
+
+        for value in 0..16 {
+            let opcode = parse_opcode(value);
+            assert_eq!(opcode.to_u8(), value);
+        }
+        assert_eq!(parse_opcode(3), OpCode::Other(3));
+        assert_eq!(OpCode::Other(3).to_string(), "OpCode(3)");
+    }
+
+    #[test]
+    fn test_flags_after_rcode_byte() {
+        // RA, AD and CD all set, each read on its own
+        let mut buf: &[u8] = b"\x00\x01\x00\xb0\0\0\0\0\0\0\0\0";
+        let header = parse_dns_header(&mut buf).unwrap();
+        assert!(header.recursion_available);
+        assert!(header.authenticated_data);
+        assert!(header.checking_disabled);
+        assert!(!header._reserved);
+        assert_eq!(&header.serialize()[..4], b"\x00\x01\x00\xb0");
+
+        let mut buf: &[u8] = b"\x00\x01\x00\x40\0\0\0\0\0\0\0\0";
+        assert!(parse_dns_header(&mut buf).is_err());
+    }
+}
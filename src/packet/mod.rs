@@ -1,26 +1,36 @@
-use bytes::{Buf as _, BufMut as _};
+use bytes::BufMut as _;
+use std::ops::Range;
 pub mod answer;
+pub mod canonical_name;
 pub mod dns_name;
+pub mod edns;
 pub mod error;
 pub mod header;
 pub mod protocol_class;
 pub mod question;
 pub mod record_type;
+pub mod svcb;
 
-pub use error::ParseError;
+pub use error::{ParseError, SerializeError};
 
 use answer::{DnsAnswer, parse_dns_answer};
-use header::{DnsHeader, parse_dns_header};
+use edns::{EdnsOpt, ExtendedError, OPT_TYPE, split_trailing_opt};
+use header::{DnsHeader, OpCode, RCode, parse_dns_header, parse_rcode};
 use question::{DnsQuestion, parse_dns_question};
+use record_type::Type;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DnsPacket {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
     pub answers: Vec<DnsAnswer>,
-    // TODO: not implemented yet: authority
-    // TODO: not implemented yet: additional
+    pub authorities: Vec<DnsAnswer>,
+    /// Additional records other than the OPT one, which is `edns`.
+    pub additionals: Vec<DnsAnswer>,
+    /// The authority and additional sections as they came, if their
+    /// records couldn't be parsed, or bytes trailing them.
     pub unparsed: Vec<u8>,
+    pub edns: Option<EdnsOpt>,
 }
 
 impl std::fmt::Display for DnsPacket {
@@ -33,6 +43,15 @@ impl std::fmt::Display for DnsPacket {
         for answer in &self.answers {
             writeln!(f, "* {}", answer)?;
         }
+        for authority in &self.authorities {
+            writeln!(f, "* Authority: {}", authority)?;
+        }
+        for additional in &self.additionals {
+            writeln!(f, "* Additional: {}", additional)?;
+        }
+        if let Some(edns) = &self.edns {
+            writeln!(f, "* {}", edns)?;
+        }
         writeln!(f, "? Unparsed: {:x?}", self.unparsed)?;
         write!(f, "}}")?;
         Ok(())
@@ -41,35 +60,600 @@ impl std::fmt::Display for DnsPacket {
 
 impl DnsPacket {
     #[must_use]
-    pub fn serialize(&self) -> Vec<u8> {
+    pub fn builder() -> DnsPacketBuilder {
+        DnsPacketBuilder::default()
+    }
+
+    /// Renders the packet the way `dig` prints replies, for client tooling.
+    #[must_use]
+    pub fn to_dig_string(&self) -> String {
+        use std::fmt::Write as _;
+
+        let header = &self.header;
+        let flags: Vec<&str> = [
+            (header.response, "qr"),
+            (header.authoritative_answer, "aa"),
+            (header.truncation, "tc"),
+            (header.recursion_desired, "rd"),
+            (header.recursion_available, "ra"),
+            (header.authenticated_data, "ad"),
+            (header.checking_disabled, "cd"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect();
+        // writing to a String can't fail
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            header.opcode,
+            header.rcode.to_string().to_ascii_uppercase(),
+            header.transaction_id
+        );
+        let _ = writeln!(
+            out,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, \
+             ADDITIONAL: {}",
+            flags.join(" "),
+            header.qd_count,
+            header.an_count,
+            header.ns_count,
+            header.ar_count
+        );
+        if let Some(edns) = &self.edns {
+            let _ = writeln!(out, "\n;; OPT PSEUDOSECTION:");
+            let _ = writeln!(
+                out,
+                "; EDNS: version: {}, flags:{}; udp: {}",
+                edns.version,
+                if edns.dnssec_ok() { " do" } else { "" },
+                edns.udp_payload_size
+            );
+            if let Ok(Some(subnet)) = edns.client_subnet() {
+                let _ = writeln!(
+                    out,
+                    "; CLIENT-SUBNET: {}/{}/{}",
+                    subnet.address, subnet.source_prefix, subnet.scope_prefix
+                );
+            }
+            if let Ok(Some(ede)) = edns.extended_error() {
+                let _ =
+                    writeln!(out, "; EDE: {}: ({})", ede.info_code, ede.text);
+            }
+        }
+        if !self.questions.is_empty() {
+            let _ = writeln!(out, "\n;; QUESTION SECTION:");
+            for q in &self.questions {
+                let _ = writeln!(
+                    out,
+                    ";{}\t\t{}\t{}",
+                    absolute(&q.qname),
+                    q.qclass,
+                    q.qtype
+                );
+            }
+        }
+        let sections = [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ];
+        for (section, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "\n;; {section} SECTION:");
+            for record in records {
+                let _ = writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    absolute(&record.name),
+                    record.ttl,
+                    record.rclass,
+                    record.rtype,
+                    record.rdata
+                );
+            }
+        }
+        out
+    }
+
+    /// Derives the section counts in the header from the sections, to be
+    /// called after changing them. Records in `unparsed` aren't counted,
+    /// so it's for packets put together here rather than parsed ones.
+    pub fn recount(&mut self) {
+        let count = |len: usize| len.try_into().unwrap_or(u16::MAX);
+        self.header.qd_count = count(self.questions.len());
+        self.header.an_count = count(self.answers.len());
+        self.header.ns_count = count(self.authorities.len());
+        self.header.ar_count =
+            count(self.additionals.len() + usize::from(self.edns.is_some()));
+    }
+
+    /// Sets the full 12-bit RCODE, of which the header carries the lower
+    /// 4 bits on the wire and the OPT record the upper 8 (RFC 6891 6.1.3).
+    pub fn set_rcode(&mut self, rcode: RCode) {
+        self.header.rcode = rcode;
+        if let Some(edns) = &mut self.edns {
+            edns.extended_rcode = rcode.extended_bits();
+        }
+    }
+
+    /// Fails rather than producing a corrupt packet when a name, a string
+    /// or some RDATA is too long for its length field, or when an RCODE
+    /// above 15 has no OPT record to carry its upper bits.
+    pub fn try_serialize(&self) -> Result<Vec<u8>, SerializeError> {
+        if self.edns.is_none() && self.header.rcode.extended_bits() != 0 {
+            return Err(SerializeError::new(format!(
+                "RCODE {} needs an OPT record",
+                self.header.rcode
+            )));
+        }
         let mut buf = Vec::with_capacity(12);
         buf.put_slice(&self.header.serialize());
         for question in &self.questions {
-            buf.put_slice(&question.serialize());
+            buf.put_slice(&question.try_serialize()?);
         }
-        for answer in &self.answers {
-            buf.put_slice(&answer.serialize());
+        for record in self.answers.iter().chain(&self.authorities) {
+            buf.put_slice(&record.try_serialize()?);
+        }
+        for additional in &self.additionals {
+            buf.put_slice(&additional.try_serialize()?);
         }
         buf.put_slice(&self.unparsed);
-        buf
+        if let Some(edns) = &self.edns {
+            let extended_rcode = self.header.rcode.extended_bits();
+            buf.put_slice(
+                &EdnsOpt { extended_rcode, ..edns.clone() }.try_serialize()?,
+            );
+        }
+        Ok(buf)
+    }
+
+    /// Like `try_serialize`, panicking on packets that don't fit.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        self.try_serialize().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// A name with the trailing dot of presentation format.
+fn absolute(name: &str) -> String {
+    if name.ends_with('.') { name.to_string() } else { format!("{name}.") }
+}
+
+/// Builds a `DnsPacket` with the flags defaulted to false and the section
+/// counts computed from the sections on `build()`.
+#[derive(Debug, Clone)]
+pub struct DnsPacketBuilder {
+    header: DnsHeader,
+    questions: Vec<DnsQuestion>,
+    answers: Vec<DnsAnswer>,
+    authorities: Vec<DnsAnswer>,
+    additionals: Vec<DnsAnswer>,
+    edns: Option<EdnsOpt>,
+    ede: Option<ExtendedError>,
+}
+
+impl Default for DnsPacketBuilder {
+    fn default() -> Self {
+        Self {
+            header: DnsHeader::query(0),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+            ede: None,
+        }
     }
 }
 
+impl DnsPacketBuilder {
+    /// Starts over from `header`, like `DnsHeader::response_to` a query.
+    /// Its counts are ignored, `build()` computes them.
+    #[must_use]
+    pub fn header(mut self, header: DnsHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    #[must_use]
+    pub fn transaction_id(mut self, transaction_id: u16) -> Self {
+        self.header.transaction_id = transaction_id;
+        self
+    }
+
+    #[must_use]
+    pub fn response(mut self, response: bool) -> Self {
+        self.header.response = response;
+        self
+    }
+
+    #[must_use]
+    pub fn opcode(mut self, opcode: OpCode) -> Self {
+        self.header.opcode = opcode;
+        self
+    }
+
+    #[must_use]
+    pub fn authoritative_answer(mut self, authoritative_answer: bool) -> Self {
+        self.header.authoritative_answer = authoritative_answer;
+        self
+    }
+
+    #[must_use]
+    pub fn truncation(mut self, truncation: bool) -> Self {
+        self.header.truncation = truncation;
+        self
+    }
+
+    #[must_use]
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    #[must_use]
+    pub fn recursion_available(mut self, recursion_available: bool) -> Self {
+        self.header.recursion_available = recursion_available;
+        self
+    }
+
+    /// Echoed from queries: with nothing validated here, it only tells
+    /// the client its request to skip validation got through.
+    #[must_use]
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.header.checking_disabled = checking_disabled;
+        self
+    }
+
+    #[must_use]
+    pub fn rcode(mut self, rcode: RCode) -> Self {
+        self.header.rcode = rcode;
+        self
+    }
+
+    #[must_use]
+    pub fn add_question(mut self, question: DnsQuestion) -> Self {
+        self.questions.push(question);
+        self
+    }
+
+    #[must_use]
+    pub fn questions(mut self, questions: Vec<DnsQuestion>) -> Self {
+        self.questions = questions;
+        self
+    }
+
+    #[must_use]
+    pub fn add_answer(mut self, answer: DnsAnswer) -> Self {
+        self.answers.push(answer);
+        self
+    }
+
+    #[must_use]
+    pub fn answers(mut self, answers: Vec<DnsAnswer>) -> Self {
+        self.answers = answers;
+        self
+    }
+
+    /// The authority section, like the NS records of a referral.
+    #[must_use]
+    pub fn authorities(mut self, authorities: Vec<DnsAnswer>) -> Self {
+        self.authorities = authorities;
+        self
+    }
+
+    /// The additional section but for the OPT record, which goes last.
+    #[must_use]
+    pub fn additionals(mut self, additionals: Vec<DnsAnswer>) -> Self {
+        self.additionals = additionals;
+        self
+    }
+
+    /// The OPT record, in the additional section after the others.
+    #[must_use]
+    pub fn edns(mut self, edns: Option<EdnsOpt>) -> Self {
+        self.edns = edns;
+        self
+    }
+
+    /// The reason for an error RCODE, added to the OPT record.
+    /// Left out if there's none, as clients not speaking EDNS can't take it.
+    #[must_use]
+    pub fn ede(mut self, ede: Option<ExtendedError>) -> Self {
+        self.ede = ede;
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> DnsPacket {
+        let Self {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+            mut edns,
+            ede,
+        } = self;
+        if let (Some(edns), Some(ede)) = (&mut edns, ede) {
+            edns.options.push(ede.to_option());
+        }
+        let rcode = header.rcode;
+        let mut packet = DnsPacket {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+            unparsed: Vec::new(),
+            edns,
+        };
+        packet.recount();
+        packet.set_rcode(rcode);
+        packet
+    }
+}
+
+/// Byte offsets of each section within a message, to pinpoint where a
+/// malformed one goes wrong. The authority and additional sections are
+/// None if their records couldn't be walked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionOffsets {
+    pub questions: Range<usize>,
+    pub answers: Range<usize>,
+    pub authority: Option<Range<usize>>,
+    pub additional: Option<Range<usize>>,
+}
+
+/// Parses a query or a response alike, the header tells them apart.
+pub fn parse_dns_message(b: &[u8]) -> Result<DnsPacket, ParseError> {
+    parse_dns_query_detailed(b).map(|(packet, _)| packet)
+}
+
+/// The server side's name for `parse_dns_message`, which it is.
 pub fn parse_dns_query(b: &[u8]) -> Result<DnsPacket, ParseError> {
+    parse_dns_message(b)
+}
+
+/// Like `parse_dns_query`, also telling where each section is.
+pub fn parse_dns_query_detailed(
+    b: &[u8],
+) -> Result<(DnsPacket, SectionOffsets), ParseError> {
     // it's a learning project, so I'm doing it low-level for fun, with just Buf
 
     let mut buf = b;
-    let header = parse_dns_header(&mut buf)?;
+    let offset = |buf: &[u8]| b.len() - buf.len();
+    let mut header = parse_dns_header(&mut buf)?;
 
+    let questions_start = offset(buf);
     let mut questions = Vec::new();
     for _ in 0..header.qd_count {
         questions.push(parse_dns_question(&mut buf)?);
     }
+    let answers_start = offset(buf);
     let mut answers = Vec::new();
     for _ in 0..header.an_count {
         answers.push(parse_dns_answer(&mut buf)?);
     }
-    let unparsed = buf.copy_to_bytes(buf.remaining()).to_vec();
+    let authority_start = offset(buf);
+    let mut rest = buf;
+    let authorities = parse_records(&mut rest, header.ns_count);
+    let authority = authorities.as_ref().map(|_| authority_start..offset(rest));
+    let additional_start = offset(rest);
+    let additionals = authorities
+        .as_ref()
+        .and_then(|_| parse_records(&mut rest, header.ar_count));
+    let additional =
+        additionals.as_ref().map(|_| additional_start..offset(rest));
+    let offsets = SectionOffsets {
+        questions: questions_start..answers_start,
+        answers: answers_start..authority_start,
+        authority,
+        additional,
+    };
+
+    let (authorities, additionals, unparsed, edns) =
+        match (authorities, additionals) {
+            (Some(authorities), Some(mut additionals)) if rest.is_empty() => {
+                let edns = split_off_opt(&mut additionals);
+                (authorities, additionals, Vec::new(), edns)
+            }
+            // kept as they came, which serializes them back the same
+            _ => {
+                let records =
+                    usize::from(header.ns_count) + usize::from(header.ar_count);
+                let (unparsed, edns) = match split_trailing_opt(buf, records) {
+                    Some((before, opt)) => (before.to_vec(), Some(opt)),
+                    None => (buf.to_vec(), None),
+                };
+                (Vec::new(), Vec::new(), unparsed, edns)
+            }
+        };
+    if let Some(edns) = &edns
+        && edns.extended_rcode != 0
+    {
+        let extended = u16::from(edns.extended_rcode) << 4;
+        header.rcode = parse_rcode(extended | header.rcode.to_u16());
+    }
+
+    let packet = DnsPacket {
+        header,
+        questions,
+        answers,
+        authorities,
+        additionals,
+        unparsed,
+        edns,
+    };
+    Ok((packet, offsets))
+}
+
+/// `count` records off `buf`, None if they don't all parse.
+fn parse_records(buf: &mut &[u8], count: u16) -> Option<Vec<DnsAnswer>> {
+    (0..count).map(|_| parse_dns_answer(buf).ok()).collect()
+}
+
+/// The OPT record, taken out of the additional records if it's the last.
+fn split_off_opt(additionals: &mut Vec<DnsAnswer>) -> Option<EdnsOpt> {
+    let last = additionals.last()?;
+    if last.rtype != Type::Other(OPT_TYPE) {
+        return None;
+    }
+    let opt = EdnsOpt::from_record(last).ok()?;
+    additionals.pop();
+    Some(opt)
+}
+
+/// Like `parse_dns_query`, but bytes left over after the parsed sections
+/// are an error instead of ending up in `unparsed`.
+pub fn parse_dns_query_strict(b: &[u8]) -> Result<DnsPacket, ParseError> {
+    let packet = parse_dns_query(b)?;
+    if !packet.unparsed.is_empty() {
+        return Err(ParseError::new(format!(
+            "Trailing bytes after the parsed sections: {}",
+            packet.unparsed.len()
+        )));
+    }
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol_class::Class;
+    use super::*;
+    use answer::RData;
+    use std::net::Ipv4Addr;
 
-    Ok(DnsPacket { header, questions, answers, unparsed })
+    #[test]
+    fn test_builder_defaults() {
+        let packet = DnsPacket::builder().build();
+        assert_eq!(packet.header.transaction_id, 0);
+        assert!(!packet.header.response);
+        assert_eq!(packet.header.opcode, OpCode::QUERY);
+        assert_eq!(packet.header.rcode, RCode::NoError);
+        assert_eq!(packet.header.qd_count, 0);
+        assert_eq!(packet.header.an_count, 0);
+        assert!(packet.questions.is_empty());
+        assert!(packet.answers.is_empty());
+        assert!(packet.unparsed.is_empty());
+        assert!(packet.edns.is_none());
+    }
+
+    #[test]
+    fn test_builder_computes_counts() {
+        let question = DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        };
+        let answer = DnsAnswer {
+            name: "example.com".to_string(),
+            rtype: Type::A,
+            rclass: Class::IN,
+            ttl: 60,
+            rdata: RData::A(Ipv4Addr::new(93, 184, 216, 34)),
+        };
+        let packet = DnsPacket::builder()
+            .transaction_id(0x1234)
+            .response(true)
+            .recursion_desired(true)
+            .add_question(question.clone())
+            .add_answer(answer.clone())
+            .add_answer(answer.clone())
+            .build();
+
+        assert_eq!(packet.header.transaction_id, 0x1234);
+        assert!(packet.header.response);
+        assert!(packet.header.recursion_desired);
+        assert!(!packet.header.authoritative_answer);
+        assert_eq!(packet.header.qd_count, 1);
+        assert_eq!(packet.header.an_count, 2);
+        assert_eq!(packet.header.ns_count, 0);
+        assert_eq!(packet.header.ar_count, 0);
+        assert_eq!(packet.questions, vec![question]);
+        assert_eq!(packet.answers, vec![answer.clone(), answer]);
+
+        let reparsed = parse_dns_query(&packet.serialize()).unwrap();
+        assert_eq!(reparsed, packet);
+    }
+
+    #[test]
+    fn test_recount() {
+        let mut packet = DnsPacket::builder()
+            .add_answer(DnsAnswer {
+                name: "example.com".to_string(),
+                rtype: Type::A,
+                rclass: Class::IN,
+                ttl: 60,
+                rdata: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            })
+            .build();
+        assert_eq!(packet.header.an_count, 1);
+        let answer = packet.answers[0].clone();
+        packet.answers.push(answer);
+        // stale until recounted, and serialized as such
+        assert_eq!(packet.header.an_count, 1);
+        packet.recount();
+        assert_eq!(packet.header.an_count, 2);
+        packet.edns = Some(EdnsOpt::default());
+        packet.recount();
+        assert_eq!(packet.header.ar_count, 1);
+        assert_eq!(parse_dns_query(&packet.serialize()).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let packet = DnsPacket::builder()
+            .transaction_id(0x4321)
+            .response(true)
+            .recursion_available(true)
+            .rcode(RCode::NXDomain)
+            .add_question(DnsQuestion {
+                qname: "nonexistent.example.com".to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+
+        let parsed = parse_dns_message(&packet.serialize()).unwrap();
+        assert!(parsed.header.response);
+        assert_eq!(parsed.header.rcode, RCode::NXDomain);
+        assert_eq!(parsed, packet);
+    }
+
+    #[test]
+    fn test_extended_rcode_roundtrip() {
+        let packet = DnsPacket::builder()
+            .transaction_id(0x1234)
+            .response(true)
+            .rcode(RCode::BADVERS)
+            .edns(Some(EdnsOpt::default()))
+            .build();
+        assert_eq!(packet.edns.as_ref().unwrap().extended_rcode, 1);
+
+        let buf = packet.serialize();
+        assert_eq!(buf[3] & 0b1111, 0); // only the low bits in the header
+        assert_eq!(buf[12 + 5], 1); // the upper ones in the OPT's TTL
+        let reparsed = parse_dns_query(&buf).unwrap();
+        assert_eq!(reparsed.header.rcode, RCode::BADVERS);
+        assert_eq!(reparsed.edns.as_ref().unwrap().extended_rcode, 1);
+        assert_eq!(reparsed, packet);
+
+        let mut packet = packet;
+        packet.set_rcode(RCode::Refused);
+        assert_eq!(packet.edns.as_ref().unwrap().extended_rcode, 0);
+        let buf = packet.serialize();
+        assert_eq!(buf[3] & 0b1111, 5);
+        assert_eq!(buf[12 + 5], 0);
+
+        // there's nowhere to put the upper bits without an OPT record
+        packet.edns = None;
+        packet.recount();
+        packet.set_rcode(RCode::BADVERS);
+        assert!(packet.try_serialize().is_err());
+    }
 }
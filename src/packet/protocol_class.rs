@@ -1,6 +1,12 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Class {
     IN, // 1 - Internet
+    CH, // 3 - Chaos, nowadays only for server identification
+    HS, // 4 - Hesiod
+    /// 254, for dynamic updates deleting a record (RFC 2136 2.4)
+    NONE,
+    /// 255, asking for records of whatever class
+    ANY,
     Other(u16),
 }
 
@@ -14,6 +20,10 @@ impl From<u16> for Class {
     fn from(qclass: u16) -> Self {
         match qclass {
             1 => Class::IN,
+            3 => Class::CH,
+            4 => Class::HS,
+            254 => Class::NONE,
+            255 => Class::ANY,
             n => Class::Other(n),
         }
     }
@@ -23,6 +33,10 @@ impl From<Class> for u16 {
     fn from(c: Class) -> u16 {
         match c {
             Class::IN => 1,
+            Class::CH => 3,
+            Class::HS => 4,
+            Class::NONE => 254,
+            Class::ANY => 255,
             Class::Other(n) => n,
         }
     }
@@ -32,6 +46,10 @@ impl std::fmt::Display for Class {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Class::IN => write!(f, "IN"),
+            Class::CH => write!(f, "CH"),
+            Class::HS => write!(f, "HS"),
+            Class::NONE => write!(f, "NONE"),
+            Class::ANY => write!(f, "ANY"),
             Class::Other(n) => write!(f, "Class({})", n),
         }
     }
@@ -1,5 +1,5 @@
 use super::dns_name::{parse_dns_name, serialize_dns_name};
-use super::error::ParseError;
+use super::error::{ParseError, SerializeError};
 use super::protocol_class::Class;
 use super::record_type::Type;
 use bytes::{Buf as _, BufMut as _};
@@ -22,13 +22,18 @@ impl std::fmt::Display for DnsQuestion {
 }
 
 impl DnsQuestion {
-    #[must_use]
-    pub fn serialize(&self) -> Vec<u8> {
+    pub fn try_serialize(&self) -> Result<Vec<u8>, SerializeError> {
         let mut buf = Vec::with_capacity(1 + self.qname.len() + 2 * 2);
-        buf.put_slice(&serialize_dns_name(&self.qname));
+        buf.put_slice(&serialize_dns_name(&self.qname)?);
         buf.put_u16(self.qtype.into());
         buf.put_u16(self.qclass.into());
-        buf
+        Ok(buf)
+    }
+
+    /// Like `try_serialize`, panicking on names that don't fit.
+    #[must_use]
+    pub fn serialize(&self) -> Vec<u8> {
+        self.try_serialize().unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
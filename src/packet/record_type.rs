@@ -1,9 +1,23 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use super::error::ParseError;
+
+/// A query for a whole zone (RFC 5936), a type only ever asked for.
+pub const AXFR_TYPE: u16 = 252;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Type {
     A,     // 1
     NS,    // 2
     CNAME, // 5
+    SOA,   // 6
+    PTR,   // 12
+    HINFO, // 13
+    MX,    // 15
+    TXT,   // 16
     AAAA,  // 28
+    DNAME, // 39
+    DS,    // 43
+    SVCB,  // 64
+    HTTPS, // 65
     Other(u16),
 }
 
@@ -19,7 +33,16 @@ impl From<u16> for Type {
             1 => Type::A,
             2 => Type::NS,
             5 => Type::CNAME,
+            6 => Type::SOA,
+            12 => Type::PTR,
+            13 => Type::HINFO,
+            15 => Type::MX,
+            16 => Type::TXT,
             28 => Type::AAAA,
+            39 => Type::DNAME,
+            43 => Type::DS,
+            64 => Type::SVCB,
+            65 => Type::HTTPS,
             n => Type::Other(n),
         }
     }
@@ -31,7 +54,16 @@ impl From<Type> for u16 {
             Type::A => 1,
             Type::NS => 2,
             Type::CNAME => 5,
+            Type::SOA => 6,
+            Type::PTR => 12,
+            Type::HINFO => 13,
+            Type::MX => 15,
+            Type::TXT => 16,
             Type::AAAA => 28,
+            Type::DNAME => 39,
+            Type::DS => 43,
+            Type::SVCB => 64,
+            Type::HTTPS => 65,
             Type::Other(n) => n,
         }
     }
@@ -43,8 +75,63 @@ impl std::fmt::Display for Type {
             Type::A => write!(f, "A"),
             Type::NS => write!(f, "NS"),
             Type::CNAME => write!(f, "CNAME"),
+            Type::SOA => write!(f, "SOA"),
+            Type::PTR => write!(f, "PTR"),
+            Type::HINFO => write!(f, "HINFO"),
+            Type::MX => write!(f, "MX"),
+            Type::TXT => write!(f, "TXT"),
             Type::AAAA => write!(f, "AAAA"),
+            Type::DNAME => write!(f, "DNAME"),
+            Type::DS => write!(f, "DS"),
+            Type::SVCB => write!(f, "SVCB"),
+            Type::HTTPS => write!(f, "HTTPS"),
             Type::Other(n) => write!(f, "Type({})", n),
         }
     }
 }
+
+/// Accepts mnemonics ("AAAA") and the generic "TYPE28" notation.
+impl std::str::FromStr for Type {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "A" => Ok(Type::A),
+            "NS" => Ok(Type::NS),
+            "CNAME" => Ok(Type::CNAME),
+            "SOA" => Ok(Type::SOA),
+            "PTR" => Ok(Type::PTR),
+            "HINFO" => Ok(Type::HINFO),
+            "MX" => Ok(Type::MX),
+            "TXT" => Ok(Type::TXT),
+            "AAAA" => Ok(Type::AAAA),
+            "DNAME" => Ok(Type::DNAME),
+            "DS" => Ok(Type::DS),
+            "SVCB" => Ok(Type::SVCB),
+            "HTTPS" => Ok(Type::HTTPS),
+            upper => upper
+                .strip_prefix("TYPE")
+                .and_then(|n| n.parse::<u16>().ok())
+                .map(Type::from)
+                .ok_or_else(|| {
+                    ParseError::new(format!("Unknown record type: {}", s))
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_type_names() {
+        assert_eq!("MX".parse::<Type>().unwrap(), Type::MX);
+        assert_eq!("aaaa".parse::<Type>().unwrap(), Type::AAAA);
+        assert_eq!("TYPE16".parse::<Type>().unwrap(), Type::TXT);
+        assert_eq!("https".parse::<Type>().unwrap(), Type::HTTPS);
+        assert_eq!("TYPE99".parse::<Type>().unwrap(), Type::Other(99));
+        assert!("BOGUS".parse::<Type>().is_err());
+        assert!("TYPE65536".parse::<Type>().is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use base64::Engine as _;
+use bytes::BufMut as _;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// SvcParamKeys with a presentation name (RFC 9460), the rest are "keyNNN".
+const KEY_NAMES: [&str; 7] = [
+    "mandatory",
+    "alpn",
+    "no-default-alpn",
+    "port",
+    "ipv4hint",
+    "ech",
+    "ipv6hint",
+];
+
+/// Example: "alpn" -> 1, "key65000" -> 65000
+#[must_use]
+pub fn svc_param_key(name: &str) -> Option<u16> {
+    match KEY_NAMES.iter().position(|known| *known == name) {
+        Some(key) => Some(key as u16),
+        None => name.strip_prefix("key")?.parse().ok(),
+    }
+}
+
+#[must_use]
+pub fn svc_param_name(key: u16) -> String {
+    match KEY_NAMES.get(usize::from(key)) {
+        Some(name) => name.to_string(),
+        None => format!("key{}", key),
+    }
+}
+
+/// Example: ("alpn", "h2,h3") -> (1, b"\x02h2\x02h3")
+pub fn parse_svc_param(
+    name: &str,
+    value: &str,
+) -> Result<(u16, Vec<u8>), String> {
+    let key = svc_param_key(name)
+        .ok_or_else(|| format!("Unknown SvcParamKey '{}'", name))?;
+    let invalid = || format!("Invalid value for {}: '{}'", name, value);
+    let mut buf = Vec::new();
+    match key {
+        0 => {
+            for name in value.split(',') {
+                buf.put_u16(svc_param_key(name).ok_or_else(invalid)?);
+            }
+        }
+        1 => {
+            for id in value.split(',') {
+                let len = u8::try_from(id.len()).map_err(|_| invalid())?;
+                buf.put_u8(len);
+                buf.put_slice(id.as_bytes());
+            }
+        }
+        2 if value.is_empty() => {}
+        2 => return Err(invalid()),
+        3 => buf.put_u16(value.parse().map_err(|_| invalid())?),
+        4 => {
+            for ip in value.split(',') {
+                let ip: Ipv4Addr = ip.parse().map_err(|_| invalid())?;
+                buf.put_slice(&ip.octets());
+            }
+        }
+        5 => {
+            buf = base64::engine::general_purpose::STANDARD
+                .decode(value)
+                .map_err(|_| invalid())?;
+        }
+        6 => {
+            for ip in value.split(',') {
+                let ip: Ipv6Addr = ip.parse().map_err(|_| invalid())?;
+                buf.put_slice(&ip.octets());
+            }
+        }
+        _ => buf.put_slice(value.as_bytes()),
+    }
+    Ok((key, buf))
+}
+
+/// The reverse of `parse_svc_param`: "alpn=h2,h3", "no-default-alpn".
+#[must_use]
+pub fn format_svc_param(key: u16, value: &[u8]) -> String {
+    let name = svc_param_name(key);
+    let formatted = match key {
+        0 if value.len().is_multiple_of(2) => Some(
+            value
+                .chunks(2)
+                .map(|pair| {
+                    svc_param_name(u16::from_be_bytes([pair[0], pair[1]]))
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        1 => format_alpn(value),
+        2 if value.is_empty() => return name,
+        3 if value.len() == 2 => {
+            Some(u16::from_be_bytes([value[0], value[1]]).to_string())
+        }
+        4 if value.len().is_multiple_of(4) => Some(
+            value
+                .chunks(4)
+                .map(|ip| Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        5 => Some(base64::engine::general_purpose::STANDARD.encode(value)),
+        6 if value.len().is_multiple_of(16) => Some(
+            value
+                .chunks(16)
+                .map(|ip| {
+                    Ipv6Addr::from(<[u8; 16]>::try_from(ip).unwrap())
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        _ => None,
+    };
+    // anything malformed or unknown is shown as escaped octets
+    let value = formatted.unwrap_or_else(|| {
+        value
+            .iter()
+            .flat_map(|b| std::ascii::escape_default(*b))
+            .map(char::from)
+            .collect()
+    });
+    format!("{}={}", name, value)
+}
+
+fn format_alpn(mut value: &[u8]) -> Option<String> {
+    let mut ids = Vec::new();
+    while let Some((&len, rest)) = value.split_first() {
+        let len = usize::from(len);
+        if rest.len() < len {
+            return None;
+        }
+        ids.push(String::from_utf8(rest[..len].to_vec()).ok()?);
+        value = &rest[len..];
+    }
+    Some(ids.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svc_params_roundtrip() {
+        for (name, value) in [
+            ("mandatory", "alpn,port"),
+            ("alpn", "h2,h3"),
+            ("port", "8443"),
+            ("ipv4hint", "192.0.2.1,192.0.2.2"),
+            ("ech", "AEX+DQBB"),
+            ("ipv6hint", "2001:db8::1"),
+            ("key65000", "hello"),
+        ] {
+            let (key, wire) = parse_svc_param(name, value).unwrap();
+            assert_eq!(format_svc_param(key, &wire), format!("{name}={value}"));
+        }
+        let (key, wire) = parse_svc_param("no-default-alpn", "").unwrap();
+        assert_eq!(format_svc_param(key, &wire), "no-default-alpn");
+        assert!(parse_svc_param("port", "http").is_err());
+        assert!(parse_svc_param("bogus", "1").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+
+/// Switches to `user` and `group` (names or numeric ids), for once the
+/// privileged port is bound. The group defaults to the user's own,
+/// and the supplementary groups are shed either way.
+#[cfg(unix)]
+pub fn drop_privileges(
+    user: Option<&str>,
+    group: Option<&str>,
+) -> io::Result<()> {
+    let user = user.map(lookup_user).transpose()?;
+    let gid = match (group, user) {
+        (Some(group), _) => Some(lookup_group(group)?),
+        (None, Some((_, Some(gid)))) => Some(gid),
+        (None, Some((uid, None))) => {
+            // keeping root's group would be no drop at all
+            return Err(io::Error::other(format!(
+                "User {uid} has no passwd entry, give its group too"
+            )));
+        }
+        (None, None) => None,
+    };
+    // the groups go first, setgid is no longer allowed after setuid
+    if user.is_some() || gid.is_some() {
+        // SAFETY: plain syscalls without pointers to keep alive
+        if unsafe { libc::setgroups(0, std::ptr::null()) } != 0 {
+            // only root has supplementary groups to shed
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EPERM) {
+                return Err(err);
+            }
+        }
+    }
+    if let Some(gid) = gid
+        && unsafe { libc::setgid(gid) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    if let Some((uid, _)) = user
+        && unsafe { libc::setuid(uid) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(
+    user: Option<&str>,
+    group: Option<&str>,
+) -> io::Result<()> {
+    if user.is_some() || group.is_some() {
+        return Err(io::Error::other("Dropping privileges needs a Unix"));
+    }
+    Ok(())
+}
+
+/// The uid and primary gid of `user`, by name or uid. A uid without
+/// a passwd entry is taken as is, but has no primary group.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> io::Result<(libc::uid_t, Option<libc::gid_t>)> {
+    // SAFETY: an all-zero passwd is valid, it's only filled in below
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0; 16384];
+    let mut found = std::ptr::null_mut();
+    let uid = user.parse().ok();
+    let err = if let Some(uid) = uid {
+        // SAFETY: the strings end up in buf, which outlives their use here
+        unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        }
+    } else {
+        let name = std::ffi::CString::new(user).map_err(|_| {
+            io::Error::other(format!("Invalid user '{}'", user))
+        })?;
+        // SAFETY: the strings end up in buf, which outlives their use here
+        unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        }
+    };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+    if found.is_null() {
+        return match uid {
+            Some(uid) => Ok((uid, None)),
+            None => Err(io::Error::other(format!("No such user '{}'", user))),
+        };
+    }
+    Ok((passwd.pw_uid, Some(passwd.pw_gid)))
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> io::Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group)
+        .map_err(|_| io::Error::other(format!("Invalid group '{}'", group)))?;
+    // SAFETY: an all-zero group is valid, it's only filled in below
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0; 16384];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: the strings end up in buf, which outlives their use here
+    let err = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+    if found.is_null() {
+        return Err(io::Error::other(format!("No such group '{}'", group)));
+    }
+    Ok(entry.gr_gid)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_user() {
+        assert_eq!(lookup_user("root").unwrap(), (0, Some(0)));
+        // a bare uid comes with its primary gid if it has one
+        assert_eq!(lookup_user("0").unwrap(), (0, Some(0)));
+        assert_eq!(lookup_user("3999999999").unwrap(), (3999999999, None));
+        assert!(
+            lookup_user("no-such-user-here")
+                .unwrap_err()
+                .to_string()
+                .contains("No such user 'no-such-user-here'")
+        );
+        assert_eq!(lookup_group("0").unwrap(), 0);
+        assert!(lookup_group("no-such-group-here").is_err());
+    }
+
+    #[test]
+    fn test_bare_uid_without_group_is_refused() {
+        let err = drop_privileges(Some("3999999999"), None).unwrap_err();
+        assert!(err.to_string().contains("give its group too"), "{err}");
+    }
+
+    #[test]
+    fn test_no_flags_change_nothing() {
+        drop_privileges(None, None).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{DnsQuestion, RCode};
+use std::fs::File;
+use std::io::{self, BufWriter, Write as _};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often buffered lines are written out.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One line per answered query, for analytics rather than debugging.
+/// Fields are tab-separated: Unix time with milliseconds, client address,
+/// name, type, rcode, number of answers and transport.
+#[derive(Debug, Default)]
+pub struct QueryLog {
+    /// None when there's no log to write, recording nothing.
+    writer: Option<Mutex<BufWriter<File>>>,
+}
+
+impl QueryLog {
+    /// Appends to the file at `path`, creating it if need be.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self { writer: Some(Mutex::new(BufWriter::new(file))) })
+    }
+
+    pub fn record(
+        &self,
+        peer: IpAddr,
+        question: Option<&DnsQuestion>,
+        rcode: RCode,
+        answers: usize,
+        transport: &str,
+    ) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let line = format_line(
+            SystemTime::now(),
+            peer,
+            question,
+            rcode,
+            answers,
+            transport,
+        );
+        if let Err(e) = writer.lock().unwrap().write_all(line.as_bytes()) {
+            eprintln!("Failed to write to the query log: {e}");
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.writer {
+            Some(writer) => writer.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes buffered lines out every second, for as long as it runs.
+    pub async fn flush_periodically(self: Arc<Self>) -> io::Result<()> {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            // a full disk is no reason to stop answering
+            if let Err(e) = self.flush() {
+                eprintln!("Failed to flush the query log: {e}");
+            }
+        }
+    }
+}
+
+fn format_line(
+    time: SystemTime,
+    peer: IpAddr,
+    question: Option<&DnsQuestion>,
+    rcode: RCode,
+    answers: usize,
+    transport: &str,
+) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (name, qtype) = match question {
+        Some(q) if q.qname.is_empty() => (".".to_string(), q.qtype.to_string()),
+        Some(q) => (q.qname.clone(), q.qtype.to_string()),
+        None => ("-".to_string(), "-".to_string()),
+    };
+    format!(
+        "{}.{:03}\t{peer}\t{name}\t{qtype}\t{rcode}\t{answers}\t{transport}\n",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Class, Type};
+
+    #[test]
+    fn test_format_line() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_042);
+        let question = DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::AAAA,
+            qclass: Class::IN,
+        };
+        let peer = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            format_line(time, peer, Some(&question), RCode::NoError, 2, "UDP"),
+            "1700000000.042\t192.0.2.1\texample.com\tAAAA\tNoError\t2\tUDP\n"
+        );
+        assert_eq!(
+            format_line(time, peer, None, RCode::FormErr, 0, "TCP"),
+            "1700000000.042\t192.0.2.1\t-\t-\tFormErr\t0\tTCP\n"
+        );
+    }
+}
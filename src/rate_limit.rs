@@ -0,0 +1,222 @@
+use crate::clock::Clock;
+use crate::packet::dns_name::canonicalize_name;
+use crate::{DnsPacket, RCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Response Rate Limiting, as BIND has it: a network getting the same
+/// response over and over is likely the victim of spoofed queries,
+/// so past a threshold those responses are dropped or truncated.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Identical responses per second a client network gets unlimited.
+    pub responses_per_second: u32,
+    /// Every `slip`th limited response goes out truncated, so genuine
+    /// clients retry over TCP, and the rest are dropped. 0 drops them all,
+    /// 1 truncates them all.
+    #[serde(default = "default_slip")]
+    pub slip: u32,
+    /// Prefix lengths client addresses are grouped into networks by.
+    #[serde(default = "default_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    #[serde(default = "default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+}
+
+fn default_slip() -> u32 {
+    2
+}
+
+fn default_ipv4_prefix() -> u8 {
+    24
+}
+
+fn default_ipv6_prefix() -> u8 {
+    56
+}
+
+/// What to do with a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Send,
+    Truncate,
+    Drop,
+}
+
+/// Client network, response name and rcode.
+type BucketKey = (IpAddr, String, RCode);
+
+/// Identical responses counted per second, sending everything if
+/// there's no config.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: Option<RateLimitConfig>,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    /// The second the counts are for, since `started`.
+    second: u64,
+    /// Responses in that second, and earlier ones until pruned.
+    counts: HashMap<BucketKey, (u64, u32)>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(config: Option<RateLimitConfig>, clock: Arc<dyn Clock>) -> Self {
+        let started = clock.now();
+        Self { config, clock, started, buckets: Mutex::default() }
+    }
+
+    /// Counts `reply` towards the limit of the network `peer` is on.
+    pub fn check(&self, peer: IpAddr, reply: &DnsPacket) -> Verdict {
+        let Some(config) = &self.config else {
+            return Verdict::Send;
+        };
+        let second = (self.clock.now() - self.started).as_secs();
+        let name = reply
+            .questions
+            .first()
+            .map(|q| canonicalize_name(&q.qname))
+            .unwrap_or_default();
+        let key = (network(peer, config), name, reply.header.rcode);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.second != second {
+            // once a second, so the map only holds the networks of late
+            buckets.counts.retain(|_, (at, _)| *at == second);
+            buckets.second = second;
+        }
+        let (at, count) = buckets.counts.entry(key).or_insert((second, 0));
+        if *at != second {
+            *at = second;
+            *count = 0;
+        }
+        *count += 1;
+        let Some(excess) = count.checked_sub(config.responses_per_second)
+        else {
+            return Verdict::Send;
+        };
+        match excess {
+            0 => Verdict::Send,
+            _ if config.slip != 0 && excess % config.slip == 0 => {
+                Verdict::Truncate
+            }
+            _ => Verdict::Drop,
+        }
+    }
+}
+
+/// The address with everything past the configured prefix zeroed.
+fn network(peer: IpAddr, config: &RateLimitConfig) -> IpAddr {
+    match peer {
+        IpAddr::V4(address) => {
+            let bits = u32::from(address);
+            let prefix = u32::from(config.ipv4_prefix.min(32));
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            IpAddr::V4((bits & mask).into())
+        }
+        IpAddr::V6(address) => {
+            let bits = u128::from(address);
+            let prefix = u32::from(config.ipv6_prefix.min(128));
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            IpAddr::V6((bits & mask).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{FakeClock, SystemClock};
+    use crate::{Class, DnsQuestion, Type};
+    use std::time::Duration;
+
+    fn reply(qname: &str) -> DnsPacket {
+        DnsPacket::builder()
+            .response(true)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_identical_responses_are_limited_per_network() {
+        let clock = Arc::new(FakeClock::default());
+        let config = RateLimitConfig {
+            responses_per_second: 2,
+            slip: 2,
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+        };
+        let limiter = RateLimiter::new(Some(config), clock.clone());
+        let peer = "192.0.2.1".parse().unwrap();
+        let neighbour = "192.0.2.200".parse().unwrap();
+        let elsewhere = "198.51.100.1".parse().unwrap();
+        let example = reply("example.com");
+
+        let verdicts: Vec<_> = [peer, neighbour, peer, neighbour, peer]
+            .into_iter()
+            .map(|peer| limiter.check(peer, &example))
+            .collect();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Send,
+                Verdict::Send,
+                Verdict::Drop,
+                Verdict::Truncate,
+                Verdict::Drop,
+            ]
+        );
+        assert_eq!(limiter.check(elsewhere, &example), Verdict::Send);
+        let other = reply("EXAMPLE.net");
+        assert_eq!(limiter.check(peer, &other), Verdict::Send);
+
+        // still the same second until a whole one has passed
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(limiter.check(peer, &example), Verdict::Truncate);
+
+        // the next second starts over, with older counts pruned
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(limiter.check(peer, &example), Verdict::Send);
+        assert_eq!(limiter.buckets.lock().unwrap().counts.len(), 1);
+    }
+
+    #[test]
+    fn test_unconfigured_limiter_sends_everything() {
+        let limiter = RateLimiter::new(None, Arc::new(SystemClock));
+        let peer = "192.0.2.1".parse().unwrap();
+        for _ in 0..100 {
+            assert_eq!(
+                limiter.check(peer, &reply("example.com")),
+                Verdict::Send
+            );
+        }
+    }
+
+    #[test]
+    fn test_network() {
+        let config = RateLimitConfig {
+            responses_per_second: 1,
+            slip: 0,
+            ipv4_prefix: 20,
+            ipv6_prefix: 0,
+        };
+        let network = |peer: &str| network(peer.parse().unwrap(), &config);
+        assert_eq!(
+            network("198.51.100.7"),
+            "198.51.96.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(network("2001:db8::1"), "::".parse::<IpAddr>().unwrap());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::packet::protocol_class::Class;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record_type::Type;
+use crate::packet::{DnsPacket, parse_dns_message};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+/// A stub resolver sending queries to a single server.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    server: SocketAddr,
+    timeout: Duration,
+}
+
+impl Resolver {
+    #[must_use]
+    pub fn new(server: SocketAddr) -> Self {
+        Self { server, timeout: Duration::from_secs(5) }
+    }
+
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Queries over UDP, retrying over TCP if the reply comes back truncated.
+    pub async fn query(
+        &self,
+        name: &str,
+        qtype: Type,
+    ) -> Result<DnsPacket, io::Error> {
+        let query = build_query(name, qtype);
+        let reply = self.exchange_udp(&query).await?;
+        if reply.header.truncation {
+            return self.exchange_tcp(&query).await;
+        }
+        Ok(reply)
+    }
+
+    /// Queries over TCP only.
+    pub async fn query_tcp(
+        &self,
+        name: &str,
+        qtype: Type,
+    ) -> Result<DnsPacket, io::Error> {
+        self.exchange_tcp(&build_query(name, qtype)).await
+    }
+
+    async fn exchange_udp(
+        &self,
+        query: &DnsPacket,
+    ) -> Result<DnsPacket, io::Error> {
+        let local: SocketAddr = if self.server.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(self.server).await?;
+        socket.send(&query.serialize()).await?;
+
+        let mut buf = vec![0; 65535];
+        // anyone can send to the socket, so junk and replies to something
+        // else are skipped rather than ending the lookup, as spoofed ones
+        // would otherwise get to cut it short
+        let receive = async {
+            loop {
+                let size = socket.recv(&mut buf).await?;
+                match parse_dns_message(&buf[..size]) {
+                    Ok(reply) if is_reply_to(&reply, query) => {
+                        return Ok(reply);
+                    }
+                    Ok(_) => eprintln!(
+                        "Ignoring unrelated reply from {}",
+                        self.server
+                    ),
+                    Err(e) => eprintln!(
+                        "Ignoring malformed reply from {}: {e}",
+                        self.server
+                    ),
+                }
+            }
+        };
+        timeout(self.timeout, receive).await.map_err(|_| timed_out())?
+    }
+
+    async fn exchange_tcp(
+        &self,
+        query: &DnsPacket,
+    ) -> Result<DnsPacket, io::Error> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(self.server).await?;
+            let query_bytes = query.serialize();
+            stream.write_u16(query_bytes.len() as u16).await?; // length prefix
+            stream.write_all(&query_bytes).await?;
+            stream.flush().await?;
+
+            let length = stream.read_u16().await?;
+            let mut data = vec![0u8; length as usize];
+            stream.read_exact(&mut data).await?;
+            let reply = parse_dns_message(&data)?;
+            if !is_reply_to(&reply, query) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "TCP reply doesn't match the query",
+                ));
+            }
+            Ok(reply)
+        };
+        timeout(self.timeout, exchange).await.map_err(|_| timed_out())?
+    }
+}
+
+fn build_query(name: &str, qtype: Type) -> DnsPacket {
+    DnsPacket::builder()
+        .transaction_id(rand::random())
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
+            qname: name.trim_end_matches('.').to_string(),
+            qtype,
+            qclass: Class::IN,
+        })
+        .build()
+}
+
+fn is_reply_to(reply: &DnsPacket, query: &DnsPacket) -> bool {
+    // servers may change the case of the name, it's the same one
+    let same_question = |a: &DnsQuestion, b: &DnsQuestion| {
+        a.qname.eq_ignore_ascii_case(&b.qname)
+            && a.qtype == b.qtype
+            && a.qclass == b.qclass
+    };
+    reply.header.response
+        && reply.header.transaction_id == query.header.transaction_id
+        && reply.questions.len() == query.questions.len()
+        && reply
+            .questions
+            .iter()
+            .zip(&query.questions)
+            .all(|(a, b)| same_question(a, b))
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::answer::{DnsAnswer, RData};
+    use crate::packet::parse_dns_query;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_truncated_reply_retries_over_tcp() {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).await.unwrap();
+
+        // a fake server that truncates over UDP and answers over TCP
+        tokio::spawn(async move {
+            let mut buf = vec![0; 512];
+            let (size, peer) = udp.recv_from(&mut buf).await.unwrap();
+            let query = parse_dns_query(&buf[..size]).unwrap();
+            let truncated = DnsPacket::builder()
+                .transaction_id(query.header.transaction_id)
+                .response(true)
+                .truncation(true)
+                .questions(query.questions)
+                .build();
+            udp.send_to(&truncated.serialize(), peer).await.unwrap();
+
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let length = stream.read_u16().await.unwrap();
+            let mut data = vec![0u8; length as usize];
+            stream.read_exact(&mut data).await.unwrap();
+            let query = parse_dns_query(&data).unwrap();
+            let full = DnsPacket::builder()
+                .transaction_id(query.header.transaction_id)
+                .response(true)
+                .questions(query.questions)
+                .add_answer(DnsAnswer {
+                    name: "example.com".to_string(),
+                    rtype: Type::A,
+                    rclass: Class::IN,
+                    ttl: 5,
+                    rdata: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                })
+                .build()
+                .serialize();
+            stream.write_u16(full.len() as u16).await.unwrap();
+            stream.write_all(&full).await.unwrap();
+        });
+
+        let reply =
+            Resolver::new(addr).query("example.com", Type::A).await.unwrap();
+        assert!(!reply.header.truncation);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(
+            reply.answers[0].rdata,
+            RData::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bogus_udp_replies_are_skipped() {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+
+        // a fake server sending junk, a reply with the wrong ID and one
+        // to another question before the real reply
+        tokio::spawn(async move {
+            let mut buf = vec![0; 512];
+            let (size, peer) = udp.recv_from(&mut buf).await.unwrap();
+            let query = parse_dns_query(&buf[..size]).unwrap();
+            let id = query.header.transaction_id;
+            let reply = |id, qname: &str| {
+                let mut question = query.questions[0].clone();
+                question.qname = qname.to_string();
+                DnsPacket::builder()
+                    .transaction_id(id)
+                    .response(true)
+                    .add_question(question)
+                    .build()
+                    .serialize()
+            };
+            for datagram in [
+                vec![0xff; 5],
+                reply(id.wrapping_add(1), "example.com"),
+                reply(id, "example.net"),
+                reply(id, "EXAMPLE.com"),
+            ] {
+                udp.send_to(&datagram, peer).await.unwrap();
+            }
+        });
+
+        let reply = Resolver::new(addr)
+            .with_timeout(Duration::from_secs(2))
+            .query("example.com", Type::A)
+            .await
+            .unwrap();
+        assert_eq!(reply.questions[0].qname, "EXAMPLE.com");
+    }
+}
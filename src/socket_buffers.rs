@@ -0,0 +1,41 @@
+use std::io;
+use tokio::net::UdpSocket;
+
+/// Sets the kernel's receive and send buffer sizes (SO_RCVBUF, SO_SNDBUF)
+/// for `socket`, so bursts of datagrams aren't dropped before they're read.
+/// The system defaults are left for whichever is None.
+pub fn set_buffer_sizes(
+    socket: &UdpSocket,
+    recv: Option<usize>,
+    send: Option<usize>,
+) -> io::Result<()> {
+    let socket = socket2::SockRef::from(socket);
+    if let Some(size) = recv {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = send {
+        socket.set_send_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_buffer_sizes_are_applied() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sock_ref = socket2::SockRef::from(&socket);
+        let default_send = sock_ref.send_buffer_size().unwrap();
+
+        // small enough for any system limit, big enough to beat defaults
+        set_buffer_sizes(&socket, Some(150_000), None).unwrap();
+        // Linux reports double what was set, for its bookkeeping
+        assert!(sock_ref.recv_buffer_size().unwrap() >= 150_000);
+        assert_eq!(sock_ref.send_buffer_size().unwrap(), default_send);
+
+        set_buffer_sizes(&socket, None, Some(100_000)).unwrap();
+        assert!(sock_ref.send_buffer_size().unwrap() >= 100_000);
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Open TCP connections per peer address, so one client can't take them all.
+#[derive(Debug)]
+pub struct ConnectionTracker {
+    limit: Option<usize>,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionTracker {
+    #[must_use]
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit, open: Mutex::new(HashMap::new()) }
+    }
+
+    /// A slot for one more connection from `ip`, or None if it's at the limit.
+    pub fn try_open(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionSlot> {
+        let mut open = self.open.lock().unwrap();
+        let count = open.get(&ip).copied().unwrap_or(0);
+        if self.limit.is_some_and(|limit| count >= limit) {
+            return None; // nor an entry, which no slot would ever remove
+        }
+        open.insert(ip, count + 1);
+        Some(ConnectionSlot { tracker: Arc::clone(self), ip })
+    }
+
+    #[must_use]
+    pub fn open_connections(&self, ip: IpAddr) -> usize {
+        self.open.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+}
+
+/// Held for as long as the connection is open, freeing its slot on drop.
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    tracker: Arc<ConnectionTracker>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open = self.tracker.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_slots_are_limited_per_ip_and_freed_on_drop() {
+        let tracker = Arc::new(ConnectionTracker::new(Some(2)));
+        let a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+        let first = tracker.try_open(a).unwrap();
+        let _second = tracker.try_open(a).unwrap();
+        assert!(tracker.try_open(a).is_none());
+        assert!(tracker.try_open(b).is_some());
+
+        drop(first);
+        assert_eq!(tracker.open_connections(a), 1);
+        assert!(tracker.try_open(a).is_some());
+        assert_eq!(tracker.open_connections(b), 0);
+    }
+
+    #[test]
+    fn test_refusals_leave_no_entries() {
+        let tracker = Arc::new(ConnectionTracker::new(Some(0)));
+        let a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        assert!(tracker.try_open(a).is_none());
+        assert!(tracker.open.lock().unwrap().is_empty());
+
+        let tracker = Arc::new(ConnectionTracker::new(Some(1)));
+        let slot = tracker.try_open(a).unwrap();
+        assert!(tracker.try_open(a).is_none());
+        drop(slot);
+        assert!(tracker.open.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::pem::{self, PemObject};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+/// DNS over TLS (RFC 7858), served next to plain UDP and TCP.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, the server's own first.
+    pub certificate: PathBuf,
+    /// PEM file with the certificate's private key.
+    pub key: PathBuf,
+    /// Addresses to listen on, port 853 on every interface by default.
+    #[serde(default = "default_listen")]
+    pub listen: Vec<String>,
+}
+
+fn default_listen() -> Vec<String> {
+    vec!["[::]:853".to_string()]
+}
+
+impl TlsConfig {
+    /// Loads the certificate and key, which happens before privileges
+    /// are dropped, so the key may be readable by root only.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, io::Error> {
+        let config = server_config(&self.certificate, &self.key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// A rustls config serving the certificate chain and key in PEM files.
+pub(crate) fn server_config(
+    certificate: &Path,
+    key: &Path,
+) -> Result<ServerConfig, io::Error> {
+    let certificates = CertificateDer::pem_file_iter(certificate)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| pem_error(certificate, e))?;
+    let key =
+        PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn pem_error(path: &Path, e: pem::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Failed to load {}: {}", path.display(), e),
+    )
+}
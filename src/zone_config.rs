@@ -1,120 +1,1433 @@
-use crate::packet::answer::RData;
+use crate::blocklist::Blocklist;
+use crate::cache::DEFAULT_CACHE_MAX_ENTRIES;
+use crate::cidr::Cidr;
+use crate::cookie::CookieSecret;
+use crate::doh::DohConfig;
+use crate::packet::ParseError;
+use crate::packet::answer::{RData, clamp_ttl};
+use crate::packet::canonical_name::CanonicalName;
+use crate::packet::dns_name::{canonicalize_name, validate_name};
 use crate::packet::record_type::Type;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use crate::packet::svcb::parse_svc_param;
+use crate::rate_limit::RateLimitConfig;
+use crate::tls::TlsConfig;
+use base64::Engine as _;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ZoneConfig {
+    /// Answers TXT queries for this name with a fresh sequence number and
+    /// timestamp, so monitors can tell the server isn't serving stale data.
+    #[serde(default)]
+    pub canary_name: Option<String>,
+    /// Consulted in order when no static record matches.
+    #[serde(default)]
+    pub rules: Vec<RegexRule>,
+    /// Upstream servers for names outside all zones and rules.
+    #[serde(default)]
+    pub forwarders: Vec<SocketAddr>,
+    /// The TTL for records that neither they nor their zone give one for.
+    #[serde(default = "default_ttl")]
+    pub default_ttl: u32,
+    /// Bounds for the TTLs of answers from the zones and rules, applied
+    /// whatever the records say. Unbounded if unset.
+    #[serde(default)]
+    pub min_ttl: Option<u32>,
+    #[serde(default)]
+    pub max_ttl: Option<u32>,
+    #[serde(default)]
+    pub answer_order: AnswerOrder,
+    #[serde(skip)]
+    pub(crate) rotation: RotationCounters,
+    /// Leave the zone's NS records and their addresses out of positive
+    /// answers, which otherwise carry them in the authority and additional
+    /// sections, for smaller replies.
+    #[serde(default)]
+    pub minimal_responses: bool,
+    /// Add a PTR for every A and AAAA record to the reverse zone for its
+    /// address, where one is configured, see `add_reverse_records`.
+    #[serde(default)]
+    pub auto_ptr: bool,
+    /// The answer for names no zone covers, when there are no forwarders.
+    #[serde(default)]
+    pub out_of_zone: OutOfZone,
+    /// Skip all lookups and answer everything with `ECHO_ADDRESS`,
+    /// to benchmark the transport in isolation.
+    #[serde(default)]
+    pub echo_mode: bool,
+    /// Further TCP connections from an address with this many open
+    /// are closed right away. Unlimited if unset.
+    #[serde(default)]
+    pub max_tcp_conns_per_ip: Option<usize>,
+    /// Answer FormErr to queries setting EDNS flags other than DO,
+    /// instead of ignoring them as RFC 6891 requires.
+    #[serde(default)]
+    pub strict_edns: bool,
+    /// Forwarded answers kept before the least recently used is dropped.
+    #[serde(default = "default_cache_max_entries")]
+    pub cache_max_entries: usize,
+    /// Answered to CHAOS-class `version.bind` TXT queries.
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// Seconds a TCP connection may sit without a query before it's
+    /// closed, announced to clients asking with EDNS keepalive.
+    #[serde(default = "default_tcp_idle_timeout")]
+    pub tcp_idle_timeout: u64,
+    /// Seconds answering a query may take, forwarding included,
+    /// before the client gets a ServFail instead.
+    #[serde(default = "default_query_timeout")]
+    pub query_timeout: u64,
+    /// Kernel buffer sizes in bytes for UDP sockets, receiving and sending.
+    /// Left to the system default if unset.
+    #[serde(default)]
+    pub udp_recv_buffer: Option<usize>,
+    #[serde(default)]
+    pub udp_send_buffer: Option<usize>,
+    /// UDP sockets per listening address, sharing it with SO_REUSEPORT
+    /// for the kernel to spread datagrams across, each read by a task.
+    #[serde(default = "default_udp_sockets")]
+    pub udp_sockets: NonZeroUsize,
+    /// Certificate, key and addresses for DNS over TLS, off if unset.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Certificate, key and addresses for DNS over HTTPS, off if unset.
+    #[serde(default)]
+    pub doh: Option<DohConfig>,
+    /// Address for plain HTTP liveness and readiness probes, answered
+    /// on `/livez` and `/readyz`, and for the answer caches' counters on
+    /// `/metrics`, none if unset.
+    #[serde(default)]
+    pub health_listen: Option<String>,
+    /// File to append a line per answered query to, for analytics.
+    #[serde(default)]
+    pub query_log: Option<PathBuf>,
+    /// Response Rate Limiting for UDP, off if unset.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Key for the server cookies of DNS Cookies (RFC 7873), as 32 hex
+    /// digits. Random if unset, so cookies don't survive a restart.
+    #[serde(default)]
+    pub cookie_secret: CookieSecret,
+    /// Whether IPv6 sockets refuse IPv4-mapped traffic (IPV6_V6ONLY).
+    /// Left to the system default if unset.
+    #[serde(default)]
+    pub ipv6_only: Option<bool>,
+    /// User to switch to once the sockets are bound, by name or uid.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Group to switch to along with `user`, by default the user's own.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Names answered with a sinkhole, whatever the zones say.
+    #[serde(default)]
+    pub blocklist: Blocklist,
+    /// Clients allowed to query, everyone if unset. The rest are refused.
+    #[serde(default)]
+    pub allow_query: Option<Vec<Cidr>>,
+    /// Secondaries allowed to pull whole zones with AXFR, nobody if empty.
+    #[serde(default)]
+    pub allow_transfer: Vec<Cidr>,
+    /// Alternative configs for clients in given address blocks,
+    /// the first matching one serving them instead of this one.
+    #[serde(default)]
+    pub views: Vec<View>,
     #[serde(flatten)]
     pub zones: HashMap<String, Zone>,
 }
 
+/// A config of its own for some clients, for split-horizon setups.
+/// Settings about listening and the process as a whole are only
+/// taken from the top level.
 #[derive(Debug, Clone, Deserialize)]
+pub struct View {
+    pub name: String,
+    pub match_clients: Vec<Cidr>,
+    #[serde(flatten)]
+    pub config: ZoneConfig,
+}
+
+const DEFAULT_TTL: u32 = 5;
+
+fn default_ttl() -> u32 {
+    DEFAULT_TTL
+}
+
+/// A few seconds, as RFC 7766 suggests for idle connections.
+const DEFAULT_TCP_IDLE_TIMEOUT: u64 = 10;
+
+fn default_tcp_idle_timeout() -> u64 {
+    DEFAULT_TCP_IDLE_TIMEOUT
+}
+
+/// As long as a forwarder gets to answer, see `Resolver`.
+const DEFAULT_QUERY_TIMEOUT: u64 = 5;
+
+fn default_query_timeout() -> u64 {
+    DEFAULT_QUERY_TIMEOUT
+}
+
+fn default_udp_sockets() -> NonZeroUsize {
+    NonZeroUsize::MIN
+}
+
+fn default_cache_max_entries() -> usize {
+    DEFAULT_CACHE_MAX_ENTRIES
+}
+
+fn default_version() -> String {
+    env!("CARGO_PKG_VERSION").to_string()
+}
+
+/// How answers with several records are ordered, for load balancing
+/// or for reproducible replies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnswerOrder {
+    /// As listed in the zone.
+    #[default]
+    Zone,
+    /// Rotated by one position every time the same records are answered.
+    Rotate,
+    /// Randomly shuffled on every query.
+    Shuffle,
+    /// Sorted by data, the same whatever order the zone lists them in.
+    Canonical,
+}
+
+/// How many times each set of records has been answered, for
+/// `AnswerOrder::Rotate`. Keyed by the records rather than the name asked
+/// for, so all the names a wildcard or rule matches share a counter and
+/// there are never more counters than the config has sets of records.
+#[derive(Debug, Default)]
+pub(crate) struct RotationCounters(Mutex<HashMap<(Type, Vec<RData>), usize>>);
+
+impl Clone for RotationCounters {
+    /// A copy of a config starts its rotation over.
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl RotationCounters {
+    /// The turn of `records`, counting this one, to rotate them by.
+    pub(crate) fn next_turn(
+        &self,
+        record_type: Type,
+        records: Vec<RData>,
+    ) -> usize {
+        // a panic elsewhere can't have left a count inconsistent
+        let mut counters =
+            self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let turn = counters.entry((record_type, records)).or_default();
+        let current = *turn;
+        *turn = turn.wrapping_add(1);
+        current
+    }
+}
+
+/// What names outside every zone are answered with, unless forwarded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfZone {
+    /// Refused, as an authoritative server has nothing to say about them.
+    #[default]
+    Refused,
+    /// NXDomain, as if the server knew the whole namespace.
+    NxDomain,
+}
+
+/// What a zone answers for names it has no records at or below.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingNames {
+    /// NXDomain, as RFC 1035 has it: a closed zone.
+    #[default]
+    NxDomain,
+    /// NoError without answers, as if every name existed: an open zone,
+    /// for names that may still turn up below a delegation.
+    NoData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Zone {
     #[serde(default)]
     pub ttl: Option<u32>,
+    #[serde(default)]
+    pub missing_names: MissingNames,
+    /// Query types answered with Refused instead of being looked up.
+    #[serde(default, deserialize_with = "deserialize_types")]
+    pub refuse_types: Vec<Type>,
+    /// Bump the SOA serial on reload (SIGHUP) if the records changed but
+    /// it wasn't.
+    #[serde(default)]
+    pub auto_serial: bool,
+    #[serde(deserialize_with = "deserialize_records")]
     pub records: Vec<Record>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+fn deserialize_types<'de, D>(deserializer: D) -> Result<Vec<Type>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| name.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Record {
     pub name: String,
     pub record_type: Type,
     pub rdata: RData,
+    /// Overrides the zone's TTL if set.
+    pub ttl: Option<u32>,
+    /// Only served during this time of day if set.
+    pub active: Option<TimeWindow>,
+    /// Only served to clients in this block if set, as told by EDNS
+    /// Client Subnet. Untagged records are served to everyone else.
+    pub subnet: Option<Cidr>,
+}
+
+/// A daily window in UTC, like "08:00-18:00". It may wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeWindow {
+    start: u32, // seconds since midnight
+    end: u32,
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+impl TimeWindow {
+    #[must_use]
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let of_day = (since_epoch.as_secs() % SECONDS_PER_DAY) as u32;
+        if self.start <= self.end {
+            self.start <= of_day && of_day < self.end
+        } else {
+            of_day >= self.start || of_day < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("Invalid time window '{}', expected 'HH:MM-HH:MM'", s);
+        let parse_time = |time: &str| -> Option<u32> {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes): (u32, u32) =
+                (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some((hours * 60 + minutes) * 60)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(TimeWindow {
+            start: parse_time(start).ok_or_else(invalid)?,
+            end: parse_time(end).ok_or_else(invalid)?,
+        })
+    }
 }
 
 #[derive(Deserialize)]
 struct RecordHelper {
     name: String,
+    #[serde(rename = "type", deserialize_with = "deserialize_type_name")]
+    record_type: String,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    addresses: Vec<String>,
+    /// Raw RDATA for types without a dedicated format, like `TYPE99`.
+    #[serde(default)]
+    rdata_hex: Option<String>,
+    #[serde(default)]
+    rdata_base64: Option<String>,
+    /// SvcParams for SVCB/HTTPS, on top of any in `address`.
+    #[serde(default)]
+    params: BTreeMap<String, ParamValue>,
+    /// What HINFO records have instead of an address.
+    #[serde(default)]
+    cpu: Option<String>,
+    #[serde(default)]
+    os: Option<String>,
+    #[serde(default)]
+    ttl: Option<u32>,
+    #[serde(default)]
+    active: Option<String>,
+    #[serde(default)]
+    subnet: Option<Cidr>,
+}
+
+/// `port: 443` reads as a YAML number, `alpn: h2` as a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParamValue {
+    Number(u64),
+    Text(String),
+}
+
+impl std::fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamValue::Number(n) => write!(f, "{}", n),
+            ParamValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// `type: 99` reads as a YAML number, taken to mean `TYPE99`.
+fn deserialize_type_name<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TypeName {
+        Number(u16),
+        Name(String),
+    }
+    Ok(match TypeName::deserialize(deserializer)? {
+        TypeName::Number(n) => format!("TYPE{n}"),
+        TypeName::Name(name) => name,
+    })
+}
+
+impl RecordHelper {
+    /// One record per address, each validated for the record type.
+    /// The master file "@" for the apex is stored as the empty name.
+    fn into_records<E: serde::de::Error>(self) -> Result<Vec<Record>, E> {
+        let name = if self.name == "@" { String::new() } else { self.name };
+        validate_name(&name).map_err(E::custom)?;
+        let active = match &self.active {
+            Some(window) => Some(window.parse().map_err(E::custom)?),
+            None => None,
+        };
+        let (ttl, subnet) = (self.ttl, self.subnet);
+        if self.record_type == "HINFO" {
+            if self.address.is_some() || !self.addresses.is_empty() {
+                return Err(E::custom(format!(
+                    "HINFO record '{}' takes 'cpu' and 'os', not an address",
+                    name
+                )));
+            }
+            let rdata = hinfo_rdata(&name, self.cpu, self.os)?;
+            return Ok(vec![Record {
+                name,
+                record_type: Type::HINFO,
+                rdata,
+                ttl,
+                active,
+                subnet,
+            }]);
+        }
+        if self.cpu.is_some() || self.os.is_some() {
+            return Err(E::custom(format!(
+                "Record '{}' has 'cpu' or 'os' but isn't HINFO",
+                name
+            )));
+        }
+        if self.rdata_hex.is_some() || self.rdata_base64.is_some() {
+            if self.address.is_some() || !self.addresses.is_empty() {
+                return Err(E::custom(format!(
+                    "Record '{}' has both an address and raw rdata",
+                    name
+                )));
+            }
+            let (record_type, rdata) = parse_raw_rdata(
+                &self.record_type,
+                self.rdata_hex,
+                self.rdata_base64,
+            )?;
+            return Ok(vec![Record {
+                name,
+                record_type,
+                rdata,
+                ttl,
+                active,
+                subnet,
+            }]);
+        }
+        let addresses = match (self.address, self.addresses.is_empty()) {
+            (Some(address), true) => vec![address],
+            (None, false) => self.addresses,
+            (Some(_), false) => {
+                return Err(E::custom(format!(
+                    "Record '{}' has both 'address' and 'addresses'",
+                    name
+                )));
+            }
+            (None, true) => return Err(E::missing_field("address")),
+        };
+        addresses
+            .into_iter()
+            .map(|address| {
+                let (record_type, mut rdata) =
+                    parse_config_rdata(&self.record_type, address)?;
+                if !self.params.is_empty() {
+                    add_svc_params(&mut rdata, &self.params)
+                        .map_err(E::custom)?;
+                }
+                validate_rdata_names(&rdata).map_err(|e| {
+                    E::custom(format!("Record '{}': {}", name, e))
+                })?;
+                Ok(Record {
+                    name: name.clone(),
+                    record_type,
+                    rdata,
+                    ttl,
+                    active,
+                    subnet,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Both strings, which have to fit a length octet each.
+fn hinfo_rdata<E: serde::de::Error>(
+    name: &str,
+    cpu: Option<String>,
+    os: Option<String>,
+) -> Result<RData, E> {
+    let (Some(cpu), Some(os)) = (cpu, os) else {
+        return Err(E::custom(format!(
+            "HINFO record '{}' needs both 'cpu' and 'os'",
+            name
+        )));
+    };
+    if cpu.len() > 255 || os.len() > 255 {
+        return Err(E::custom(format!(
+            "HINFO record '{}' has a string longer than 255 octets",
+            name
+        )));
+    }
+    Ok(RData::HINFO { cpu, os })
+}
+
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut records =
+            RecordHelper::deserialize(deserializer)?.into_records()?;
+        if records.len() != 1 {
+            return Err(serde::de::Error::custom(
+                "Expected a single address for a single record",
+            ));
+        }
+        Ok(records.remove(0))
+    }
+}
+
+fn deserialize_records<'de, D>(deserializer: D) -> Result<Vec<Record>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut records = Vec::new();
+    for helper in Vec::<RecordHelper>::deserialize(deserializer)? {
+        records.extend(helper.into_records()?);
+    }
+    Ok(records)
+}
+
+/// Answers any name matching `pattern` that has no static record.
+#[derive(Debug, Clone)]
+pub struct RegexRule {
+    pub pattern: Regex,
+    pub record_type: Type,
+    pub rdata: RData,
+}
+
+#[derive(Deserialize)]
+struct RegexRuleHelper {
+    pattern: String,
     #[serde(rename = "type")]
     record_type: String,
     address: String,
 }
 
-impl<'de> Deserialize<'de> for Record {
+impl<'de> Deserialize<'de> for RegexRule {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
-        D: serde::Deserializer<'de>,
+        D: Deserializer<'de>,
     {
-        let helper = RecordHelper::deserialize(deserializer)?;
-
-        let record_type = match helper.record_type.as_str() {
-            "A" => Type::A,
-            "NS" => Type::NS,
-            "CNAME" => Type::CNAME,
-            "AAAA" => Type::AAAA,
-            _ => {
-                return Err(serde::de::Error::unknown_variant(
-                    &helper.record_type,
-                    &["A", "NS", "CNAME", "AAAA"],
-                ));
-            }
+        let helper = RegexRuleHelper::deserialize(deserializer)?;
+        let pattern = Regex::new(&helper.pattern).map_err(|e| {
+            serde::de::Error::custom(format!(
+                "Invalid pattern '{}': {}",
+                helper.pattern, e
+            ))
+        })?;
+        let (record_type, rdata) =
+            parse_config_rdata(&helper.record_type, helper.address)?;
+        Ok(RegexRule { pattern, record_type, rdata })
+    }
+}
+
+fn parse_config_rdata<E: serde::de::Error>(
+    record_type: &str,
+    address: String,
+) -> Result<(Type, RData), E> {
+    let record_type = match record_type {
+        "A" => Type::A,
+        "NS" => Type::NS,
+        "CNAME" => Type::CNAME,
+        "DNAME" => Type::DNAME,
+        "PTR" => Type::PTR,
+        "SOA" => Type::SOA,
+        "AAAA" => Type::AAAA,
+        "DS" => Type::DS,
+        "SVCB" => Type::SVCB,
+        "HTTPS" => Type::HTTPS,
+        _ => {
+            return Err(E::unknown_variant(
+                record_type,
+                &[
+                    "A", "NS", "CNAME", "DNAME", "PTR", "SOA", "AAAA", "DS",
+                    "SVCB", "HTTPS",
+                ],
+            ));
+        }
+    };
+
+    let rdata = match record_type {
+        Type::A => {
+            let ip: Ipv4Addr = address.parse().map_err(|e| {
+                E::custom(format!("Invalid IPv4 address '{}': {}", address, e))
+            })?;
+            RData::A(ip)
+        }
+        Type::AAAA => {
+            let ip: Ipv6Addr = address.parse().map_err(|e| {
+                E::custom(format!("Invalid IPv6 address '{}': {}", address, e))
+            })?;
+            RData::AAAA(ip)
+        }
+        Type::NS => RData::NS(address),
+        Type::CNAME => RData::CNAME(address),
+        Type::DNAME => RData::DNAME(address),
+        Type::PTR => RData::PTR(address),
+        Type::SOA => parse_soa(&address).ok_or_else(|| {
+            E::custom(format!(
+                "Invalid SOA '{}', expected \
+                 'mname rname serial refresh retry expire minimum'",
+                address
+            ))
+        })?,
+        Type::DS => parse_ds(&address).ok_or_else(|| {
+            E::custom(format!(
+                "Invalid DS '{}', expected \
+                 'key_tag algorithm digest_type digest'",
+                address
+            ))
+        })?,
+        Type::SVCB | Type::HTTPS => parse_svcb(&address).map_err(|e| {
+            E::custom(format!("Invalid {} '{}': {}", record_type, address, e))
+        })?,
+        // HINFO has fields of its own instead, see `RecordHelper`
+        Type::MX | Type::TXT | Type::HINFO | Type::Other(_) => {
+            return Err(E::custom(format!(
+                "{} type not supported in config",
+                record_type
+            )));
+        }
+    };
+
+    Ok((record_type, rdata))
+}
+
+/// Catches names that would come out corrupt on the wire, e.g. "ns..example".
+fn validate_rdata_names(rdata: &RData) -> Result<(), ParseError> {
+    match rdata {
+        RData::NS(name)
+        | RData::CNAME(name)
+        | RData::DNAME(name)
+        | RData::PTR(name) => validate_name(name),
+        RData::SOA { mname, rname, .. } => {
+            validate_name(mname)?;
+            validate_name(rname)
+        }
+        RData::MX { exchange, .. } => validate_name(exchange),
+        RData::Svcb { target, .. } => validate_name(target),
+        _ => Ok(()),
+    }
+}
+
+/// For types without a dedicated format, given as "99" or "TYPE99".
+fn parse_raw_rdata<E: serde::de::Error>(
+    record_type: &str,
+    rdata_hex: Option<String>,
+    rdata_base64: Option<String>,
+) -> Result<(Type, RData), E> {
+    let record_type = match record_type.parse::<u16>() {
+        Ok(n) => Type::from(n),
+        Err(_) => record_type.parse().map_err(E::custom)?,
+    };
+    if !matches!(record_type, Type::Other(_)) {
+        return Err(E::custom(format!(
+            "Raw rdata is only for unknown types, use 'address' for {}",
+            record_type
+        )));
+    }
+    let data = match (rdata_hex, rdata_base64) {
+        (Some(hex), None) => decode_hex(&hex)
+            .ok_or_else(|| E::custom(format!("Invalid rdata_hex '{}'", hex)))?,
+        (None, Some(base64)) => base64::engine::general_purpose::STANDARD
+            .decode(base64.trim())
+            .map_err(|e| {
+                E::custom(format!("Invalid rdata_base64 '{}': {}", base64, e))
+            })?,
+        _ => {
+            return Err(E::custom(
+                "Expected one of 'rdata_hex' and 'rdata_base64'",
+            ));
+        }
+    };
+    Ok((record_type, RData::Other(data)))
+}
+
+/// Example: "0a 0b0c" -> [10, 11, 12]
+pub(crate) fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> =
+        text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2)
+        || !digits.iter().all(u8::is_ascii_hexdigit)
+    {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()
+        })
+        .collect()
+}
+
+/// Example: "ns1.example.com. hostmaster.example.com. 1 7200 3600 1209600 300"
+fn parse_soa(text: &str) -> Option<RData> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let [mname, rname, serial, refresh, retry, expire, minimum] = fields[..]
+    else {
+        return None;
+    };
+    Some(RData::SOA {
+        mname: mname.to_string(),
+        rname: rname.to_string(),
+        serial: serial.parse().ok()?,
+        refresh: refresh.parse().ok()?,
+        retry: retry.parse().ok()?,
+        expire: expire.parse().ok()?,
+        minimum: minimum.parse().ok()?,
+    })
+}
+
+/// Example: "60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118"
+fn parse_ds(text: &str) -> Option<RData> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let [key_tag, algorithm, digest_type, digest @ ..] = &fields[..] else {
+        return None;
+    };
+    Some(RData::DS {
+        key_tag: key_tag.parse().ok()?,
+        algorithm: algorithm.parse().ok()?,
+        digest_type: digest_type.parse().ok()?,
+        digest: decode_hex(&digest.concat())?,
+    })
+}
+
+/// Example: "1 . alpn=h2,h3 port=8443"
+fn parse_svcb(text: &str) -> Result<RData, String> {
+    let mut fields = text.split_whitespace();
+    let (Some(priority), Some(target)) = (fields.next(), fields.next()) else {
+        return Err("expected 'priority target [key=value...]'".to_string());
+    };
+    let mut rdata = RData::Svcb {
+        priority: priority
+            .parse()
+            .map_err(|_| format!("invalid priority '{}'", priority))?,
+        target: target.to_string(),
+        params: Vec::new(),
+    };
+    let params: BTreeMap<String, ParamValue> = fields
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (key.to_string(), ParamValue::Text(value.to_string()))
+        })
+        .collect();
+    add_svc_params(&mut rdata, &params)?;
+    Ok(rdata)
+}
+
+/// Merges presentation-format params in, keeping them sorted by key.
+fn add_svc_params(
+    rdata: &mut RData,
+    new_params: &BTreeMap<String, ParamValue>,
+) -> Result<(), String> {
+    let RData::Svcb { params, .. } = rdata else {
+        return Err("'params' are only for SVCB and HTTPS records".to_string());
+    };
+    for (name, value) in new_params {
+        let (key, wire) = parse_svc_param(name, &value.to_string())?;
+        if params.iter().any(|(existing, _)| *existing == key) {
+            return Err(format!("Duplicate SvcParamKey '{}'", name));
+        }
+        params.push((key, wire));
+    }
+    params.sort_by_key(|(key, _)| *key);
+    Ok(())
+}
+
+impl std::str::FromStr for ZoneConfig {
+    type Err = serde_yaml::Error;
+
+    /// Parses a YAML config, leaving `validate` to the caller.
+    /// `${VAR}` is replaced by the environment variable first, anywhere
+    /// in the text, and `${VAR:-fallback}` by the fallback if it's unset.
+    fn from_str(yaml: &str) -> Result<Self, Self::Err> {
+        let yaml = expand_variables(yaml, |name| std::env::var(name).ok())
+            .map_err(<serde_yaml::Error as serde::de::Error>::custom)?;
+        serde_yaml::from_str(&yaml)
+    }
+}
+
+static VARIABLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap()
+});
+
+/// Example: ("port: ${PORT:-53}", PORT unset) -> "port: 53"
+fn expand_variables(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut last = 0;
+    for captures in VARIABLE.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        let name = &captures[1];
+        let value = lookup(name)
+            .or_else(|| captures.get(2).map(|default| default.as_str().into()))
+            .ok_or_else(|| format!("Environment variable {name} is not set"))?;
+        expanded.push_str(&text[last..whole.start()]);
+        expanded.push_str(&value);
+        last = whole.end();
+    }
+    expanded.push_str(&text[last..]);
+    Ok(expanded)
+}
+
+impl ZoneConfig {
+    /// Parses a JSON config the way `from_str` does a YAML one,
+    /// environment variables included.
+    pub fn from_json_str(json: &str) -> Result<ZoneConfig, serde_json::Error> {
+        let json = expand_variables(json, |name| std::env::var(name).ok())
+            .map_err(<serde_json::Error as serde::de::Error>::custom)?;
+        serde_json::from_str(&json)
+    }
+
+    /// Reads and parses a config file, leaving `validate` to the caller.
+    /// Files ending in `.json` are JSON, any others YAML.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<ZoneConfig> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let parsed = if path.extension().is_some_and(|ext| ext == "json") {
+            ZoneConfig::from_json_str(&text).map_err(|e| e.to_string())
+        } else {
+            text.parse().map_err(|e: serde_yaml::Error| e.to_string())
         };
+        parsed.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
 
-        let rdata = match record_type {
-            Type::A => {
-                let ip: Ipv4Addr = helper.address.parse().map_err(|e| {
-                    serde::de::Error::custom(format!(
-                        "Invalid IPv4 address '{}': {}",
-                        helper.address, e
-                    ))
-                })?;
-                RData::A(ip)
+    /// Combines configs split across files. Zones may only be defined once;
+    /// rules, forwarders, views and blocked names are appended, and every
+    /// other setting is taken from `self`.
+    pub fn merge(mut self, other: ZoneConfig) -> Result<ZoneConfig, String> {
+        let mut duplicates: Vec<&String> = other
+            .zones
+            .keys()
+            .filter(|zone_name| self.zones.contains_key(*zone_name))
+            .collect();
+        if !duplicates.is_empty() {
+            duplicates.sort();
+            let duplicates: Vec<&str> =
+                duplicates.into_iter().map(String::as_str).collect();
+            return Err(format!(
+                "Zones defined more than once: {}",
+                duplicates.join(", ")
+            ));
+        }
+        self.zones.extend(other.zones);
+        self.rules.extend(other.rules);
+        self.forwarders.extend(other.forwarders);
+        self.views.extend(other.views);
+        self.blocklist.extend(other.blocklist);
+        Ok(self)
+    }
+
+    /// The zone named `name`, along with its name as configured.
+    #[must_use]
+    pub fn zone(&self, name: &str) -> Option<(&str, &Zone)> {
+        let name = canonicalize_name(name);
+        self.zones
+            .iter()
+            .find(|(zone_name, _)| canonicalize_name(zone_name) == name)
+            .map(|(zone_name, zone)| (zone_name.as_str(), zone))
+    }
+
+    /// `ttl` raised to `min_ttl` and lowered to `max_ttl`, where set.
+    #[must_use]
+    pub fn bound_ttl(&self, ttl: u32) -> u32 {
+        let ttl = self.min_ttl.map_or(ttl, |min_ttl| ttl.max(min_ttl));
+        clamp_ttl(self.max_ttl.map_or(ttl, |max_ttl| ttl.min(max_ttl)))
+    }
+
+    /// Whether names outside our zones get resolved, advertised as RA.
+    #[must_use]
+    pub fn recursion_available(&self) -> bool {
+        !self.forwarders.is_empty()
+    }
+
+    #[must_use]
+    pub fn allows_query(&self, peer: IpAddr) -> bool {
+        self.allow_query.as_ref().is_none_or(|allowed| {
+            allowed.iter().any(|block| block.contains(peer))
+        })
+    }
+
+    #[must_use]
+    pub fn allows_transfer(&self, peer: IpAddr) -> bool {
+        self.allow_transfer.iter().any(|block| block.contains(peer))
+    }
+
+    /// Index into `views` of the view serving clients at `peer`.
+    #[must_use]
+    pub fn view_index(&self, peer: IpAddr) -> Option<usize> {
+        self.views.iter().position(|view| {
+            view.match_clients.iter().any(|block| block.contains(peer))
+        })
+    }
+
+    /// The config serving clients at `peer`, this one if no view matches.
+    #[must_use]
+    pub fn view_for(&self, peer: IpAddr) -> &ZoneConfig {
+        match self.view_index(peer) {
+            Some(index) => &self.views[index].config,
+            None => self,
+        }
+    }
+
+    /// To be called on a freshly loaded config replacing `previous`.
+    /// For zones with `auto_serial`, a changed record set gets a serial
+    /// above the previously served one even if the file wasn't bumped,
+    /// and an unchanged one keeps serving the previous serial. Views
+    /// carry theirs over from the previous view of the same name.
+    pub fn carry_serials_from(&mut self, previous: &ZoneConfig) {
+        for view in &mut self.views {
+            let old_view =
+                previous.views.iter().find(|old| old.name == view.name);
+            if let Some(old_view) = old_view {
+                view.config.carry_serials_from(&old_view.config);
             }
-            Type::AAAA => {
-                let ip: Ipv6Addr = helper.address.parse().map_err(|e| {
-                    serde::de::Error::custom(format!(
-                        "Invalid IPv6 address '{}': {}",
-                        helper.address, e
-                    ))
-                })?;
-                RData::AAAA(ip)
+        }
+        for (zone_name, zone) in &mut self.zones {
+            if !zone.auto_serial {
+                continue;
             }
-            Type::NS => RData::NS(helper.address),
-            Type::CNAME => RData::CNAME(helper.address),
-            Type::Other(_) => {
-                return Err(serde::de::Error::custom(
-                    "Other type not supported in config",
+            let Some(old_zone) = previous.zones.get(zone_name) else {
+                continue;
+            };
+            let (Some(old_serial), Some(new_serial)) =
+                (old_zone.soa_serial(), zone.soa_serial())
+            else {
+                continue;
+            };
+            let serial = if zone.content() == old_zone.content() {
+                old_serial
+            } else if serial_gt(new_serial, old_serial) {
+                new_serial
+            } else {
+                old_serial.wrapping_add(1)
+            };
+            zone.set_soa_serial(serial);
+        }
+    }
+
+    /// To be called once a config is loaded, with `auto_ptr` set.
+    /// Every A and AAAA record gets a PTR pointing back at its name in
+    /// the most specific in-addr.arpa or ip6.arpa zone for its address,
+    /// unless that zone has a PTR at the name already. Views get theirs
+    /// from their own zones.
+    pub fn add_reverse_records(&mut self) {
+        for view in &mut self.views {
+            view.config.add_reverse_records();
+        }
+        if !self.auto_ptr {
+            return;
+        }
+        let mut pointers = Vec::new();
+        for (zone_name, zone) in &self.zones {
+            for record in &zone.records {
+                let address = match record.rdata {
+                    RData::A(ip) => IpAddr::V4(ip),
+                    RData::AAAA(ip) => IpAddr::V6(ip),
+                    _ => continue,
+                };
+                let Some((reverse_zone, _)) =
+                    find_zone(self, &reverse_name(address))
+                else {
+                    continue;
+                };
+                let target = absolute_name(&record.name, zone_name);
+                let pointer = Record {
+                    name: format!("{}.", reverse_name(address)),
+                    record_type: Type::PTR,
+                    rdata: RData::PTR(target),
+                    ttl: record.ttl.or(zone.ttl),
+                    active: record.active,
+                    subnet: record.subnet,
+                };
+                pointers.push((reverse_zone.to_string(), pointer));
+            }
+        }
+        let explicit: HashSet<String> = self
+            .zones
+            .iter()
+            .flat_map(|(zone_name, zone)| {
+                zone.records
+                    .iter()
+                    .filter(|record| record.record_type == Type::PTR)
+                    .map(move |record| {
+                        canonicalize_name(&absolute_name(
+                            &record.name,
+                            zone_name,
+                        ))
+                    })
+            })
+            .collect();
+        for (zone_name, pointer) in pointers {
+            let zone = self.zones.get_mut(&zone_name).unwrap();
+            // expanding twice adds nothing the second time
+            if !explicit.contains(&canonicalize_name(&pointer.name))
+                && !zone.records.contains(&pointer)
+            {
+                zone.records.push(pointer);
+            }
+        }
+    }
+}
+
+/// Example: 192.0.2.1 -> "1.2.0.192.in-addr.arpa"
+/// Example: 2001:db8::1 -> "1.0.0.0.[...].8.b.d.0.1.0.0.2.ip6.arpa"
+#[must_use]
+pub fn reverse_name(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<String> = ip
+                .octets()
+                .into_iter()
+                .rev()
+                .flat_map(|octet| [octet & 0xf, octet >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+impl ZoneConfig {
+    /// Consistency checks beyond what deserialization enforces,
+    /// reporting every problem found rather than just the first one.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut zone_names: Vec<&String> = self.zones.keys().collect();
+        zone_names.sort();
+        let mut problems: Vec<String> = zone_names
+            .into_iter()
+            .flat_map(|zone_name| self.zones[zone_name].problems(zone_name))
+            .collect();
+        if let (Some(min_ttl), Some(max_ttl)) = (self.min_ttl, self.max_ttl)
+            && min_ttl > max_ttl
+        {
+            problems
+                .push(format!("min_ttl {min_ttl} is above max_ttl {max_ttl}"));
+        }
+        let mut view_names = HashSet::new();
+        for view in &self.views {
+            if !view_names.insert(&view.name) {
+                problems.push(format!("View '{}' defined twice", view.name));
+            }
+            if !view.config.views.is_empty() {
+                problems.push(format!("View '{}' has views", view.name));
+            }
+            if let Err(view_problems) = view.config.validate() {
+                problems.extend(view_problems.into_iter().map(|problem| {
+                    format!("In view '{}': {problem}", view.name)
+                }));
+            }
+        }
+        if problems.is_empty() { Ok(()) } else { Err(problems) }
+    }
+}
+
+/// RFC 1982 serial number comparison.
+fn serial_gt(a: u32, b: u32) -> bool {
+    a != b && (a.wrapping_sub(b) as i32) > 0
+}
+
+impl Zone {
+    /// The record's own TTL, else the zone's, else `default_ttl`.
+    #[must_use]
+    pub fn ttl_of(&self, record: &Record, default_ttl: u32) -> u32 {
+        clamp_ttl(record.ttl.or(self.ttl).unwrap_or(default_ttl))
+    }
+
+    #[must_use]
+    pub fn soa_serial(&self) -> Option<u32> {
+        self.records.iter().find_map(|record| match record.rdata {
+            RData::SOA { serial, .. } => Some(serial),
+            _ => None,
+        })
+    }
+
+    fn set_soa_serial(&mut self, new_serial: u32) {
+        for record in &mut self.records {
+            if let RData::SOA { serial, .. } = &mut record.rdata {
+                *serial = new_serial;
+            }
+        }
+    }
+
+    /// The record set with SOA serials blanked, for change detection.
+    fn content(&self) -> HashSet<Record> {
+        let mut zone = self.clone();
+        zone.set_soa_serial(0);
+        zone.records.into_iter().collect()
+    }
+
+    fn problems(&self, zone_name: &str) -> Vec<String> {
+        let zone_name = zone_name.to_ascii_lowercase();
+        let apex = CanonicalName::from(zone_name.as_str());
+        let mut problems = Vec::new();
+        let mut seen = HashSet::new();
+        let mut types_by_name: HashMap<String, Vec<Type>> = HashMap::new();
+        for record in &self.records {
+            let name =
+                absolute_name(&record.name, &zone_name).to_ascii_lowercase();
+            if !CanonicalName::from(name.as_str()).is_within(&apex) {
+                problems.push(format!(
+                    "{zone_name}: '{}' is outside the zone",
+                    record.name
                 ));
+                continue;
             }
-        };
+            if !seen.insert((name.clone(), record.record_type, &record.rdata)) {
+                problems.push(format!(
+                    "{zone_name}: duplicate {} record for '{name}'",
+                    record.record_type
+                ));
+            }
+            types_by_name.entry(name).or_default().push(record.record_type);
+        }
+
+        let mut names: Vec<_> = types_by_name.iter().collect();
+        names.sort_by_key(|(name, _)| *name);
+        for (name, types) in names {
+            if *name == zone_name && types.contains(&Type::CNAME) {
+                // RFC 1912 2.4, a CNAME can't share the apex with SOA and NS
+                problems.push(format!(
+                    "{zone_name}: CNAME at the apex, which has to have SOA \
+                     and NS records"
+                ));
+            } else if types.contains(&Type::CNAME)
+                && types.iter().any(|t| *t != Type::CNAME)
+            {
+                problems.push(format!(
+                    "{zone_name}: '{name}' has a CNAME alongside other records"
+                ));
+            }
+        }
 
-        Ok(Record { name: helper.name, record_type, rdata })
+        let apex = types_by_name.get(&zone_name);
+        for required in [Type::SOA, Type::NS] {
+            if !apex.is_some_and(|types| types.contains(&required)) {
+                problems.push(format!(
+                    "{zone_name}: no {required} record at the apex"
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Records in canonical order: by absolute owner name compared label
+    /// by label from the right (apex first), then by type, keeping config
+    /// order for ties. Zone transfer consumers diff these, so it must be
+    /// stable, whether owners are written relative to `zone_name` or not.
+    #[must_use]
+    pub fn canonical_records(&self, zone_name: &str) -> Vec<&Record> {
+        let mut records: Vec<&Record> = self.records.iter().collect();
+        records.sort_by_cached_key(|record| {
+            let name = absolute_name(&record.name, zone_name);
+            (CanonicalName::from(name.as_str()), u16::from(record.record_type))
+        });
+        records
     }
 }
 
-// TODO: make an iterator
+/// Example: ("subdomain", "example.org") -> "subdomain.example.org"
+/// Example: ("host.example.org.", "example.org") -> "host.example.org"
+#[must_use]
+pub fn absolute_name(record_name: &str, zone_name: &str) -> String {
+    if record_name.is_empty() || record_name == "@" {
+        zone_name.to_string()
+    } else if let Some(fqdn) = record_name.strip_suffix('.') {
+        fqdn.to_string()
+    } else {
+        format!("{}.{}", record_name, zone_name)
+    }
+}
+
+/// The zone with the longest name that `domain` is equal to or under.
+pub fn find_zone<'a>(
+    config: &'a ZoneConfig,
+    domain: &str,
+) -> Option<(&'a str, &'a Zone)> {
+    let domain = CanonicalName::from(domain);
+    config
+        .zones
+        .iter()
+        .map(|(zone_name, zone)| {
+            (CanonicalName::from(zone_name.as_str()), zone_name, zone)
+        })
+        .filter(|(apex, _, _)| domain.is_within(apex))
+        .max_by_key(|(apex, _, _)| apex.label_count())
+        .map(|(_, zone_name, zone)| (zone_name.as_str(), zone))
+}
+
+/// Whether `domain` exists in the most specific zone for it: with records
+/// of any type at it, or below it as an empty non-terminal (RFC 8020).
+/// A rule matching it counts as well.
+pub fn name_exists(config: &ZoneConfig, domain: &str) -> bool {
+    let domain = CanonicalName::from(domain);
+    let in_zone =
+        find_zone(config, domain.as_str()).is_some_and(|(zone_name, zone)| {
+            zone.records.iter().any(|record| {
+                let name = absolute_name(&record.name, zone_name);
+                CanonicalName::from(name.as_str()).is_within(&domain)
+            })
+        });
+    in_zone
+        || config
+            .rules
+            .iter()
+            .any(|rule| rule.pattern.is_match(domain.as_str()))
+}
+
+/// The zone records of `record_type` at `domain` with their TTLs, lazily
+/// and in zone order. Unlike `find_record`, rules aren't consulted.
+pub fn matching_records<'a>(
+    config: &'a ZoneConfig,
+    domain: &str,
+    record_type: Type,
+) -> impl Iterator<Item = (&'a Record, u32)> + 'a {
+    let domain = CanonicalName::from(domain);
+    // only the most specific zone is authoritative for the name
+    let zone = find_zone(config, domain.as_str());
+    zone.into_iter().flat_map(move |(zone_name, zone)| {
+        let default_ttl = config.default_ttl;
+        records_at(zone_name, zone, domain.clone(), record_type, default_ttl)
+    })
+}
+
+/// The records of `record_type` at `domain`, each with its TTL,
+/// falling back to the rules if the zones have none.
 pub fn find_record(
     config: &ZoneConfig,
     domain: &str,
     record_type: Type,
-) -> (Vec<Record>, u32) {
-    let mut results = Vec::new();
-    let mut ttl = 5; // default TTL
+) -> Vec<(Record, u32)> {
+    let domain = CanonicalName::from(domain);
+    let mut results: Vec<(Record, u32)> =
+        matching_records(config, domain.as_str(), record_type)
+            .map(|(record, ttl)| (record.clone(), ttl))
+            .collect();
+    if results.is_empty() {
+        results.extend(
+            config
+                .rules
+                .iter()
+                .filter(|rule| {
+                    rule.record_type == record_type
+                        && rule.pattern.is_match(domain.as_str())
+                })
+                .map(|rule| {
+                    let record = Record {
+                        name: domain.to_string(),
+                        record_type: rule.record_type,
+                        rdata: rule.rdata.clone(),
+                        ttl: None,
+                        active: None,
+                        subnet: None,
+                    };
+                    (record, clamp_ttl(config.default_ttl))
+                }),
+        );
+    }
+    results
+}
 
-    for (zone_name, zone) in &config.zones {
-        if !domain.ends_with(zone_name) {
-            continue; // optimization
+/// The zone on the parent side of the cut at `domain`, if there is one:
+/// the enclosing zone for the apex of a zone nested in another one,
+/// the delegating zone for a delegation's name.
+pub fn ds_parent_zone<'a>(
+    config: &'a ZoneConfig,
+    domain: &str,
+) -> Option<(&'a str, &'a Zone)> {
+    let domain = canonicalize_name(domain);
+    let (zone_name, zone) = find_zone(config, &domain)?;
+    if canonicalize_name(zone_name) == domain {
+        let (_, parent) = domain.split_once('.')?;
+        find_zone(config, parent)
+    } else {
+        find_delegation(config, &domain)
+            .is_some_and(|delegation| delegation.name == domain)
+            .then_some((zone_name, zone))
+    }
+}
+
+/// DS records sit on the parent side of a zone cut, so for the apex of a
+/// zone nested in another one they come from the enclosing zone.
+pub fn find_ds_record(config: &ZoneConfig, domain: &str) -> Vec<(Record, u32)> {
+    let domain = canonicalize_name(domain);
+    match ds_parent_zone(config, &domain) {
+        Some((zone_name, zone)) => {
+            let default_ttl = config.default_ttl;
+            let domain = CanonicalName::from(domain.as_str());
+            records_at(zone_name, zone, domain, Type::DS, default_ttl)
+                .map(|(record, ttl)| (record.clone(), ttl))
+                .collect()
         }
-        for record in &zone.records {
-            let combined_name_matches = if record.name.is_empty() {
-                zone_name == domain
-            } else {
-                format!("{}.{}", record.name, zone_name) == domain
-            };
-            if combined_name_matches {
-                if results.is_empty() {
-                    // Set TTL from the zone on first match
-                    ttl = zone.ttl.unwrap_or(5);
-                }
-                if record.record_type == record_type {
-                    results.push(record.clone());
+        None => find_record(config, &domain, Type::DS),
+    }
+}
+
+/// The DNAME redirecting the subtree `domain` is in, if any, as its
+/// absolute owner name, target and TTL. The closest one wins.
+pub fn find_dname(
+    config: &ZoneConfig,
+    domain: &str,
+) -> Option<(String, String, u32)> {
+    let domain = canonicalize_name(domain);
+    let (zone_name, zone) = find_zone(config, &domain)?;
+    let apex = canonicalize_name(zone_name);
+    for ancestor in names_up_to(&domain, &apex).skip(1) {
+        let dname =
+            zone.records.iter().find_map(|record| match &record.rdata {
+                RData::DNAME(target)
+                    if canonicalize_name(&absolute_name(
+                        &record.name,
+                        zone_name,
+                    )) == ancestor =>
+                {
+                    Some((target, zone.ttl_of(record, config.default_ttl)))
                 }
-            }
+                _ => None,
+            });
+        if let Some((target, ttl)) = dname {
+            return Some((ancestor.to_string(), target.clone(), ttl));
         }
     }
-    (results, ttl)
+    None
+}
+
+/// A zone cut inside the zone answering for a name, see `find_delegation`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delegation {
+    /// Where the child zone starts, in canonical form.
+    pub name: String,
+    /// The NS records there with their TTLs, for the authority section
+    /// of a referral.
+    pub name_servers: Vec<(Record, u32)>,
+}
+
+/// The delegation `domain` is at or below, if the zone answering for it
+/// hands that part of the namespace to a child zone with NS records.
+/// The cut closest to the apex wins, as everything below it is the
+/// child's. Zones configured here aren't delegations, being answered
+/// for themselves.
+pub fn find_delegation(
+    config: &ZoneConfig,
+    domain: &str,
+) -> Option<Delegation> {
+    let domain = canonicalize_name(domain);
+    let (zone_name, zone) = find_zone(config, &domain)?;
+    let apex = canonicalize_name(zone_name);
+    let below_apex: Vec<&str> =
+        names_up_to(&domain, &apex).take_while(|name| *name != apex).collect();
+    below_apex.into_iter().rev().find_map(|name| {
+        let name_servers: Vec<(Record, u32)> = records_at(
+            zone_name,
+            zone,
+            CanonicalName::from(name),
+            Type::NS,
+            config.default_ttl,
+        )
+        .map(|(record, ttl)| (record.clone(), ttl))
+        .collect();
+        (!name_servers.is_empty())
+            .then(|| Delegation { name: name.to_string(), name_servers })
+    })
+}
+
+/// `domain` and the names above it up to `apex`, closest first.
+/// Example: ("a.b.example.com", "example.com")
+///     -> "a.b.example.com", "b.example.com", "example.com"
+fn names_up_to<'a>(
+    domain: &'a str,
+    apex: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    std::iter::successors(Some(domain), move |name| {
+        if *name == apex {
+            return None;
+        }
+        name.split_once('.').map(|(_, parent)| parent)
+    })
+}
+
+/// The records of `record_type` at `domain` within a single zone.
+fn records_at<'a>(
+    zone_name: &'a str,
+    zone: &'a Zone,
+    domain: CanonicalName,
+    record_type: Type,
+    default_ttl: u32,
+) -> impl Iterator<Item = (&'a Record, u32)> + 'a {
+    zone.records
+        .iter()
+        .filter(move |record| {
+            let name = absolute_name(&record.name, zone_name);
+            record.record_type == record_type
+                && CanonicalName::from(name.as_str()) == domain
+        })
+        .map(move |record| (record, zone.ttl_of(record, default_ttl)))
 }
 
 #[cfg(test)]
@@ -129,34 +1442,789 @@ mod tests {
         let config: ZoneConfig =
             serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
 
-        let (result, ttl) = find_record(&config, "example.com", Type::A);
+        let result = find_record(&config, "example.com", Type::A);
         let expected = vec![
+            (
+                Record {
+                    name: String::new(),
+                    record_type: Type::A,
+                    rdata: RData::A("23.192.228.80".parse().unwrap()),
+                    ttl: None,
+                    active: None,
+                    subnet: None,
+                },
+                5,
+            ),
+            (
+                Record {
+                    name: String::new(),
+                    record_type: Type::A,
+                    rdata: RData::A("23.192.228.84".parse().unwrap()),
+                    ttl: None,
+                    active: None,
+                    subnet: None,
+                },
+                5,
+            ),
+        ];
+        assert_eq!(result, expected);
+
+        let result = find_record(&config, "subdomain.example.org", Type::A);
+        let expected = vec![(
             Record {
-                name: String::new(),
-                record_type: Type::A,
-                rdata: RData::A("23.192.228.80".parse().unwrap()),
-            },
-            Record {
-                name: String::new(),
+                name: "subdomain".to_string(),
                 record_type: Type::A,
-                rdata: RData::A("23.192.228.84".parse().unwrap()),
+                rdata: RData::A("172.66.157.88".parse().unwrap()),
+                ttl: None,
+                active: None,
+                subnet: None,
             },
-        ];
+            7,
+        )];
         assert_eq!(result, expected);
-        assert_eq!(ttl, 5);
-
-        let (result, ttl) =
-            find_record(&config, "subdomain.example.org", Type::A);
-        let expected = vec![Record {
-            name: "subdomain".to_string(),
-            record_type: Type::A,
-            rdata: RData::A("172.66.157.88".parse().unwrap()),
-        }];
-        assert_eq!(result, expected);
-        assert_eq!(ttl, 7);
 
-        let (result, ttl) = find_record(&config, "nonexistent.com", Type::A);
+        let result = find_record(&config, "nonexistent.com", Type::A);
         assert_eq!(result, Vec::new());
-        assert_eq!(ttl, 5);
+    }
+
+    fn problems(records: &str) -> Vec<String> {
+        let yaml = format!(
+            "
+example.com:
+  records:
+  - {{name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}}
+  - {{name: '', type: NS, address: ns.example.com}}
+{records}"
+        );
+        let config: ZoneConfig = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().err().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_validate_example_zone() {
+        let config = ZoneConfig::from_file("tests/example_zone.yaml").unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        assert!(problems("").is_empty());
+    }
+
+    #[test]
+    fn test_validate_cname_conflict() {
+        let records = "
+  - {name: 'www', type: CNAME, address: example.com}
+  - {name: 'WWW', type: A, address: 192.0.2.1}
+";
+        assert_eq!(
+            problems(records),
+            vec![
+                "example.com: 'www.example.com' has a CNAME alongside other \
+                  records"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_cname_at_apex() {
+        let records = "
+  - {name: '@', type: CNAME, address: elsewhere.example.net.}
+";
+        assert_eq!(
+            problems(records),
+            vec![
+                "example.com: CNAME at the apex, which has to have SOA and NS \
+                 records"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_duplicate_records() {
+        let records = "
+  - {name: 'www', type: A, addresses: [192.0.2.1, 192.0.2.2, 192.0.2.1]}
+";
+        assert_eq!(
+            problems(records),
+            vec!["example.com: duplicate A record for 'www.example.com'"]
+        );
+    }
+
+    #[test]
+    fn test_validate_record_outside_zone() {
+        let records = "
+  - {name: 'www.example.com.', type: A, address: 192.0.2.1}
+  - {name: 'www.example.net.', type: A, address: 192.0.2.2}
+";
+        assert_eq!(
+            problems(records),
+            vec!["example.com: 'www.example.net.' is outside the zone"]
+        );
+    }
+
+    #[test]
+    fn test_validate_missing_apex_records() {
+        let yaml = "
+example.com:
+  records:
+  - {name: 'www', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+example.net:
+  records:
+  - {name: '', type: NS, address: ns.example.net}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.validate(),
+            Err(vec![
+                "example.com: no SOA record at the apex".to_string(),
+                "example.com: no NS record at the apex".to_string(),
+                "example.net: no SOA record at the apex".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_at_sign_apex() {
+        let yaml = "
+example.net:
+  records:
+  - {name: '@', type: A, address: 192.0.2.1}
+  - {name: '', type: A, address: 192.0.2.2}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.zones["example.net"].records[0].name, "");
+
+        let result = find_record(&config, "example.net", Type::A);
+        let addresses: Vec<RData> =
+            result.into_iter().map(|(r, _)| r.rdata).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                RData::A("192.0.2.1".parse().unwrap()),
+                RData::A("192.0.2.2".parse().unwrap()),
+            ]
+        );
+        assert_eq!(absolute_name("@", "example.net"), "example.net");
+    }
+
+    #[test]
+    fn test_raw_rdata_is_validated() {
+        let err = |record: &str| {
+            let yaml = format!("example.net:\n  records:\n  - {record}\n");
+            serde_yaml::from_str::<ZoneConfig>(&yaml).unwrap_err().to_string()
+        };
+        assert!(
+            err("{name: x, type: 99, rdata_hex: 0a, rdata_base64: Cg==}")
+                .contains("Expected one of 'rdata_hex' and 'rdata_base64'")
+        );
+        assert!(
+            err("{name: x, type: TYPE1, rdata_hex: c0000201}")
+                .contains("use 'address' for A")
+        );
+        assert!(
+            err("{name: x, type: 99, rdata_hex: 0a0}")
+                .contains("Invalid rdata_hex '0a0'")
+        );
+        assert!(
+            err("{name: x, type: 99, address: a, rdata_hex: 0a}")
+                .contains("both an address and raw rdata")
+        );
+    }
+
+    #[test]
+    fn test_malformed_names_are_rejected() {
+        let err = |record: &str| {
+            let yaml = format!("example.net:\n  records:\n  - {record}\n");
+            serde_yaml::from_str::<ZoneConfig>(&yaml).unwrap_err().to_string()
+        };
+        assert!(
+            err("{name: foo..bar, type: A, address: 192.0.2.1}")
+                .contains("Name 'foo..bar' has an empty label")
+        );
+        assert!(
+            err("{name: www, type: CNAME, address: 'web..example.net.'}")
+                .contains("Record 'www': Name 'web..example.net' has an empty")
+        );
+        assert!(
+            err("{name: 'a b', type: A, address: 192.0.2.1}")
+                .contains("Label 'a b' has characters other than")
+        );
+    }
+
+    #[test]
+    fn test_svcb_params() {
+        let yaml = "
+example.com:
+  records:
+  - {name: '', type: HTTPS, address: '1 . alpn=h2,h3'}
+  - {name: 'svc', type: SVCB, address: '2 pool.example.com',
+     params: {port: 8443, ipv4hint: 192.0.2.1}}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        let records = &config.zones["example.com"].records;
+        assert_eq!(records[0].rdata.to_string(), "1 . alpn=h2,h3");
+        assert_eq!(
+            records[1].rdata.to_string(),
+            "2 pool.example.com port=8443 ipv4hint=192.0.2.1"
+        );
+
+        let yaml = "
+example.com:
+  records:
+  - {name: '', type: HTTPS, address: '1 . port=1', params: {port: 2}}
+";
+        let err = serde_yaml::from_str::<ZoneConfig>(yaml).unwrap_err();
+        assert!(err.to_string().contains("Duplicate SvcParamKey 'port'"));
+    }
+
+    #[test]
+    fn test_find_delegation() {
+        let yaml = "
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: sub, type: NS, addresses: [ns1.sub.example.com, ns2.example.net]}
+  - {name: ns1.sub, type: A, address: 192.0.2.53}
+  - {name: deeper.sub, type: NS, address: ns.deeper.example.net}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        for name in [
+            "sub.example.com",
+            "host.sub.example.com",
+            "a.deeper.Sub.example.com.",
+        ] {
+            let delegation = find_delegation(&config, name).unwrap();
+            assert_eq!(delegation.name, "sub.example.com");
+            let name_servers: Vec<String> = delegation
+                .name_servers
+                .iter()
+                .map(|(record, _)| record.rdata.to_string())
+                .collect();
+            assert_eq!(
+                name_servers,
+                ["ns1.sub.example.com", "ns2.example.net"]
+            );
+        }
+        // the apex NS records are the zone's own, not a delegation
+        assert_eq!(find_delegation(&config, "example.com"), None);
+        assert_eq!(find_delegation(&config, "www.example.com"), None);
+        assert_eq!(find_delegation(&config, "example.org"), None);
+        assert_eq!(
+            names_up_to("a.b.example.com", "example.com").collect::<Vec<_>>(),
+            ["a.b.example.com", "b.example.com", "example.com"]
+        );
+    }
+
+    #[test]
+    fn test_hinfo_fields() {
+        let yaml = "
+example.com:
+  records:
+  - {name: host, type: HINFO, cpu: RISC-V, os: Linux}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        let records = &config.zones["example.com"].records;
+        assert_eq!(records[0].record_type, Type::HINFO);
+        assert_eq!(records[0].rdata.to_string(), "\"RISC-V\" \"Linux\"");
+
+        for (record, error) in [
+            ("{name: host, type: HINFO, cpu: RISC-V}", "needs both"),
+            (
+                "{name: host, type: HINFO, cpu: a, os: b, address: c}",
+                "not an address",
+            ),
+            ("{name: host, type: A, address: 192.0.2.1, os: b}", "isn't HINFO"),
+        ] {
+            let yaml = format!("example.com:\n  records:\n  - {record}\n");
+            let err = serde_yaml::from_str::<ZoneConfig>(&yaml).unwrap_err();
+            assert!(err.to_string().contains(error), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_time_windows() {
+        let at = |hours: u64, minutes: u64| {
+            UNIX_EPOCH
+                + std::time::Duration::from_secs((hours * 60 + minutes) * 60)
+        };
+        let day: TimeWindow = "08:00-18:00".parse().unwrap();
+        assert!(!day.contains(at(7, 59)));
+        assert!(day.contains(at(8, 0)));
+        assert!(!day.contains(at(18, 0)));
+        let night: TimeWindow = "18:00-08:00".parse().unwrap();
+        assert!(night.contains(at(23, 0)));
+        assert!(night.contains(at(24 + 7, 59)));
+        assert!(!night.contains(at(12, 0)));
+        assert!("8-18".parse::<TimeWindow>().is_err());
+        assert!("08:00-24:00".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn test_multiple_addresses() {
+        let yaml = "
+example.net:
+  records:
+  - {name: 'www', type: A, addresses: [192.0.2.1, 192.0.2.2, 192.0.2.3]}
+  - {name: 'www', type: AAAA, address: '2001:db8::1'}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let result = find_record(&config, "www.example.net", Type::A);
+        let addresses: Vec<RData> =
+            result.into_iter().map(|(r, _)| r.rdata).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                RData::A("192.0.2.1".parse().unwrap()),
+                RData::A("192.0.2.2".parse().unwrap()),
+                RData::A("192.0.2.3".parse().unwrap()),
+            ]
+        );
+        let result = find_record(&config, "www.example.net", Type::AAAA);
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_multiple_addresses_are_validated() {
+        let yaml = "
+example.net:
+  records:
+  - {name: 'www', type: A, addresses: [192.0.2.1, '2001:db8::1']}
+";
+        let err = serde_yaml::from_str::<ZoneConfig>(yaml).unwrap_err();
+        assert!(err.to_string().contains("Invalid IPv4 address '2001:db8::1'"));
+
+        let yaml = "
+example.net:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1, addresses: [192.0.2.2]}
+";
+        let err = serde_yaml::from_str::<ZoneConfig>(yaml).unwrap_err();
+        assert!(err.to_string().contains("both 'address' and 'addresses'"));
+    }
+
+    #[test]
+    fn test_invalid_rule_pattern() {
+        let yaml = "
+rules:
+- {pattern: 'db-(\\d+', type: A, address: 10.0.0.5}
+";
+        let err = serde_yaml::from_str::<ZoneConfig>(yaml).unwrap_err();
+        assert!(err.to_string().contains("Invalid pattern 'db-(\\d+'"));
+    }
+
+    #[test]
+    fn test_carry_serials_from() {
+        let load = |serial: u32, address: &str| -> ZoneConfig {
+            serde_yaml::from_str(&format!(
+                "
+example.com:
+  auto_serial: true
+  records:
+  - {{name: '', type: SOA, address: 'ns. host. {serial} 1 1 1 1'}}
+  - {{name: '', type: A, address: {address}}}
+"
+            ))
+            .unwrap()
+        };
+        let serial = |config: &ZoneConfig| {
+            config.zones["example.com"].soa_serial().unwrap()
+        };
+        let old = load(10, "192.0.2.1");
+
+        // unchanged records keep the previously served serial
+        let mut same = load(7, "192.0.2.1");
+        same.carry_serials_from(&old);
+        assert_eq!(serial(&same), 10);
+
+        // changed records with a stale serial get bumped
+        let mut stale = load(10, "192.0.2.2");
+        stale.carry_serials_from(&old);
+        assert_eq!(serial(&stale), 11);
+
+        // a serial bumped in the file is respected
+        let mut bumped = load(20, "192.0.2.2");
+        bumped.carry_serials_from(&old);
+        assert_eq!(serial(&bumped), 20);
+
+        // serials wrap around
+        let mut wrapped = load(u32::MAX, "192.0.2.1");
+        let mut next = load(u32::MAX, "192.0.2.2");
+        next.carry_serials_from(&wrapped);
+        assert_eq!(serial(&next), 0);
+
+        // without auto_serial the file is served as is
+        wrapped.zones.get_mut("example.com").unwrap().auto_serial = false;
+        let mut manual = load(3, "192.0.2.2");
+        manual.zones.get_mut("example.com").unwrap().auto_serial = false;
+        manual.carry_serials_from(&wrapped);
+        assert_eq!(serial(&manual), 3);
+    }
+
+    #[test]
+    fn test_add_reverse_records() {
+        let yaml = "
+auto_ptr: true
+example.com:
+  ttl: 60
+  records:
+  - {name: 'host', type: A, address: 192.0.2.1}
+  - {name: 'host', type: A, address: 192.0.2.1, subnet: 10.0.0.0/8}
+  - {name: 'named', type: A, address: 192.0.2.2}
+  - {name: 'v6', type: AAAA, address: '2001:db8::1'}
+  - {name: 'elsewhere', type: A, address: 198.51.100.1}
+2.0.192.in-addr.arpa:
+  records:
+  - {name: '2', type: PTR, address: by-hand.example.com}
+8.b.d.0.1.0.0.2.ip6.arpa:
+  records: []
+";
+        let mut config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        config.add_reverse_records();
+        let names = |zone_name: &str| -> Vec<(String, String)> {
+            config.zones[zone_name]
+                .records
+                .iter()
+                .map(|record| (record.name.clone(), record.rdata.to_string()))
+                .collect()
+        };
+        let pointer =
+            |name: &str, target: &str| (name.to_string(), target.to_string());
+        // one per record, the one written by hand kept alone
+        assert_eq!(
+            names("2.0.192.in-addr.arpa"),
+            vec![
+                pointer("2", "by-hand.example.com"),
+                pointer("1.2.0.192.in-addr.arpa.", "host.example.com"),
+                pointer("1.2.0.192.in-addr.arpa.", "host.example.com"),
+            ]
+        );
+        assert_eq!(
+            names("8.b.d.0.1.0.0.2.ip6.arpa"),
+            vec![pointer(
+                &format!("{}.", reverse_name("2001:db8::1".parse().unwrap())),
+                "v6.example.com"
+            )]
+        );
+        let record = &config.zones["2.0.192.in-addr.arpa"].records[1];
+        assert_eq!(record.ttl, Some(60));
+        assert_eq!(record.subnet, None);
+        let tagged = &config.zones["2.0.192.in-addr.arpa"].records[2];
+        assert_eq!(tagged.subnet, Some("10.0.0.0/8".parse().unwrap()));
+        config.add_reverse_records();
+        assert_eq!(config.zones["2.0.192.in-addr.arpa"].records.len(), 3);
+
+        // off by default
+        let mut config: ZoneConfig =
+            serde_yaml::from_str(&yaml.replace("auto_ptr: true", "")).unwrap();
+        config.add_reverse_records();
+        assert_eq!(config.zones["8.b.d.0.1.0.0.2.ip6.arpa"].records, []);
+    }
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name("192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let lookup = |name: &str| match name {
+            "ADDRESS" => Some("192.0.2.1".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let expand = |text| expand_variables(text, lookup);
+        assert_eq!(
+            expand("{address: ${ADDRESS}, ttl: ${TTL:-300}}"),
+            Ok("{address: 192.0.2.1, ttl: 300}".to_string())
+        );
+        // set but empty isn't unset, and a fallback may be empty too
+        assert_eq!(expand("[${EMPTY:-x}${TTL:-}]"), Ok("[]".to_string()));
+        assert_eq!(
+            expand("${ADDRESS:-192.0.2.2} $ {ADDRESS} $HOME"),
+            Ok("192.0.2.1 $ {ADDRESS} $HOME".to_string())
+        );
+        assert_eq!(
+            expand("address: ${MISSING}"),
+            Err("Environment variable MISSING is not set".to_string())
+        );
+
+        // applied by from_str, before the YAML is parsed
+        let err = "example.com:\n  records: ${TOY_DNS_SERVER_UNSET_VARIABLE}"
+            .parse::<ZoneConfig>()
+            .unwrap_err();
+        assert!(err.to_string().contains("TOY_DNS_SERVER_UNSET_VARIABLE"));
+        let config: ZoneConfig =
+            "${TOY_DNS_SERVER_UNSET_VARIABLE:-example.com}:\n  records: []"
+                .parse()
+                .unwrap();
+        assert!(config.zones.contains_key("example.com"));
+    }
+
+    #[test]
+    fn test_find_zone() {
+        let yaml = "
+example.com:
+  records: []
+sub.example.com:
+  refuse_types: [MX, TYPE99]
+  records: []
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let (name, _) = find_zone(&config, "example.com").unwrap();
+        assert_eq!(name, "example.com");
+        let (name, zone) = find_zone(&config, "host.sub.example.com").unwrap();
+        assert_eq!(name, "sub.example.com");
+        assert_eq!(zone.refuse_types, vec![Type::MX, Type::Other(99)]);
+        assert!(find_zone(&config, "notexample.com").is_none());
+        let (name, _) = find_zone(&config, "Host.Sub.EXAMPLE.com.").unwrap();
+        assert_eq!(name, "sub.example.com");
+    }
+
+    #[test]
+    fn test_most_specific_zone_answers() {
+        let yaml = "
+example.com:
+  ttl: 10
+  records:
+  - {name: 'host.sub', type: A, address: 192.0.2.1}
+sub.example.com:
+  ttl: 20
+  records:
+  - {name: 'host', type: A, address: 192.0.2.2}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let result = find_record(&config, "host.sub.example.com", Type::A);
+        assert_eq!(result.len(), 1);
+        let (record, ttl) = &result[0];
+        assert_eq!(record.rdata, RData::A("192.0.2.2".parse().unwrap()));
+        assert_eq!(*ttl, 20);
+    }
+
+    #[test]
+    fn test_canonical_records_order() {
+        let yaml = "
+example.org:
+  records:
+  - {name: 'b', type: A, address: 192.0.2.2}
+  - {name: 'a.b', type: A, address: 192.0.2.3}
+  - {name: 'A', type: AAAA, address: '2001:db8::1'}
+  - {name: 'a', type: A, address: 192.0.2.1}
+  - {name: '', type: NS, address: ns.example.org}
+  - {name: '', type: A, address: 192.0.2.4}
+  - {name: 'zz.example.org.', type: A, address: 192.0.2.5}
+  - {name: 'p', type: A, address: 192.0.2.6}
+  - {name: 'example.org.', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '@', type: AAAA, address: '2001:db8::4'}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        let zone = &config.zones["example.org"];
+
+        let order: Vec<(&str, Type)> = zone
+            .canonical_records("example.org")
+            .into_iter()
+            .map(|r| (r.name.as_str(), r.record_type))
+            .collect();
+        // absolute owners sort among the relative ones, not by their labels
+        assert_eq!(
+            order,
+            vec![
+                ("", Type::A),
+                ("", Type::NS),
+                ("example.org.", Type::SOA),
+                ("", Type::AAAA),
+                ("a", Type::A),
+                ("A", Type::AAAA),
+                ("b", Type::A),
+                ("a.b", Type::A),
+                ("p", Type::A),
+                ("zz.example.org.", Type::A),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_global_default_ttl() {
+        let yaml = "
+default_ttl: 300
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1}
+  - {name: 'mail', type: A, address: 192.0.2.25, ttl: 60}
+example.net:
+  ttl: 10
+  records:
+  - {name: 'www', type: A, address: 192.0.2.2}
+";
+        let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.default_ttl, 300);
+        assert!(!config.zones.contains_key("default_ttl"));
+        let ttls = |name: &str| -> Vec<u32> {
+            find_record(&config, name, Type::A)
+                .into_iter()
+                .map(|(_, ttl)| ttl)
+                .collect()
+        };
+        assert_eq!(ttls("www.example.com"), vec![300]);
+        assert_eq!(ttls("mail.example.com"), vec![60]);
+        assert_eq!(ttls("www.example.net"), vec![10]);
+
+        let config: ZoneConfig = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(config.default_ttl, 5);
+    }
+
+    #[test]
+    fn test_bound_ttl() {
+        let mut config: ZoneConfig = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(config.bound_ttl(5), 5);
+        config.min_ttl = Some(60);
+        config.max_ttl = Some(3600);
+        assert_eq!(config.bound_ttl(5), 60);
+        assert_eq!(config.bound_ttl(300), 300);
+        assert_eq!(config.bound_ttl(86400), 3600);
+        assert_eq!(config.validate(), Ok(()));
+
+        config.min_ttl = Some(7200);
+        assert_eq!(
+            config.validate(),
+            Err(vec!["min_ttl 7200 is above max_ttl 3600".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_matching_records_iterator() {
+        let mut config =
+            ZoneConfig::from_file("tests/example_zone.yaml").unwrap();
+        config.rules = serde_yaml::from_str(
+            "[{pattern: '^rule\\.example\\.com$', type: A, address: 192.0.2.9}]",
+        )
+        .unwrap();
+        for (domain, record_type) in [
+            ("example.com", Type::A),
+            ("example.com", Type::AAAA),
+            ("subdomain.example.org", Type::A),
+            ("nonexistent.com", Type::A),
+        ] {
+            let lazy: Vec<(Record, u32)> =
+                matching_records(&config, domain, record_type)
+                    .map(|(record, ttl)| (record.clone(), ttl))
+                    .collect();
+            assert_eq!(lazy, find_record(&config, domain, record_type));
+        }
+
+        let mut matches = matching_records(&config, "example.com", Type::A);
+        let (first, _) = matches.next().unwrap();
+        assert_eq!(first.rdata, RData::A("23.192.228.80".parse().unwrap()));
+
+        // rules are left to find_record
+        assert_eq!(
+            matching_records(&config, "rule.example.com", Type::A).count(),
+            0
+        );
+        assert_eq!(find_record(&config, "rule.example.com", Type::A).len(), 1);
+    }
+
+    #[test]
+    fn test_load_through_helpers() {
+        let config = ZoneConfig::from_file("tests/example_zone.yaml").unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        let yaml = std::fs::read_to_string("tests/example_zone.yaml").unwrap();
+        let parsed: ZoneConfig = yaml.parse().unwrap();
+        let mut zone_names: Vec<_> = config.zones.keys().collect();
+        zone_names.sort();
+        let mut parsed_names: Vec<_> = parsed.zones.keys().collect();
+        parsed_names.sort();
+        assert_eq!(zone_names, parsed_names);
+        assert_eq!(
+            find_record(&config, "example.com", Type::A),
+            find_record(&parsed, "example.com", Type::A)
+        );
+
+        let missing = ZoneConfig::from_file("tests/nonexistent.yaml");
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let invalid = ZoneConfig::from_file("Cargo.toml").unwrap_err();
+        assert_eq!(invalid.kind(), io::ErrorKind::InvalidData);
+        assert!(invalid.to_string().starts_with("Cargo.toml: "));
+        assert!("example.com: [".parse::<ZoneConfig>().is_err());
+    }
+
+    #[test]
+    fn test_json_config() {
+        let yaml = ZoneConfig::from_file("tests/example_zone.yaml").unwrap();
+        let json = ZoneConfig::from_file("tests/example_zone.json").unwrap();
+        assert_eq!(json.zones, yaml.zones);
+        assert_eq!(json.default_ttl, yaml.default_ttl);
+        assert_eq!(json.answer_order, yaml.answer_order);
+
+        let json = r#"{
+            "default_ttl": 60,
+            "answer_order": "rotate",
+            "example.net": {"records": [
+                {"name": "x", "type": 99, "rdata_hex": "0a"},
+                {"name": "svc", "type": "SVCB", "address": "1 .",
+                 "params": {"port": 8443, "alpn": "h2"}}
+            ]}
+        }"#;
+        let yaml = "
+default_ttl: 60
+answer_order: rotate
+example.net:
+  records:
+  - {name: x, type: 99, rdata_hex: 0a}
+  - {name: svc, type: SVCB, address: 1 ., params: {port: 8443, alpn: h2}}
+";
+        let json = ZoneConfig::from_json_str(json).unwrap();
+        let yaml: ZoneConfig = yaml.parse().unwrap();
+        assert_eq!(json.zones, yaml.zones);
+        assert_eq!(json.default_ttl, 60);
+        assert_eq!(json.answer_order, AnswerOrder::Rotate);
+
+        let invalid = ZoneConfig::from_file("Cargo.lock").unwrap_err();
+        assert_eq!(invalid.kind(), io::ErrorKind::InvalidData);
+        assert!(ZoneConfig::from_json_str("{\"example.com\": [").is_err());
+    }
+
+    #[test]
+    fn test_rotation_counts_per_record_set() {
+        use crate::packet::DnsPacket;
+        use crate::packet::protocol_class::Class;
+        use crate::packet::question::DnsQuestion;
+
+        let config: ZoneConfig = r"
+answer_order: rotate
+rules:
+- {pattern: '^host\d+\.dyn\.example$', type: A, address: 192.0.2.1}
+- {pattern: '^host\d+\.dyn\.example$', type: A, address: 192.0.2.2}
+"
+        .parse()
+        .unwrap();
+        let first_address = |qname: &str| {
+            let query = DnsPacket::builder()
+                .add_question(DnsQuestion {
+                    qname: qname.to_string(),
+                    qtype: Type::A,
+                    qclass: Class::IN,
+                })
+                .build();
+            let reply = crate::construct_reply(&config, &query).unwrap();
+            reply.unwrap().answers[0].rdata.clone()
+        };
+
+        // every name the rules match takes the next turn of the same set
+        let firsts: Vec<RData> = (1..=4)
+            .map(|i| first_address(&format!("host{i}.dyn.example")))
+            .collect();
+        let (a, b) = (firsts[0].clone(), firsts[1].clone());
+        assert_ne!(a, b);
+        assert_eq!(firsts, [a.clone(), b.clone(), a, b]);
+        assert_eq!(config.rotation.0.lock().unwrap().len(), 1);
+        assert!(config.clone().rotation.0.lock().unwrap().is_empty());
     }
 }
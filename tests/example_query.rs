@@ -1,8 +1,12 @@
 use std::fs;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
 use toy_dns_server::{
-    Class, DnsAnswer, DnsHeader, DnsPacket, DnsQuestion, OpCode, RCode, RData,
-    Type, ZoneConfig, construct_reply, parse_dns_query,
+    AXFR_TYPE, AnswerOrder, Class, ClientSubnet, DnsAnswer, DnsHeader,
+    DnsPacket, DnsQuestion, EdnsOpt, FakeClock, OpCode, RCode, RData,
+    SectionOffsets, Sinkhole, Type, ZoneConfig, axfr_answers, construct_reply,
+    construct_reply_with_clock, parse_dns_query, parse_dns_query_detailed,
+    parse_dns_query_strict, transfer_replies,
 };
 
 #[test]
@@ -35,7 +39,10 @@ fn test_packet_parsing() {
             qclass: Class::IN,
         }],
         answers: vec![],
-        unparsed: vec![0, 0, 41, 5, 192, 0, 0, 0, 0, 0, 0],
+        authorities: vec![],
+        additionals: vec![],
+        unparsed: vec![],
+        edns: Some(EdnsOpt { udp_payload_size: 1472, ..EdnsOpt::default() }),
     };
 
     assert_eq!(packet, expected);
@@ -52,18 +59,57 @@ fn test_packet_serialization_roundtrip() {
     assert_eq!(serialized.as_slice(), data);
 }
 
+#[test]
+fn test_strict_parsing_rejects_trailing_bytes() {
+    let data = fs::read("tests/example.query.bin")
+        .expect("Failed to read example.query.bin");
+
+    // the OPT record at the end of the additional section is consumed
+    let query = parse_dns_query_strict(&data).unwrap();
+    assert!(query.edns.is_some());
+
+    let mut garbage = data.clone();
+    garbage.extend_from_slice(b"\xde\xad");
+    assert!(parse_dns_query(&garbage).is_ok());
+    let err = parse_dns_query_strict(&garbage).unwrap_err();
+    assert_eq!(err.to_string(), "Trailing bytes after the parsed sections: 13");
+}
+
+#[test]
+fn test_section_offsets() {
+    let data = fs::read("tests/example.query.bin")
+        .expect("Failed to read example.query.bin");
+
+    let (packet, offsets) = parse_dns_query_detailed(&data).unwrap();
+    assert_eq!(packet, parse_dns_query(&data).unwrap());
+    assert_eq!(
+        offsets,
+        SectionOffsets {
+            questions: 12..29,
+            answers: 29..29,
+            authority: Some(29..29),
+            additional: Some(29..40),
+        }
+    );
+    assert_eq!(data.len(), 40);
+
+    // a cut off OPT record can't be walked
+    let (_, offsets) = parse_dns_query_detailed(&data[..35]).unwrap();
+    assert_eq!(offsets.authority, Some(29..29));
+    assert_eq!(offsets.additional, None);
+}
+
 #[test]
 fn test_reply_to_example() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
     let data = fs::read("tests/example.query.bin")
         .expect("Failed to read example.query.bin");
     let query = parse_dns_query(&data).expect("Failed to parse DNS query");
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let expected = DnsPacket {
         header: DnsHeader {
@@ -80,8 +126,8 @@ fn test_reply_to_example() {
             rcode: RCode::NoError,
             qd_count: 1,
             an_count: 2,
-            ns_count: 0,
-            ar_count: 0,
+            ns_count: 2,
+            ar_count: 1, // OPT, as the query has one,
         },
         questions: vec![DnsQuestion {
             qname: "example.com".to_string(),
@@ -104,7 +150,25 @@ fn test_reply_to_example() {
                 rdata: RData::A(Ipv4Addr::new(23, 192, 228, 84)),
             },
         ],
+        authorities: vec![
+            DnsAnswer {
+                name: "example.com".to_string(),
+                rclass: Class::IN,
+                rtype: Type::NS,
+                ttl: 5,
+                rdata: RData::NS("a.iana-servers.net".to_string()),
+            },
+            DnsAnswer {
+                name: "example.com".to_string(),
+                rclass: Class::IN,
+                rtype: Type::NS,
+                ttl: 5,
+                rdata: RData::NS("b.iana-servers.net".to_string()),
+            },
+        ],
+        additionals: vec![],
         unparsed: Vec::new(),
+        edns: Some(EdnsOpt::default()),
     };
 
     assert_eq!(reply, expected);
@@ -112,16 +176,15 @@ fn test_reply_to_example() {
 
 #[test]
 fn test_reply_to_example_serialization_roundtrip() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
     let data = fs::read("tests/example.query.bin")
         .expect("Failed to read example.query.bin");
     let query = parse_dns_query(&data).expect("Failed to parse DNS query");
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let reply_serialized = reply.serialize();
     let reply_deserialized = parse_dns_query(&reply_serialized).unwrap();
@@ -131,40 +194,22 @@ fn test_reply_to_example_serialization_roundtrip() {
 
 #[test]
 fn test_reply_aaaa_query() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
-    let query = DnsPacket {
-        header: DnsHeader {
-            transaction_id: 0x1234,
-            response: false,
-            opcode: OpCode::QUERY,
-            authoritative_answer: false,
-            truncation: false,
-            recursion_desired: true,
-            recursion_available: false,
-            _reserved: false,
-            authenticated_data: false,
-            checking_disabled: false,
-            rcode: RCode::NoError,
-            qd_count: 1,
-            an_count: 0,
-            ns_count: 0,
-            ar_count: 0,
-        },
-        questions: vec![DnsQuestion {
+    let query = DnsPacket::builder()
+        .transaction_id(0x1234)
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
             qname: "example.com".to_string(),
             qtype: Type::AAAA,
             qclass: Class::IN,
-        }],
-        answers: vec![],
-        unparsed: vec![],
-    };
+        })
+        .build();
 
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let expected = DnsPacket {
         header: DnsHeader {
@@ -181,7 +226,7 @@ fn test_reply_aaaa_query() {
             rcode: RCode::NoError,
             qd_count: 1,
             an_count: 2,
-            ns_count: 0,
+            ns_count: 2,
             ar_count: 0,
         },
         questions: vec![DnsQuestion {
@@ -209,7 +254,25 @@ fn test_reply_aaaa_query() {
                 )),
             },
         ],
+        authorities: vec![
+            DnsAnswer {
+                name: "example.com".to_string(),
+                rclass: Class::IN,
+                rtype: Type::NS,
+                ttl: 5,
+                rdata: RData::NS("a.iana-servers.net".to_string()),
+            },
+            DnsAnswer {
+                name: "example.com".to_string(),
+                rclass: Class::IN,
+                rtype: Type::NS,
+                ttl: 5,
+                rdata: RData::NS("b.iana-servers.net".to_string()),
+            },
+        ],
+        additionals: vec![],
         unparsed: vec![],
+        edns: None,
     };
 
     assert_eq!(reply, expected);
@@ -217,40 +280,22 @@ fn test_reply_aaaa_query() {
 
 #[test]
 fn test_reply_ns_query() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
-    let query = DnsPacket {
-        header: DnsHeader {
-            transaction_id: 0x1234,
-            response: false,
-            opcode: OpCode::QUERY,
-            authoritative_answer: false,
-            truncation: false,
-            recursion_desired: true,
-            recursion_available: false,
-            _reserved: false,
-            authenticated_data: false,
-            checking_disabled: false,
-            rcode: RCode::NoError,
-            qd_count: 1,
-            an_count: 0,
-            ns_count: 0,
-            ar_count: 0,
-        },
-        questions: vec![DnsQuestion {
+    let query = DnsPacket::builder()
+        .transaction_id(0x1234)
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
             qname: "example.com".to_string(),
             qtype: Type::NS,
             qclass: Class::IN,
-        }],
-        answers: vec![],
-        unparsed: vec![],
-    };
+        })
+        .build();
 
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let expected = DnsPacket {
         header: DnsHeader {
@@ -291,7 +336,10 @@ fn test_reply_ns_query() {
                 rdata: RData::NS("b.iana-servers.net.".to_string()),
             },
         ],
+        authorities: vec![],
+        additionals: vec![],
         unparsed: vec![],
+        edns: None,
     };
 
     assert_eq!(reply, expected);
@@ -299,40 +347,22 @@ fn test_reply_ns_query() {
 
 #[test]
 fn test_reply_example_org_custom_ttl() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
-    let query = DnsPacket {
-        header: DnsHeader {
-            transaction_id: 0x5678,
-            response: false,
-            opcode: OpCode::QUERY,
-            authoritative_answer: false,
-            truncation: false,
-            recursion_desired: true,
-            recursion_available: false,
-            _reserved: false,
-            authenticated_data: false,
-            checking_disabled: false,
-            rcode: RCode::NoError,
-            qd_count: 1,
-            an_count: 0,
-            ns_count: 0,
-            ar_count: 0,
-        },
-        questions: vec![DnsQuestion {
+    let query = DnsPacket::builder()
+        .transaction_id(0x5678)
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
             qname: "example.org".to_string(),
             qtype: Type::A,
             qclass: Class::IN,
-        }],
-        answers: vec![],
-        unparsed: vec![],
-    };
+        })
+        .build();
 
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let expected = DnsPacket {
         header: DnsHeader {
@@ -349,7 +379,7 @@ fn test_reply_example_org_custom_ttl() {
             rcode: RCode::NoError,
             qd_count: 1,
             an_count: 1,
-            ns_count: 0,
+            ns_count: 1,
             ar_count: 0,
         },
         questions: vec![DnsQuestion {
@@ -364,7 +394,16 @@ fn test_reply_example_org_custom_ttl() {
             ttl: 7,
             rdata: RData::A(Ipv4Addr::new(104, 20, 26, 109)),
         }],
+        authorities: vec![DnsAnswer {
+            name: "example.org".to_string(),
+            rclass: Class::IN,
+            rtype: Type::NS,
+            ttl: 7,
+            rdata: RData::NS("ns.example.org".to_string()),
+        }],
+        additionals: vec![],
         unparsed: vec![],
+        edns: None,
     };
 
     assert_eq!(reply, expected);
@@ -372,40 +411,22 @@ fn test_reply_example_org_custom_ttl() {
 
 #[test]
 fn test_reply_subdomain_query() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
-    let query = DnsPacket {
-        header: DnsHeader {
-            transaction_id: 0x9abc,
-            response: false,
-            opcode: OpCode::QUERY,
-            authoritative_answer: false,
-            truncation: false,
-            recursion_desired: true,
-            recursion_available: false,
-            _reserved: false,
-            authenticated_data: false,
-            checking_disabled: false,
-            rcode: RCode::NoError,
-            qd_count: 1,
-            an_count: 0,
-            ns_count: 0,
-            ar_count: 0,
-        },
-        questions: vec![DnsQuestion {
+    let query = DnsPacket::builder()
+        .transaction_id(0x9abc)
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
             qname: "subdomain.example.org".to_string(),
             qtype: Type::A,
             qclass: Class::IN,
-        }],
-        answers: vec![],
-        unparsed: vec![],
-    };
+        })
+        .build();
 
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let expected = DnsPacket {
         header: DnsHeader {
@@ -422,7 +443,7 @@ fn test_reply_subdomain_query() {
             rcode: RCode::NoError,
             qd_count: 1,
             an_count: 1,
-            ns_count: 0,
+            ns_count: 1,
             ar_count: 0,
         },
         questions: vec![DnsQuestion {
@@ -437,48 +458,61 @@ fn test_reply_subdomain_query() {
             ttl: 7,
             rdata: RData::A(Ipv4Addr::new(172, 66, 157, 88)),
         }],
+        authorities: vec![DnsAnswer {
+            name: "example.org".to_string(),
+            rclass: Class::IN,
+            rtype: Type::NS,
+            ttl: 7,
+            rdata: RData::NS("ns.example.org".to_string()),
+        }],
+        additionals: vec![],
         unparsed: vec![],
+        edns: None,
     };
 
     assert_eq!(reply, expected);
 }
 
+#[test]
+fn test_reply_echoes_query_name_case() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let query = DnsPacket::builder()
+        .add_question(DnsQuestion {
+            qname: "ExAmPle.CoM".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 2);
+    assert_eq!(reply.questions[0].qname, "ExAmPle.CoM");
+    assert!(reply.answers.iter().all(|answer| answer.name == "ExAmPle.CoM"));
+    // the question goes back byte for byte, as 0x20 checks expect
+    let wire = reply.serialize();
+    assert_eq!(&wire[12..25], b"\x07ExAmPle\x03CoM\x00");
+}
+
 #[test]
 fn test_reply_cname_query() {
-    let yaml = fs::read_to_string("tests/example_zone.yaml")
-        .expect("Failed to read example zone file");
-    let config: ZoneConfig =
-        serde_yaml::from_str(&yaml).expect("Failed to parse zone config");
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
 
-    let query = DnsPacket {
-        header: DnsHeader {
-            transaction_id: 0xdef0,
-            response: false,
-            opcode: OpCode::QUERY,
-            authoritative_answer: false,
-            truncation: false,
-            recursion_desired: true,
-            recursion_available: false,
-            _reserved: false,
-            authenticated_data: false,
-            checking_disabled: false,
-            rcode: RCode::NoError,
-            qd_count: 1,
-            an_count: 0,
-            ns_count: 0,
-            ar_count: 0,
-        },
-        questions: vec![DnsQuestion {
+    let query = DnsPacket::builder()
+        .transaction_id(0xdef0)
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
             qname: "alias.example.org".to_string(),
             qtype: Type::CNAME,
             qclass: Class::IN,
-        }],
-        answers: vec![],
-        unparsed: vec![],
-    };
+        })
+        .build();
 
-    let reply =
-        construct_reply(&config, &query).expect("Should construct a reply");
+    let reply = construct_reply(&config, &query)
+        .unwrap()
+        .expect("Should construct a reply");
 
     let expected = DnsPacket {
         header: DnsHeader {
@@ -495,7 +529,7 @@ fn test_reply_cname_query() {
             rcode: RCode::NoError,
             qd_count: 1,
             an_count: 1,
-            ns_count: 0,
+            ns_count: 1,
             ar_count: 0,
         },
         questions: vec![DnsQuestion {
@@ -510,8 +544,1565 @@ fn test_reply_cname_query() {
             ttl: 7,
             rdata: RData::CNAME("something-else.example.org".to_string()),
         }],
+        authorities: vec![DnsAnswer {
+            name: "example.org".to_string(),
+            rclass: Class::IN,
+            rtype: Type::NS,
+            ttl: 7,
+            rdata: RData::NS("ns.example.org".to_string()),
+        }],
+        additionals: vec![],
         unparsed: vec![],
+        edns: None,
     };
 
     assert_eq!(reply, expected);
 }
+
+#[test]
+fn test_axfr_answers_stable_order() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+
+    let serialize = |answers: &[DnsAnswer]| -> Vec<u8> {
+        answers.iter().flat_map(DnsAnswer::serialize).collect()
+    };
+    let first = axfr_answers(&config, "example.com").unwrap();
+    let second = axfr_answers(&config, "example.com").unwrap();
+    assert_eq!(serialize(&first), serialize(&second));
+
+    let types: Vec<Type> = first.iter().map(|a| a.rtype).collect();
+    assert_eq!(
+        types,
+        vec![
+            Type::A,
+            Type::A,
+            Type::NS,
+            Type::NS,
+            Type::SOA,
+            Type::AAAA,
+            Type::AAAA
+        ]
+    );
+
+    let names: Vec<String> = axfr_answers(&config, "example.org")
+        .unwrap()
+        .into_iter()
+        .map(|a| a.name)
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "example.org",
+            "example.org",
+            "example.org",
+            "alias.example.org",
+            "subdomain.example.org"
+        ]
+    );
+
+    assert_eq!(axfr_answers(&config, "example.net"), None);
+}
+
+#[test]
+fn test_transfer_replies() {
+    let mut yaml = String::from(
+        "
+allow_transfer: [192.0.2.0/24]
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+",
+    );
+    for i in 0..1000 {
+        yaml += &format!(
+            "  - {{name: host{i}, type: A, address: 192.0.2.{}}}\n",
+            i % 256
+        );
+    }
+    let config: ZoneConfig = yaml.parse().unwrap();
+    let query = |qname: &str, qtype| {
+        DnsPacket::builder()
+            .transaction_id(7)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build()
+    };
+    let axfr = Type::Other(AXFR_TYPE);
+    let secondary = "192.0.2.53".parse().unwrap();
+
+    let messages =
+        transfer_replies(&config, &query("example.com", axfr), secondary);
+    assert!(messages.len() > 1, "{} messages", messages.len());
+    assert_eq!(messages[0].questions.len(), 1);
+    assert!(messages[1..].iter().all(|m| m.questions.is_empty()));
+    for message in &messages {
+        assert!(message.serialize().len() < 65535);
+        assert!(message.header.authoritative_answer);
+    }
+    let answers: Vec<&DnsAnswer> =
+        messages.iter().flat_map(|m| &m.answers).collect();
+    assert_eq!(answers.len(), 1003);
+    assert_eq!(answers[0].rtype, Type::SOA);
+    assert_eq!(answers[1002].rtype, Type::SOA);
+
+    let not_ours =
+        transfer_replies(&config, &query("example.net", axfr), secondary);
+    assert_eq!(not_ours.len(), 1);
+    assert_eq!(not_ours[0].header.rcode, RCode::NotAuth);
+
+    let stranger = "198.51.100.1".parse().unwrap();
+    let refused =
+        transfer_replies(&config, &query("example.com", axfr), stranger);
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0].header.rcode, RCode::Refused);
+
+    // there's no room for a whole zone in a datagram
+    let udp =
+        construct_reply(&config, &query("example.com", axfr)).unwrap().unwrap();
+    assert_eq!(udp.header.rcode, RCode::NotImp);
+    assert!(udp.answers.is_empty());
+}
+
+#[test]
+fn test_canary_answer_changes() {
+    let mut config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    config.canary_name = Some("canary.monitoring.".to_string());
+
+    let query = DnsPacket::builder()
+        .transaction_id(0xcafe)
+        .add_question(DnsQuestion {
+            qname: "canary.monitoring".to_string(),
+            qtype: Type::TXT,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let canary_txt = |reply: DnsPacket| -> Vec<String> {
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(reply.answers[0].ttl, 0);
+        match &reply.answers[0].rdata {
+            RData::TXT(strings) => strings.clone(),
+            other => panic!("Expected TXT, got {other}"),
+        }
+    };
+    let first = canary_txt(construct_reply(&config, &query).unwrap().unwrap());
+    let second = canary_txt(construct_reply(&config, &query).unwrap().unwrap());
+    assert!(first[0].starts_with("seq="));
+    assert!(first[1].starts_with("time="));
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_reply_refused_type() {
+    let yaml = "
+example.net:
+  refuse_types: [MX]
+  records:
+  - {name: '', type: A, address: 192.0.2.1}
+";
+    let config: ZoneConfig =
+        serde_yaml::from_str(yaml).expect("Failed to parse zone config");
+
+    let query = |qtype| {
+        DnsPacket::builder()
+            .transaction_id(0x4242)
+            .add_question(DnsQuestion {
+                qname: "example.net".to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build()
+    };
+
+    let reply = construct_reply(&config, &query(Type::MX)).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::Refused);
+    assert!(reply.answers.is_empty());
+
+    let reply = construct_reply(&config, &query(Type::A)).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(reply.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+}
+
+#[test]
+fn test_reply_regex_rule() {
+    let yaml = r"
+internal:
+  records:
+  - {name: 'static', type: A, address: 10.0.0.1}
+rules:
+- {pattern: '^db-\d+\.internal$', type: A, address: 10.0.0.5}
+";
+    let config: ZoneConfig =
+        serde_yaml::from_str(yaml).expect("Failed to parse zone config");
+
+    let query = |qname: &str| {
+        DnsPacket::builder()
+            .transaction_id(0x0db0)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build()
+    };
+
+    let reply =
+        construct_reply(&config, &query("db-42.internal")).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(reply.answers[0].name, "db-42.internal");
+    assert_eq!(reply.answers[0].rdata, RData::A(Ipv4Addr::new(10, 0, 0, 5)));
+
+    let reply =
+        construct_reply(&config, &query("static.internal")).unwrap().unwrap();
+    assert_eq!(reply.answers[0].rdata, RData::A(Ipv4Addr::new(10, 0, 0, 1)));
+
+    let reply =
+        construct_reply(&config, &query("web-42.internal")).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NXDomain);
+    assert!(reply.answers.is_empty());
+}
+
+#[test]
+fn test_reload_bumps_served_soa_serial() {
+    let zone = |address: &str| -> ZoneConfig {
+        let yaml = format!(
+            "
+example.net:
+  auto_serial: true
+  records:
+  - {{name: '', type: SOA, address: 'ns1.example.net. hostmaster.example.net. 2024010101 7200 3600 1209600 300'}}
+  - {{name: 'www', type: A, address: {address}}}
+"
+        );
+        serde_yaml::from_str(&yaml).expect("Failed to parse zone config")
+    };
+    let query = DnsPacket::builder()
+        .transaction_id(0x50a0)
+        .add_question(DnsQuestion {
+            qname: "example.net".to_string(),
+            qtype: Type::SOA,
+            qclass: Class::IN,
+        })
+        .build();
+    let served_serial = |config: &ZoneConfig| {
+        let reply = construct_reply(config, &query).unwrap().unwrap();
+        match reply.answers[..] {
+            [DnsAnswer { rdata: RData::SOA { serial, .. }, .. }] => serial,
+            _ => panic!("Expected a single SOA answer"),
+        }
+    };
+
+    let old = zone("192.0.2.1");
+    let mut reloaded = zone("192.0.2.2");
+    reloaded.carry_serials_from(&old);
+
+    assert_eq!(served_serial(&old), 2024010101);
+    assert_eq!(served_serial(&reloaded), 2024010102);
+}
+
+#[test]
+fn test_reply_rotated_answer_order() {
+    let mut config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    config.answer_order = AnswerOrder::Rotate;
+
+    let query = DnsPacket::builder()
+        .transaction_id(0x0707)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+    let first_address = || {
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        assert_eq!(reply.answers.len(), 2);
+        reply.answers[0].rdata.clone()
+    };
+
+    let a = RData::A(Ipv4Addr::new(23, 192, 228, 80));
+    let b = RData::A(Ipv4Addr::new(23, 192, 228, 84));
+    assert_eq!(
+        [first_address(), first_address(), first_address()],
+        [a.clone(), b, a]
+    );
+}
+
+#[test]
+fn test_reply_canonical_answer_order() {
+    let config: ZoneConfig = "
+answer_order: canonical
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns2.example.net}
+  - {name: '', type: NS, address: ns10.example.net}
+  - {name: '', type: NS, address: a.example.org}
+  - {name: www, type: A, address: 192.0.2.10}
+  - {name: www, type: A, address: 192.0.2.9}
+  - {name: www, type: A, address: 10.0.0.1}
+"
+    .parse()
+    .unwrap();
+    let answers = |qname: &str, qtype| -> Vec<RData> {
+        let query = DnsPacket::builder()
+            .transaction_id(0x50f7)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        reply.answers.into_iter().map(|a| a.rdata).collect()
+    };
+
+    // by octets, which isn't how the addresses sort as text
+    assert_eq!(
+        answers("www.example.com", Type::A),
+        [
+            RData::A(Ipv4Addr::new(10, 0, 0, 1)),
+            RData::A(Ipv4Addr::new(192, 0, 2, 9)),
+            RData::A(Ipv4Addr::new(192, 0, 2, 10)),
+        ]
+    );
+    assert_eq!(
+        answers("example.com", Type::NS),
+        [
+            RData::NS("a.example.org".to_string()),
+            RData::NS("ns10.example.net".to_string()),
+            RData::NS("ns2.example.net".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_reply_drops_repeated_records() {
+    let config: ZoneConfig = "
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: www, type: A, address: 192.0.2.1}
+  - {name: www, type: A, address: 192.0.2.2}
+  - {name: www, type: A, address: 192.0.2.1, ttl: 60}
+"
+    .parse()
+    .unwrap();
+    let query = DnsPacket::builder()
+        .transaction_id(0xd0d0)
+        .add_question(DnsQuestion {
+            qname: "www.example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.an_count, 2);
+    let addresses: Vec<&RData> =
+        reply.answers.iter().map(|a| &a.rdata).collect();
+    assert_eq!(
+        addresses,
+        [
+            &RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            &RData::A(Ipv4Addr::new(192, 0, 2, 2))
+        ]
+    );
+}
+
+#[test]
+fn test_reply_chases_https_alias() {
+    let yaml = "
+example.com:
+  records:
+  - {name: '', type: HTTPS, address: '0 svc.example.net.'}
+example.net:
+  ttl: 30
+  records:
+  - {name: 'svc', type: HTTPS, address: '1 pool.example.net'}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let query = DnsPacket::builder()
+        .transaction_id(0x0808)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::HTTPS,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    let answers: Vec<(&str, u32, &RData)> = reply
+        .answers
+        .iter()
+        .map(|a| (a.name.as_str(), a.ttl, &a.rdata))
+        .collect();
+    assert_eq!(
+        answers,
+        vec![
+            (
+                "example.com",
+                5,
+                &RData::Svcb {
+                    priority: 0,
+                    target: "svc.example.net.".to_string(),
+                    params: vec![],
+                }
+            ),
+            (
+                "svc.example.net",
+                30,
+                &RData::Svcb {
+                    priority: 1,
+                    target: "pool.example.net".to_string(),
+                    params: vec![],
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_reply_raw_record_type() {
+    let yaml = "
+example.net:
+  records:
+  - {name: 'x', type: 99, rdata_hex: 'c0 00 02 01 ff'}
+  - {name: 'y', type: TYPE65280, rdata_base64: 'aGVsbG8='}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+
+    for (name, qtype, data) in [
+        ("x.example.net", Type::Other(99), b"\xc0\x00\x02\x01\xff".to_vec()),
+        ("y.example.net", Type::Other(65280), b"hello".to_vec()),
+    ] {
+        let query = DnsPacket::builder()
+            .transaction_id(0x0909)
+            .add_question(DnsQuestion {
+                qname: name.to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        let reply = parse_dns_query(&reply.serialize()).unwrap();
+
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(reply.answers[0].rtype, qtype);
+        assert_eq!(reply.answers[0].rdata, RData::Other(data));
+    }
+}
+
+#[test]
+fn test_missing_names_policy() {
+    let yaml = "
+closed.example:
+  records:
+  - {name: '@', type: SOA, address: 'ns.closed.example. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.closed.example}
+  - {name: 'a.b', type: A, address: 192.0.2.1}
+open.example:
+  missing_names: nodata
+  records:
+  - {name: '@', type: SOA, address: 'ns.open.example. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.open.example}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    let rcode = |qname: &str, qtype: Type| {
+        let query = DnsPacket::builder()
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        assert!(reply.answers.is_empty());
+        reply.header.rcode
+    };
+
+    // closed by default: only names that exist get NODATA
+    assert_eq!(rcode("missing.closed.example", Type::A), RCode::NXDomain);
+    assert_eq!(rcode("a.b.closed.example", Type::AAAA), RCode::NoError);
+    assert_eq!(rcode("b.closed.example", Type::A), RCode::NoError); // ENT
+    assert_eq!(rcode("c.a.b.closed.example", Type::A), RCode::NXDomain);
+
+    // open: every name gets NODATA
+    assert_eq!(rcode("missing.open.example", Type::A), RCode::NoError);
+    assert_eq!(rcode("open.example", Type::AAAA), RCode::NoError);
+}
+
+#[test]
+fn test_out_of_zone_names() {
+    let zone = "
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+";
+    let rcode = |config: &ZoneConfig, qname: &str| {
+        let query = DnsPacket::builder()
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        construct_reply(config, &query).unwrap().unwrap().header.rcode
+    };
+
+    // not authoritative for it, so refused by default
+    let config: ZoneConfig = serde_yaml::from_str(zone).unwrap();
+    assert_eq!(rcode(&config, "example.org"), RCode::Refused);
+    assert_eq!(rcode(&config, "missing.example.com"), RCode::NXDomain);
+
+    let yaml = format!("out_of_zone: nxdomain{zone}");
+    let config: ZoneConfig = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(rcode(&config, "example.org"), RCode::NXDomain);
+    assert_eq!(rcode(&config, "missing.example.com"), RCode::NXDomain);
+}
+
+#[test]
+fn test_reply_below_delegation_is_referral() {
+    let yaml = "
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'sub', type: NS, addresses: [ns.sub.example.com, ns.example.net]}
+  - {name: 'ns.sub', type: A, address: 192.0.2.53}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    // the glue itself is below the cut, so it's referred as well
+    for qname in ["host.sub.example.com", "ns.sub.example.com"] {
+        let query = DnsPacket::builder()
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        let reply = parse_dns_query(&reply.serialize()).unwrap();
+        // the child's to answer, so neither NXDOMAIN nor authoritative
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert!(!reply.header.authoritative_answer);
+        assert!(reply.answers.is_empty());
+        let name_servers: Vec<(&str, &RData)> = reply
+            .authorities
+            .iter()
+            .map(|record| (record.name.as_str(), &record.rdata))
+            .collect();
+        assert_eq!(
+            name_servers,
+            [
+                ("sub.example.com", &RData::NS("ns.sub.example.com".into())),
+                ("sub.example.com", &RData::NS("ns.example.net".into())),
+            ]
+        );
+        // an address only for the name server the zone knows of
+        assert_eq!(reply.additionals.len(), 1);
+        assert_eq!(reply.additionals[0].name, "ns.sub.example.com");
+        assert_eq!(
+            reply.additionals[0].rdata,
+            RData::A(Ipv4Addr::new(192, 0, 2, 53))
+        );
+    }
+}
+
+#[test]
+fn test_reply_minimal_responses() {
+    let zone = "
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'ns', type: A, address: 192.0.2.53}
+  - {name: 'www', type: A, address: 192.0.2.80}
+";
+    let query = DnsPacket::builder()
+        .add_question(DnsQuestion {
+            qname: "www.example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+
+    // full responses by default, with the zone's name servers
+    let config: ZoneConfig = serde_yaml::from_str(zone).unwrap();
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(reply.authorities.len(), 1);
+    assert_eq!(reply.authorities[0].name, "example.com");
+    assert_eq!(
+        reply.authorities[0].rdata,
+        RData::NS("ns.example.com".to_string())
+    );
+    assert_eq!(reply.additionals.len(), 1);
+    assert_eq!(
+        reply.additionals[0].rdata,
+        RData::A(Ipv4Addr::new(192, 0, 2, 53))
+    );
+
+    let yaml = format!("minimal_responses: true{zone}");
+    let config: ZoneConfig = serde_yaml::from_str(&yaml).unwrap();
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.answers.len(), 1);
+    assert!(reply.authorities.is_empty());
+    assert!(reply.additionals.is_empty());
+    assert_eq!(reply.header.ns_count, 0);
+    assert_eq!(reply.header.ar_count, 0);
+}
+
+#[test]
+fn test_reply_auto_ptr() {
+    let yaml = "
+auto_ptr: true
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'host', type: A, address: 1.2.3.4}
+3.2.1.in-addr.arpa:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+";
+    let mut config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    config.add_reverse_records();
+    assert_eq!(config.validate(), Ok(()));
+    let query = DnsPacket::builder()
+        .add_question(DnsQuestion {
+            qname: "4.3.2.1.in-addr.arpa".to_string(),
+            qtype: Type::PTR,
+            qclass: Class::IN,
+        })
+        .build();
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    let reply = parse_dns_query(&reply.serialize()).unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(reply.answers[0].name, "4.3.2.1.in-addr.arpa");
+    assert_eq!(
+        reply.answers[0].rdata,
+        RData::PTR("host.example.com".to_string())
+    );
+}
+
+#[test]
+fn test_reply_hinfo() {
+    let yaml = "
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'host', type: HINFO, cpu: RISC-V, os: Linux}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    let query = DnsPacket::builder()
+        .transaction_id(0x4846)
+        .add_question(DnsQuestion {
+            qname: "host.example.com".to_string(),
+            qtype: Type::HINFO,
+            qclass: Class::IN,
+        })
+        .build();
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    let reply = parse_dns_query(&reply.serialize()).unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(reply.answers[0].rtype, Type::HINFO);
+    assert_eq!(
+        reply.answers[0].rdata,
+        RData::HINFO { cpu: "RISC-V".to_string(), os: "Linux".to_string() }
+    );
+}
+
+#[test]
+fn test_reply_ds_from_parent_at_delegation() {
+    let yaml = "
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'sub', type: NS, address: ns.sub.example.com}
+  - {name: 'sub', type: DS, address: '60485 5 1 2BB183AF 5F225881'}
+sub.example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.sub.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.sub.example.com}
+  - {name: '@', type: A, address: 192.0.2.1}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    let query = |qtype: Type| {
+        let query = DnsPacket::builder()
+            .transaction_id(0x0a0a)
+            .add_question(DnsQuestion {
+                qname: "sub.example.com".to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build();
+        construct_reply(&config, &query).unwrap().unwrap()
+    };
+
+    let reply = query(Type::DS);
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert!(reply.header.authoritative_answer);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(
+        reply.answers[0].rdata,
+        RData::DS {
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: vec![0x2b, 0xb1, 0x83, 0xaf, 0x5f, 0x22, 0x58, 0x81],
+        }
+    );
+    // and the parent's name servers are the authority for them
+    assert_eq!(reply.authorities.len(), 1);
+    assert_eq!(reply.authorities[0].name, "example.com");
+    assert_eq!(
+        reply.authorities[0].rdata,
+        RData::NS("ns.example.com".to_string())
+    );
+
+    // everything else at the cut still comes from the child
+    let reply = query(Type::A);
+    assert_eq!(reply.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+}
+
+#[test]
+fn test_reply_ds_absent_at_cut() {
+    let yaml = "
+example.com:
+  ttl: 300
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 7 1 1 1 60'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'sub', type: NS, address: ns.sub.example.com}
+  - {name: 'away', type: NS, address: ns.example.net}
+sub.example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.sub.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.sub.example.com}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+
+    // a nested zone and a delegation to elsewhere, both unsigned
+    for qname in ["sub.example.com", "away.example.com"] {
+        let query = DnsPacket::builder()
+            .transaction_id(0x0a0b)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::DS,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        let reply = parse_dns_query(&reply.serialize()).unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError, "{qname}");
+        assert!(reply.header.authoritative_answer, "{qname}");
+        assert!(reply.answers.is_empty(), "{qname}");
+        assert_eq!(reply.authorities.len(), 1, "{qname}");
+        let soa = &reply.authorities[0];
+        assert_eq!(soa.name, "example.com");
+        assert_eq!(soa.ttl, 60); // capped by the SOA minimum
+        assert_eq!(
+            soa.rdata,
+            RData::SOA {
+                mname: "ns.example.com".to_string(),
+                rname: "host".to_string(),
+                serial: 7,
+                refresh: 1,
+                retry: 1,
+                expire: 1,
+                minimum: 60,
+            }
+        );
+    }
+}
+
+#[test]
+fn test_reply_reserved_edns_flags() {
+    let mut config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+
+    let mut query = DnsPacket::builder()
+        .transaction_id(0x0b0b)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+    query.edns = Some(EdnsOpt { flags: 0x4000, ..EdnsOpt::default() });
+    query.recount();
+    let query = parse_dns_query(&query.serialize()).unwrap();
+    assert_eq!(query.edns.as_ref().unwrap().reserved_flags(), 0x4000);
+
+    // must be ignored by default
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 2);
+
+    config.strict_edns = true;
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::FormErr);
+    assert!(reply.answers.is_empty());
+}
+
+#[test]
+fn test_reply_dname_synthesis() {
+    let yaml = "
+example.com:
+  ttl: 60
+  records:
+  - {name: 'old', type: DNAME, address: new.example.com}
+  - {name: 'host.new', type: A, address: 192.0.2.1}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let query = DnsPacket::builder()
+        .transaction_id(0x0c0c)
+        .add_question(DnsQuestion {
+            qname: "host.old.example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(
+        reply.answers,
+        vec![
+            DnsAnswer {
+                name: "old.example.com".to_string(),
+                rtype: Type::DNAME,
+                rclass: Class::IN,
+                ttl: 60,
+                rdata: RData::DNAME("new.example.com".to_string()),
+            },
+            DnsAnswer {
+                name: "host.old.example.com".to_string(),
+                rtype: Type::CNAME,
+                rclass: Class::IN,
+                ttl: 60,
+                rdata: RData::CNAME("host.new.example.com".to_string()),
+            },
+        ]
+    );
+    let reply = parse_dns_query(&reply.serialize()).unwrap();
+    assert_eq!(reply.answers.len(), 2);
+}
+
+#[test]
+fn test_reply_iquery_not_implemented() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let query = DnsPacket::builder()
+        .transaction_id(0x0d0d)
+        .opcode(OpCode::IQUERY)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert!(reply.header.response);
+    assert_eq!(reply.header.opcode, OpCode::IQUERY);
+    assert_eq!(reply.header.rcode, RCode::NotImp);
+    assert!(reply.answers.is_empty());
+}
+
+#[test]
+fn test_reply_unsupported_opcodes_not_implemented() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    for opcode in
+        [OpCode::IQUERY, OpCode::STATUS, OpCode::UPDATE, OpCode::Other(3)]
+    {
+        let query = DnsPacket::builder()
+            .transaction_id(0x1035)
+            .opcode(opcode)
+            .add_question(DnsQuestion {
+                qname: "example.com".to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        assert_eq!(reply.header.opcode, opcode);
+        assert_eq!(reply.header.rcode, RCode::NotImp);
+        assert!(reply.answers.is_empty());
+        assert_eq!(reply.header.an_count, 0);
+    }
+}
+
+#[test]
+fn test_reply_chaos_version_bind() {
+    let mut config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let query = |qname: &str| {
+        DnsPacket::builder()
+            .transaction_id(0x0c0c)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::TXT,
+                qclass: Class::CH,
+            })
+            .build()
+    };
+
+    let reply =
+        construct_reply(&config, &query("version.bind")).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(
+        reply.answers,
+        vec![DnsAnswer {
+            name: "version.bind".to_string(),
+            rtype: Type::TXT,
+            rclass: Class::CH,
+            ttl: 0,
+            rdata: RData::TXT(vec![env!("CARGO_PKG_VERSION").to_string()]),
+        }]
+    );
+    let reply = parse_dns_query(&reply.serialize()).unwrap();
+    assert_eq!(reply.answers[0].rclass, Class::CH);
+
+    config.version = "toy".to_string();
+    let reply =
+        construct_reply(&config, &query("VERSION.BIND")).unwrap().unwrap();
+    assert_eq!(reply.answers[0].rdata, RData::TXT(vec!["toy".to_string()]));
+
+    let reply =
+        construct_reply(&config, &query("example.com")).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::Refused);
+    assert!(reply.answers.is_empty());
+}
+
+#[test]
+fn test_reply_refuses_other_classes() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    for qclass in [Class::HS, Class::NONE, Class::Other(42)] {
+        let question = DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass,
+        };
+        let query = DnsPacket::builder()
+            .transaction_id(0x0042)
+            .add_question(question.clone())
+            .build();
+
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        assert_eq!(reply.header.rcode, RCode::Refused);
+        assert_eq!(reply.questions, vec![question]);
+        assert_eq!(reply.header.qd_count, 1);
+        assert!(reply.answers.is_empty());
+    }
+}
+
+#[test]
+fn test_reply_echoes_checking_disabled() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    for checking_disabled in [true, false] {
+        let mut query = DnsPacket::builder()
+            .add_question(DnsQuestion {
+                qname: "example.com".to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .checking_disabled(checking_disabled)
+            .build();
+        // set by the client, yet nothing here is validated to back it up
+        query.header.authenticated_data = true;
+        let query = parse_dns_query(&query.serialize()).unwrap();
+
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        let reply = parse_dns_query(&reply.serialize()).unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.header.checking_disabled, checking_disabled);
+        assert!(!reply.header.authenticated_data);
+    }
+}
+
+#[test]
+fn test_reply_class_any() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let question = DnsQuestion {
+        qname: "example.com".to_string(),
+        qtype: Type::A,
+        qclass: Class::ANY,
+    };
+    let query = DnsPacket::builder().add_question(question.clone()).build();
+    let query = parse_dns_query(&query.serialize()).unwrap();
+    assert_eq!(query.questions[0].qclass, Class::ANY);
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.questions, vec![question]);
+    let answers: Vec<(Class, &RData)> = reply
+        .answers
+        .iter()
+        .map(|answer| (answer.rclass, &answer.rdata))
+        .collect();
+    assert_eq!(
+        answers,
+        [
+            (Class::IN, &RData::A(Ipv4Addr::new(23, 192, 228, 80))),
+            (Class::IN, &RData::A(Ipv4Addr::new(23, 192, 228, 84))),
+        ]
+    );
+}
+
+#[test]
+fn test_reply_min_ttl() {
+    let yaml = "
+min_ttl: 60
+max_ttl: 3600
+example.com:
+  records:
+  - {name: '@', type: SOA, address: 'ns.example.com. host. 1 1 1 1 1'}
+  - {name: '@', type: NS, address: ns.example.com}
+  - {name: 'short', type: A, address: 192.0.2.1, ttl: 5}
+  - {name: 'long', type: A, address: 192.0.2.2, ttl: 86400}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    let ttl = |qname: &str| {
+        let query = DnsPacket::builder()
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        reply.answers[0].ttl
+    };
+    assert_eq!(ttl("short.example.com"), 60);
+    assert_eq!(ttl("long.example.com"), 3600);
+}
+
+#[test]
+fn test_reply_notify() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let notify = |zone: &str| {
+        let query = DnsPacket::builder()
+            .transaction_id(0x1996)
+            .opcode(OpCode::NOTIFY)
+            .authoritative_answer(true)
+            .add_question(DnsQuestion {
+                qname: zone.to_string(),
+                qtype: Type::SOA,
+                qclass: Class::IN,
+            })
+            .build();
+        let query = parse_dns_query(&query.serialize()).unwrap();
+        assert_eq!(query.header.opcode, OpCode::NOTIFY);
+        construct_reply(&config, &query).unwrap().unwrap()
+    };
+
+    let reply = notify("example.com");
+    assert!(reply.header.response);
+    assert!(reply.header.authoritative_answer);
+    assert_eq!(reply.header.opcode, OpCode::NOTIFY);
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.questions[0].qname, "example.com");
+    assert_eq!(reply.questions[0].qtype, Type::SOA);
+    assert!(reply.answers.is_empty());
+
+    let reply = notify("example.net");
+    assert!(!reply.header.authoritative_answer);
+    assert_eq!(reply.header.rcode, RCode::Refused);
+}
+
+#[test]
+fn test_reply_recursion_available() {
+    let mut config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let query = DnsPacket::builder()
+        .transaction_id(0x0f0f)
+        .recursion_desired(true)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert!(!reply.header.recursion_available);
+
+    config.forwarders = vec!["192.0.2.53:53".parse().unwrap()];
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert!(reply.header.recursion_available);
+    assert!(reply.header.recursion_desired);
+}
+
+#[test]
+fn test_reply_time_of_day_window() {
+    let yaml = "
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1, active: '08:00-18:00'}
+  - {name: 'www', type: A, address: 192.0.2.2, active: '18:00-08:00'}
+  - {name: 'office', type: A, address: 192.0.2.3, active: '09:00-17:00'}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let addresses = |name: &str, clock: &FakeClock| -> Vec<RData> {
+        let query = DnsPacket::builder()
+            .transaction_id(0x0e0e)
+            .add_question(DnsQuestion {
+                qname: name.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply_with_clock(&config, &query, clock)
+            .unwrap()
+            .unwrap();
+        reply.answers.into_iter().map(|a| a.rdata).collect()
+    };
+
+    // 2024-01-01 12:00 UTC
+    let clock = FakeClock::starting_at(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_110_400),
+    );
+    assert_eq!(
+        addresses("www.example.com", &clock),
+        vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))]
+    );
+    assert_eq!(
+        addresses("office.example.com", &clock),
+        vec![RData::A(Ipv4Addr::new(192, 0, 2, 3))]
+    );
+
+    clock.advance(Duration::from_secs(8 * 60 * 60)); // 20:00
+    assert_eq!(
+        addresses("www.example.com", &clock),
+        vec![RData::A(Ipv4Addr::new(192, 0, 2, 2))]
+    );
+    assert!(addresses("office.example.com", &clock).is_empty());
+}
+
+#[test]
+fn test_views_by_client_address() {
+    let yaml = "
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1}
+views:
+- name: internal
+  match_clients: [10.0.0.0/8, 'fd00::/8']
+  example.com:
+    records:
+    - {name: 'www', type: A, address: 10.0.0.80}
+- name: lab
+  match_clients: [10.1.0.0/16, 192.168.0.0/16]
+  example.com:
+    records:
+    - {name: 'www', type: A, address: 192.168.0.80}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let query = DnsPacket::builder()
+        .transaction_id(0x0f0f)
+        .add_question(DnsQuestion {
+            qname: "www.example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build();
+    let address_for = |client: &str| -> RData {
+        let view = config.view_for(client.parse().unwrap());
+        let reply = construct_reply(view, &query).unwrap().unwrap();
+        assert_eq!(reply.answers.len(), 1);
+        reply.answers[0].rdata.clone()
+    };
+
+    assert_eq!(
+        address_for("203.0.113.9"),
+        RData::A(Ipv4Addr::new(192, 0, 2, 1))
+    );
+    assert_eq!(address_for("10.2.3.4"), RData::A(Ipv4Addr::new(10, 0, 0, 80)));
+    assert_eq!(address_for("fd12::1"), RData::A(Ipv4Addr::new(10, 0, 0, 80)));
+    // the first matching view wins
+    assert_eq!(address_for("10.1.2.3"), RData::A(Ipv4Addr::new(10, 0, 0, 80)));
+    assert_eq!(
+        address_for("::ffff:192.168.1.1"),
+        RData::A(Ipv4Addr::new(192, 168, 0, 80))
+    );
+}
+
+#[test]
+fn test_invalid_views_are_rejected() {
+    let yaml = "
+views:
+- name: internal
+  match_clients: [10.0.0.0/8]
+  example.com:
+    records:
+    - {name: '', type: CNAME, address: other.example.net}
+    - {name: '', type: A, address: 10.0.0.1}
+- name: internal
+  match_clients: [10.0.0.0/8]
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let problems = config.validate().unwrap_err();
+    assert_eq!(
+        problems,
+        vec![
+            "In view 'internal': example.com: CNAME at the apex, which has \
+             to have SOA and NS records",
+            "In view 'internal': example.com: no SOA record at the apex",
+            "In view 'internal': example.com: no NS record at the apex",
+            "View 'internal' defined twice",
+        ]
+    );
+
+    let yaml = "views: [{name: x, match_clients: [10.0.0.0/33]}]";
+    assert!(serde_yaml::from_str::<ZoneConfig>(yaml).is_err());
+}
+
+#[test]
+fn test_client_subnet_echo() {
+    let yaml = "
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1}
+  - {name: 'www', type: A, address: 198.51.100.1, subnet: 203.0.113.0/24}
+  - {name: 'www', type: A, address: 198.51.100.6, subnet: '2001:db8::/32'}
+  - {name: 'mail', type: A, address: 192.0.2.25}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let ask =
+        |name: &str, subnet: ClientSubnet| -> (Vec<RData>, ClientSubnet) {
+            let query = DnsPacket::builder()
+                .transaction_id(0x0ec5)
+                .add_question(DnsQuestion {
+                    qname: name.to_string(),
+                    qtype: Type::A,
+                    qclass: Class::IN,
+                })
+                .edns(Some(EdnsOpt {
+                    options: vec![subnet.to_option()],
+                    ..EdnsOpt::default()
+                }))
+                .build();
+            let query = parse_dns_query(&query.serialize()).unwrap();
+            let reply = construct_reply(&config, &query).unwrap().unwrap();
+            let reply = parse_dns_query(&reply.serialize()).unwrap();
+            let echoed = reply.edns.unwrap().client_subnet().unwrap().unwrap();
+            (reply.answers.into_iter().map(|a| a.rdata).collect(), echoed)
+        };
+
+    let v4 = ClientSubnet {
+        address: "203.0.113.0".parse().unwrap(),
+        source_prefix: 24,
+        scope_prefix: 0,
+    };
+    let (addresses, echoed) = ask("www.example.com", v4);
+    assert_eq!(addresses, vec![RData::A(Ipv4Addr::new(198, 51, 100, 1))]);
+    assert_eq!(echoed, ClientSubnet { scope_prefix: 24, ..v4 });
+
+    let v6 = ClientSubnet {
+        address: "2001:db8:ab00::".parse().unwrap(),
+        source_prefix: 40,
+        scope_prefix: 0,
+    };
+    let (addresses, echoed) = ask("www.example.com", v6);
+    assert_eq!(addresses, vec![RData::A(Ipv4Addr::new(198, 51, 100, 6))]);
+    assert_eq!(echoed, ClientSubnet { scope_prefix: 40, ..v6 });
+
+    // elsewhere, the untagged record
+    let other = ClientSubnet {
+        address: "192.0.2.0".parse().unwrap(),
+        source_prefix: 24,
+        scope_prefix: 0,
+    };
+    let (addresses, echoed) = ask("www.example.com", other);
+    assert_eq!(addresses, vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))]);
+    assert_eq!(echoed.scope_prefix, 24);
+
+    // not depending on the subnet, valid for everyone
+    let (addresses, echoed) = ask("mail.example.com", v4);
+    assert_eq!(addresses, vec![RData::A(Ipv4Addr::new(192, 0, 2, 25))]);
+    assert_eq!(echoed, v4);
+
+    let malformed = DnsPacket::builder()
+        .add_question(DnsQuestion {
+            qname: "www.example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .edns(Some(EdnsOpt {
+            options: vec![(8, vec![0, 1, 24, 0, 203])],
+            ..EdnsOpt::default()
+        }))
+        .build();
+    let reply = construct_reply(&config, &malformed).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::FormErr);
+    assert!(reply.edns.unwrap().options.is_empty());
+}
+
+#[test]
+fn test_blocklist_sinkhole() {
+    let hosts = std::env::temp_dir()
+        .join(format!("toy-dns-server-blocklist-{}.hosts", std::process::id()));
+    fs::write(&hosts, "0.0.0.0 banner.example.com\n").unwrap();
+    let yaml = format!(
+        "
+blocklist:
+  names: [ads.example.com, '*.tracking.example.com']
+  hosts_files: ['{}']
+example.com:
+  records:
+  - {{name: 'ads', type: A, address: 192.0.2.66}}
+  - {{name: 'www', type: A, address: 192.0.2.1}}
+",
+        hosts.display()
+    );
+    let mut config: ZoneConfig = serde_yaml::from_str(&yaml).unwrap();
+    fs::remove_file(&hosts).ok();
+    let ask = |config: &ZoneConfig, name: &str, qtype: Type| -> DnsPacket {
+        let query = DnsPacket::builder()
+            .transaction_id(0xb10c)
+            .add_question(DnsQuestion {
+                qname: name.to_string(),
+                qtype,
+                qclass: Class::IN,
+            })
+            .build();
+        construct_reply(config, &query).unwrap().unwrap()
+    };
+    let rdata = |reply: DnsPacket| -> Vec<RData> {
+        reply.answers.into_iter().map(|a| a.rdata).collect()
+    };
+
+    // the zone's own record is overridden
+    let reply = ask(&config, "ads.example.com", Type::A);
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(rdata(reply), vec![RData::A(Ipv4Addr::UNSPECIFIED)]);
+    let reply = ask(&config, "x.tracking.example.com", Type::AAAA);
+    assert_eq!(rdata(reply), vec![RData::AAAA(Ipv6Addr::UNSPECIFIED)]);
+    let reply = ask(&config, "banner.example.com", Type::A);
+    assert_eq!(rdata(reply), vec![RData::A(Ipv4Addr::UNSPECIFIED)]);
+    let reply = ask(&config, "ads.example.com", Type::MX);
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert!(reply.answers.is_empty());
+
+    // everything else passes through
+    let reply = ask(&config, "www.example.com", Type::A);
+    assert_eq!(rdata(reply), vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))]);
+
+    config.blocklist.sinkhole =
+        Sinkhole::Address("192.0.2.53".parse().unwrap());
+    let reply = ask(&config, "ads.example.com", Type::A);
+    assert_eq!(rdata(reply), vec![RData::A(Ipv4Addr::new(192, 0, 2, 53))]);
+    let reply = ask(&config, "ads.example.com", Type::AAAA);
+    assert!(reply.answers.is_empty());
+
+    config.blocklist.sinkhole = Sinkhole::NxDomain;
+    let reply = ask(&config, "ads.example.com", Type::A);
+    assert_eq!(reply.header.rcode, RCode::NXDomain);
+}
+
+#[test]
+fn test_per_record_ttl() {
+    let yaml = "
+example.com:
+  ttl: 60
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1, ttl: 300}
+  - {name: 'www', type: A, address: 192.0.2.2}
+  - {name: 'www', type: AAAA, address: '2001:db8::1', ttl: 30}
+example.net:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.3}
+  - {name: 'www', type: A, address: 192.0.2.4, ttl: 3600}
+";
+    let config: ZoneConfig = serde_yaml::from_str(yaml).unwrap();
+    let ttls = |name: &str| -> Vec<(RData, u32)> {
+        let query = DnsPacket::builder()
+            .transaction_id(0x7171)
+            .add_question(DnsQuestion {
+                qname: name.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        reply.answers.into_iter().map(|a| (a.rdata, a.ttl)).collect()
+    };
+
+    assert_eq!(
+        ttls("www.example.com"),
+        vec![
+            (RData::A(Ipv4Addr::new(192, 0, 2, 1)), 300),
+            (RData::A(Ipv4Addr::new(192, 0, 2, 2)), 60),
+        ]
+    );
+    // falling back to the default without a zone TTL
+    assert_eq!(
+        ttls("www.example.net"),
+        vec![
+            (RData::A(Ipv4Addr::new(192, 0, 2, 3)), 5),
+            (RData::A(Ipv4Addr::new(192, 0, 2, 4)), 3600),
+        ]
+    );
+
+    let axfr = axfr_answers(&config, "example.com").unwrap();
+    let aaaa = axfr.iter().find(|a| a.rtype == Type::AAAA).unwrap();
+    assert_eq!(aaaa.ttl, 30);
+}
+
+#[test]
+fn test_merge_configs() {
+    let com: ZoneConfig = "
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1}
+"
+    .parse()
+    .unwrap();
+    let net: ZoneConfig = "
+forwarders: ['192.0.2.53:53']
+example.net:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.2}
+"
+    .parse()
+    .unwrap();
+    let config = com.clone().merge(net).unwrap();
+    assert!(config.recursion_available());
+
+    for (name, address) in [
+        ("www.example.com", Ipv4Addr::new(192, 0, 2, 1)),
+        ("www.example.net", Ipv4Addr::new(192, 0, 2, 2)),
+    ] {
+        let query = DnsPacket::builder()
+            .transaction_id(0x3e6e)
+            .add_question(DnsQuestion {
+                qname: name.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(reply.answers[0].rdata, RData::A(address));
+    }
+
+    let err = config.merge(com).unwrap_err();
+    assert_eq!(err, "Zones defined more than once: example.com");
+}
+
+#[test]
+fn test_dig_rendering() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let data = fs::read("tests/example.query.bin")
+        .expect("Failed to read example.query.bin");
+    let query = parse_dns_query(&data).expect("Failed to parse DNS query");
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+
+    assert_eq!(
+        reply.to_dig_string(),
+        "\
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 29982
+;; flags: qr rd; QUERY: 1, ANSWER: 2, AUTHORITY: 2, ADDITIONAL: 1
+
+;; OPT PSEUDOSECTION:
+; EDNS: version: 0, flags:; udp: 1232
+
+;; QUESTION SECTION:
+;example.com.\t\tIN\tA
+
+;; ANSWER SECTION:
+example.com.\t5\tIN\tA\t23.192.228.80
+example.com.\t5\tIN\tA\t23.192.228.84
+
+;; AUTHORITY SECTION:
+example.com.\t5\tIN\tNS\ta.iana-servers.net
+example.com.\t5\tIN\tNS\tb.iana-servers.net
+"
+    );
+}
+
+#[test]
+fn test_zero_question_query() {
+    let config = ZoneConfig::from_file("tests/example_zone.yaml")
+        .expect("Failed to load example zone");
+    let query = DnsPacket::builder()
+        .transaction_id(0x0000)
+        .recursion_desired(true)
+        .build();
+    assert_eq!(query.header.qd_count, 0);
+
+    let reply = construct_reply(&config, &query).unwrap().unwrap();
+    assert_eq!(reply.header.rcode, RCode::FormErr);
+    assert!(reply.header.response);
+    assert_eq!(reply.header.qd_count, 0);
+    assert!(reply.questions.is_empty());
+    assert!(reply.answers.is_empty());
+}
+
+#[test]
+fn test_oversized_ttl_is_clamped() {
+    let yaml = "
+default_ttl: 4000000000
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1, ttl: 3000000000}
+  - {name: 'www', type: A, address: 192.0.2.2, ttl: 2147483647}
+example.net:
+  ttl: 2147483648
+  records:
+  - {name: 'www', type: A, address: 192.0.2.3}
+example.org:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.4}
+";
+    let config: ZoneConfig = yaml.parse().unwrap();
+    let ttls = |name: &str| -> Vec<u32> {
+        let query = DnsPacket::builder()
+            .transaction_id(0x8000)
+            .add_question(DnsQuestion {
+                qname: name.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reply = construct_reply(&config, &query).unwrap().unwrap();
+        reply.answers.iter().map(|a| a.ttl).collect()
+    };
+    assert_eq!(ttls("www.example.com"), vec![0, 2_147_483_647]);
+    assert_eq!(ttls("www.example.net"), vec![0]);
+    assert_eq!(ttls("www.example.org"), vec![0]);
+}
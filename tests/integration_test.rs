@@ -1,85 +1,181 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt as _, Full};
+use hyper_util::rt::TokioIo;
 use regex::Regex;
 use std::io::{BufRead, BufReader};
-use std::sync::{Mutex, OnceLock};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::process::Command;
+use tokio_rustls::rustls;
+use toy_dns_server::{
+    AXFR_TYPE, COOKIE_OPTION, Class, Cookie, DNS_MESSAGE, DOH_PATH, DnsPacket,
+    DnsQuestion, ECHO_ADDRESS, EdnsOpt, ExtendedError, MAX_PIPELINED_QUERIES,
+    RCode, RData, Resolver, TCP_KEEPALIVE_OPTION, Type, parse_dns_query,
+};
 
 const TEST_ADDR: &str = "127.0.0.1";
 
 static UDP_PORT: OnceLock<u16> = OnceLock::new();
 static TCP_PORT: OnceLock<u16> = OnceLock::new();
-static SERVER_CHILD: OnceLock<Mutex<std::process::Child>> = OnceLock::new();
+static SERVER: OnceLock<TestServer> = OnceLock::new();
 
-async fn ensure_server_started() {
-    use std::sync::Once;
+/// A server process listening on ephemeral loopback ports, killed on drop.
+struct TestServer {
+    child: Mutex<std::process::Child>,
+    udp_addrs: Vec<SocketAddr>,
+    tcp_addrs: Vec<SocketAddr>,
+    /// TLS, HTTPS and HTTP ones, reported if the config sets them up.
+    other_addrs: Mutex<mpsc::Receiver<(String, SocketAddr)>>,
+}
 
-    static INIT: Once = Once::new();
+impl TestServer {
+    fn start(args: &[&str]) -> TestServer {
+        let mut command =
+            std::process::Command::new(env!("CARGO_BIN_EXE_toy-dns-server"));
+        command.arg("--listen").arg("127.0.0.1:0").args(args);
+        let listeners =
+            1 + args.iter().filter(|arg| **arg == "--listen").count();
+        Self::spawn(command, listeners)
+    }
 
-    // Use Once to ensure only one thread starts the server
-    INIT.call_once(|| {
-        let mut child =
-            std::process::Command::new(env!("CARGO_BIN_EXE_toy-dns-server"))
-                .arg("--listen")
-                .arg("127.0.0.1:0")
-                .arg("--config")
-                .arg("tests/example_zone.yaml")
-                .stderr(std::process::Stdio::piped())
-                .spawn()
-                .expect("Failed to start DNS server");
+    /// Waits for the server to report `listeners` pairs of sockets.
+    fn spawn(
+        mut command: std::process::Command,
+        listeners: usize,
+    ) -> TestServer {
+        let mut child = command
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("Failed to start DNS server");
 
         let stderr = child.stderr.take().expect("Failed to capture stderr");
+        let (udp_tx, udp_rx) = mpsc::channel();
+        let (tcp_tx, tcp_rx) = mpsc::channel();
+        let (other_tx, other_rx) = mpsc::channel();
 
-        // Store the child process immediately
-        SERVER_CHILD.set(Mutex::new(child)).ok();
-
-        // Spawn a thread to read stderr and extract ports
+        // Spawn a thread to read stderr and extract addresses
         // This thread keeps stderr open to prevent server from getting SIGPIPE
         std::thread::spawn(move || {
             let reader = BufReader::new(stderr);
-            let re_udp = Regex::new(r"127\.0\.0\.1:(\d+) \(UDP\)").unwrap();
-            let re_tcp = Regex::new(r"127\.0\.0\.1:(\d+) \(TCP\)").unwrap();
+            let re_udp = Regex::new(r"Listening on (\S+) \(UDP\)").unwrap();
+            let re_tcp = Regex::new(r"Listening on (\S+) \(TCP\)").unwrap();
+            let re_other =
+                Regex::new(r"Listening on (\S+) \((TLS|HTTPS?)\)").unwrap();
 
             for line in reader.lines().map_while(Result::ok) {
                 eprintln!("server> {}", line);
 
-                if UDP_PORT.get().is_none()
-                    && let Some(port_str) = re_udp.captures(&line)
-                    && let Ok(port) = port_str[1].parse::<u16>()
+                if let Some(addr_str) = re_udp.captures(&line)
+                    && let Ok(addr) = addr_str[1].parse::<SocketAddr>()
                 {
-                    UDP_PORT.set(port).ok();
+                    udp_tx.send(addr).ok();
                 }
 
-                if TCP_PORT.get().is_none()
-                    && let Some(port_str) = re_tcp.captures(&line)
-                    && let Ok(port) = port_str[1].parse::<u16>()
+                if let Some(addr_str) = re_tcp.captures(&line)
+                    && let Ok(addr) = addr_str[1].parse::<SocketAddr>()
                 {
-                    TCP_PORT.set(port).ok();
+                    tcp_tx.send(addr).ok();
+                }
+
+                if let Some(captures) = re_other.captures(&line)
+                    && let Ok(addr) = captures[1].parse::<SocketAddr>()
+                {
+                    other_tx.send((captures[2].to_string(), addr)).ok();
                 }
             }
         });
 
+        // Wait for addresses to be available
+        let wait = |rx: mpsc::Receiver<SocketAddr>| -> Vec<SocketAddr> {
+            (0..listeners)
+                .map(|_| rx.recv().expect("Server exited before binding"))
+                .collect()
+        };
+        let udp_addrs = wait(udp_rx);
+        let tcp_addrs = wait(tcp_rx);
+        TestServer {
+            child: Mutex::new(child),
+            udp_addrs,
+            tcp_addrs,
+            other_addrs: Mutex::new(other_rx),
+        }
+    }
+
+    fn udp_addr(&self) -> SocketAddr {
+        self.udp_addrs[0]
+    }
+
+    fn tcp_addr(&self) -> SocketAddr {
+        self.tcp_addrs[0]
+    }
+
+    /// The next address reported for `transport`: TLS, HTTPS or HTTP.
+    fn other_addr(&self, transport: &str) -> SocketAddr {
+        let other_addrs = self.other_addrs.lock().unwrap();
+        loop {
+            let (reported, addr) = other_addrs
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap_or_else(|_| panic!("Not listening over {transport}"));
+            if reported == transport {
+                return addr;
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn signal(&self, signal: libc::c_int) {
+        let pid = self.child.lock().unwrap().id() as libc::pid_t;
+        // SAFETY: a plain syscall, to a process that's still ours
+        assert_eq!(unsafe { libc::kill(pid, signal) }, 0);
+    }
+
+    fn stop(&self) {
+        if let Ok(mut child) = self.child.lock() {
+            eprintln!("Stopping DNS server...");
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn ensure_server_started() {
+    let server = SERVER.get_or_init(|| {
         // Register cleanup handler to kill server when tests exit
         extern "C" fn cleanup() {
-            stop_server();
+            if let Some(server) = SERVER.get() {
+                server.stop();
+            }
         }
         unsafe {
             libc::atexit(cleanup);
         }
-    });
 
-    // Wait for ports to be available
-    while UDP_PORT.get().is_none() || TCP_PORT.get().is_none() {
-        std::thread::sleep(std::time::Duration::from_millis(10));
-    }
+        TestServer::start(&["--config", "tests/example_zone.yaml"])
+    });
+    UDP_PORT.set(server.udp_addr().port()).ok();
+    TCP_PORT.set(server.tcp_addr().port()).ok();
 }
 
-fn stop_server() {
-    if let Some(child_mutex) = SERVER_CHILD.get()
-        && let Ok(mut child) = child_mutex.lock()
-    {
-        eprintln!("Stopping DNS server...");
-        let _ = child.kill();
-        let _ = child.wait();
-    }
+/// Writes a config for a single test into the temporary directory.
+fn write_config(test_name: &str, yaml: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "toy-dns-server-{}-{}.yaml",
+        test_name,
+        std::process::id()
+    ));
+    std::fs::write(&path, yaml).expect("Failed to write config");
+    path
 }
 
 async fn dig(
@@ -205,3 +301,1307 @@ async fn test_udp_nxdomain() {
     // Check answer count is 0
     assert!(output.contains("ANSWER: 0"), "Expected 0 answers");
 }
+
+fn server_addr(port: &OnceLock<u16>) -> SocketAddr {
+    SocketAddr::new(TEST_ADDR.parse().unwrap(), *port.get().unwrap())
+}
+
+#[tokio::test]
+async fn test_resolver_udp_query() {
+    ensure_server_started().await;
+
+    let reply = Resolver::new(server_addr(&UDP_PORT))
+        .query("example.com", Type::A)
+        .await
+        .expect("Resolver query failed");
+
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    let addresses: Vec<RData> =
+        reply.answers.into_iter().map(|answer| answer.rdata).collect();
+    assert_eq!(
+        addresses,
+        vec![
+            RData::A(Ipv4Addr::new(23, 192, 228, 80)),
+            RData::A(Ipv4Addr::new(23, 192, 228, 84)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_resolver_tcp_query() {
+    ensure_server_started().await;
+
+    let reply = Resolver::new(server_addr(&TCP_PORT))
+        .query_tcp("alias.example.org", Type::CNAME)
+        .await
+        .expect("Resolver query failed");
+
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(
+        reply.answers[0].rdata,
+        RData::CNAME("something-else.example.org".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_resolver_nxdomain() {
+    ensure_server_started().await;
+
+    let reply = Resolver::new(server_addr(&UDP_PORT))
+        .query("nonexistent.example.com", Type::A)
+        .await
+        .expect("Resolver query failed");
+
+    assert_eq!(reply.header.rcode, RCode::NXDomain);
+    assert!(reply.answers.is_empty());
+}
+
+#[tokio::test]
+async fn test_forwarding_to_upstream() {
+    ensure_server_started().await;
+
+    let config = write_config(
+        "forwarding",
+        &format!("forwarders: ['{TEST_ADDR}:{}']\n", UDP_PORT.get().unwrap()),
+    );
+    let forwarder = TestServer::start(&["--config", config.to_str().unwrap()]);
+
+    let resolver = Resolver::new(forwarder.udp_addr());
+    for _ in 0..2 {
+        // the second round is served from the cache
+        let reply = resolver
+            .query("example.com", Type::A)
+            .await
+            .expect("Resolver query failed");
+
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert!(reply.header.recursion_available);
+        let addresses: Vec<RData> =
+            reply.answers.into_iter().map(|answer| answer.rdata).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                RData::A(Ipv4Addr::new(23, 192, 228, 80)),
+                RData::A(Ipv4Addr::new(23, 192, 228, 84)),
+            ]
+        );
+    }
+
+    let reply = resolver
+        .query("nonexistent.example.com", Type::A)
+        .await
+        .expect("Resolver query failed");
+    assert_eq!(reply.header.rcode, RCode::NXDomain);
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_echo_mode() {
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--echo-mode",
+    ]);
+    let resolver = Resolver::new(server.udp_addr());
+
+    for name in ["example.com", "nonexistent.example.net"] {
+        let reply = resolver
+            .query(name, Type::AAAA)
+            .await
+            .expect("Resolver query failed");
+
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.questions[0].qname, name);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(reply.answers[0].name, name);
+        assert_eq!(reply.answers[0].rdata, RData::A(ECHO_ADDRESS));
+    }
+}
+
+/// Connects to `server` from a specific loopback address.
+async fn connect_from(source: IpAddr, server: SocketAddr) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(SocketAddr::new(source, 0)).unwrap();
+    socket.connect(server).await.expect("Failed to connect")
+}
+
+/// Sends a query over an open connection, None if the server closed it.
+async fn tcp_exchange(stream: &mut TcpStream) -> Option<DnsPacket> {
+    let query = DnsPacket::builder()
+        .transaction_id(0x1234)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build()
+        .serialize();
+    stream.write_u16(query.len() as u16).await.ok()?;
+    stream.write_all(&query).await.ok()?;
+    let length = stream.read_u16().await.ok()?;
+    let mut data = vec![0u8; length as usize];
+    stream.read_exact(&mut data).await.ok()?;
+    parse_dns_query(&data).ok()
+}
+
+#[tokio::test]
+async fn test_ephemeral_port_shared_by_udp_and_tcp() {
+    // the second goes through the path setting socket options itself
+    for extra_args in [&[][..], &["--ipv6-only", "false"]] {
+        let mut args = vec!["--config", "tests/example_zone.yaml"];
+        args.extend_from_slice(extra_args);
+        let server = TestServer::start(&args);
+        assert_eq!(server.udp_addr(), server.tcp_addr());
+    }
+}
+
+#[tokio::test]
+async fn test_udp_sockets_sharing_a_port() {
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--udp-sockets",
+        "4",
+    ]);
+    assert_eq!(server.udp_addr(), server.tcp_addr());
+    // each from a port of its own, for the kernel to spread them around
+    for _ in 0..16 {
+        let reply = Resolver::new(server.udp_addr())
+            .query("example.com", Type::A)
+            .await
+            .expect("No reply from a shared socket");
+        assert_eq!(reply.header.rcode, RCode::NoError);
+    }
+}
+
+/// A self-signed certificate for localhost and its key written into
+/// the temporary directory, along with a client config trusting it.
+fn write_certificate(
+    test_name: &str,
+) -> (PathBuf, PathBuf, Arc<rustls::ClientConfig>) {
+    let certified =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .unwrap();
+    let pem_path = |name: &str| {
+        std::env::temp_dir().join(format!(
+            "toy-dns-server-{}-{}-{}.pem",
+            test_name,
+            name,
+            std::process::id()
+        ))
+    };
+    let (certificate, key) = (pem_path("cert"), pem_path("key"));
+    std::fs::write(&certificate, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let client_config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (certificate, key, Arc::new(client_config))
+}
+
+async fn connect_tls(
+    addr: SocketAddr,
+    client_config: Arc<rustls::ClientConfig>,
+) -> tokio_rustls::client::TlsStream<TcpStream> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    tokio_rustls::TlsConnector::from(client_config)
+        .connect("localhost".try_into().unwrap(), stream)
+        .await
+        .expect("TLS handshake failed")
+}
+
+#[tokio::test]
+async fn test_dns_over_tls() {
+    let (certificate, key, client_config) = write_certificate("dot");
+    let zone = std::fs::read_to_string("tests/example_zone.yaml").unwrap();
+    let config = write_config(
+        "dot",
+        &format!(
+            "tls:\n  certificate: {}\n  key: {}\n  listen: ['{TEST_ADDR}:0']\n\
+             {zone}",
+            certificate.display(),
+            key.display()
+        ),
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let mut stream = connect_tls(server.other_addr("TLS"), client_config).await;
+
+    let query = DnsPacket::builder()
+        .transaction_id(0x0853)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build()
+        .serialize();
+    stream.write_u16(query.len() as u16).await.unwrap();
+    stream.write_all(&query).await.unwrap();
+    let length = stream.read_u16().await.unwrap();
+    let mut reply = vec![0; length as usize];
+    stream.read_exact(&mut reply).await.unwrap();
+    let reply = parse_dns_query(&reply).unwrap();
+    assert_eq!(reply.header.transaction_id, 0x0853);
+    assert!(
+        reply
+            .answers
+            .iter()
+            .any(|a| a.rdata == RData::A(Ipv4Addr::new(23, 192, 228, 80)))
+    );
+}
+
+#[tokio::test]
+async fn test_dns_over_https() {
+    let (certificate, key, client_config) = write_certificate("doh");
+    let zone = std::fs::read_to_string("tests/example_zone.yaml").unwrap();
+    let config = write_config(
+        "doh",
+        &format!(
+            "doh:\n  certificate: {}\n  key: {}\n  listen: ['{TEST_ADDR}:0']\n\
+             {zone}",
+            certificate.display(),
+            key.display()
+        ),
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let stream = connect_tls(server.other_addr("HTTPS"), client_config).await;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+
+    // a transaction ID of zero, as RFC 8484 asks for cache friendliness
+    let query = DnsPacket::builder()
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .build()
+        .serialize();
+    let request = hyper::Request::post(DOH_PATH)
+        .header(hyper::header::HOST, "localhost")
+        .header(hyper::header::CONTENT_TYPE, DNS_MESSAGE)
+        .body(Full::new(Bytes::from(query)))
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], DNS_MESSAGE);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let reply = parse_dns_query(&body).unwrap();
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert!(
+        reply
+            .answers
+            .iter()
+            .any(|a| a.rdata == RData::A(Ipv4Addr::new(23, 192, 228, 80)))
+    );
+
+    let request = hyper::Request::get("/elsewhere")
+        .header(hyper::header::HOST, "localhost")
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_health_probes() {
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--health-listen",
+        "127.0.0.1:0",
+    ]);
+    let stream = TcpStream::connect(server.other_addr("HTTP")).await.unwrap();
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+    for (path, status) in [
+        ("/livez", hyper::StatusCode::OK),
+        ("/readyz", hyper::StatusCode::OK),
+        ("/nowhere", hyper::StatusCode::NOT_FOUND),
+    ] {
+        let request = hyper::Request::get(path)
+            .header(hyper::header::HOST, "localhost")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), status, "{path}");
+    }
+    let request = hyper::Request::get("/metrics")
+        .header(hyper::header::HOST, "localhost")
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        body.contains("toy_dns_server_cache_hits_total{view=\"_default\"} 0"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn test_pidfile_written_and_removed() {
+    let pidfile = std::env::temp_dir()
+        .join(format!("toy-dns-server-{}.pid", std::process::id()));
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--pidfile",
+        pidfile.to_str().unwrap(),
+    ]);
+    let mut child = server.child.lock().unwrap();
+    let written = std::fs::read_to_string(&pidfile).unwrap();
+    assert_eq!(written.trim(), child.id().to_string());
+
+    // SAFETY: signalling a child that hasn't been waited for yet
+    assert_eq!(unsafe { libc::kill(child.id() as i32, libc::SIGTERM) }, 0);
+    assert!(child.wait().unwrap().success());
+    assert!(!pidfile.exists());
+}
+
+#[tokio::test]
+async fn test_query_log() {
+    let query_log = std::env::temp_dir()
+        .join(format!("toy-dns-server-{}-queries.log", std::process::id()));
+    std::fs::remove_file(&query_log).ok();
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--query-log",
+        query_log.to_str().unwrap(),
+    ]);
+    let resolver = Resolver::new(server.udp_addr());
+    resolver.query("example.com", Type::A).await.unwrap();
+    resolver.query("nonexistent.example.com", Type::AAAA).await.unwrap();
+    let mut stream =
+        connect_from(TEST_ADDR.parse().unwrap(), server.tcp_addr()).await;
+    tcp_exchange(&mut stream).await.unwrap();
+
+    // written out within a second
+    let mut lines = Vec::new();
+    for _ in 0..50 {
+        let log = std::fs::read_to_string(&query_log).unwrap_or_default();
+        lines = log.lines().map(str::to_string).collect();
+        if lines.len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let fields: Vec<Vec<&str>> =
+        lines.iter().map(|line| line.split('\t').collect()).collect();
+    assert_eq!(fields.len(), 3, "{lines:?}");
+    for line in &fields {
+        assert_eq!(line.len(), 7);
+        let (seconds, millis) = line[0].split_once('.').unwrap();
+        assert!(seconds.parse::<u64>().is_ok() && millis.len() == 3);
+        assert_eq!(line[1], TEST_ADDR);
+    }
+    assert_eq!(fields[0][2..], ["example.com", "A", "NoError", "2", "UDP"]);
+    assert_eq!(
+        fields[1][2..],
+        ["nonexistent.example.com", "AAAA", "NXDomain", "0", "UDP"]
+    );
+    assert_eq!(fields[2][6], "TCP");
+}
+
+#[tokio::test]
+async fn test_tcp_connections_per_ip_limit() {
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--max-tcp-conns-per-ip",
+        "2",
+    ]);
+    let client = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let other_client = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+
+    let mut first = connect_from(client, server.tcp_addr()).await;
+    let mut second = connect_from(client, server.tcp_addr()).await;
+    assert!(tcp_exchange(&mut first).await.is_some());
+    assert!(tcp_exchange(&mut second).await.is_some());
+
+    // the excess connection is closed without an answer
+    let mut excess = connect_from(client, server.tcp_addr()).await;
+    let read = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut buf = [0u8; 1];
+        excess.read(&mut buf).await
+    })
+    .await
+    .expect("Excess connection wasn't closed");
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    // another address still gets in, and so does this one after a close
+    let mut other = connect_from(other_client, server.tcp_addr()).await;
+    assert!(tcp_exchange(&mut other).await.is_some());
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut again = connect_from(client, server.tcp_addr()).await;
+    assert!(tcp_exchange(&mut again).await.is_some());
+}
+
+#[tokio::test]
+async fn test_tcp_pipelined_queries() {
+    ensure_server_started().await;
+    let mut stream = TcpStream::connect(server_addr(&TCP_PORT))
+        .await
+        .expect("Failed to connect");
+
+    // both queries go out before either reply is read
+    let mut queries = Vec::new();
+    for (id, qname) in [(1, "example.com"), (2, "subdomain.example.org")] {
+        let query = DnsPacket::builder()
+            .transaction_id(id)
+            .add_question(DnsQuestion {
+                qname: qname.to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build()
+            .serialize();
+        queries.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        queries.extend_from_slice(&query);
+    }
+    stream.write_all(&queries).await.unwrap();
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let length = stream.read_u16().await.expect("Missing reply");
+        let mut data = vec![0u8; length as usize];
+        stream.read_exact(&mut data).await.unwrap();
+        let reply = parse_dns_query(&data).unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert!(!reply.answers.is_empty());
+        ids.push(reply.header.transaction_id);
+    }
+    ids.sort_unstable();
+    assert_eq!(ids, [1, 2]);
+}
+
+#[tokio::test]
+async fn test_tcp_pipelining_is_bounded() {
+    // forwarded to a server that never answers, every query takes
+    // as long as the query timeout
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = write_config(
+        "pipelining_bound",
+        &format!("forwarders: ['{}']\n", silent.local_addr().unwrap()),
+    );
+    let server = TestServer::start(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--query-timeout",
+        "1",
+    ]);
+    let mut stream =
+        TcpStream::connect(server.tcp_addr()).await.expect("Failed to connect");
+
+    // one more query than are answered at a time
+    let last_id = MAX_PIPELINED_QUERIES as u16;
+    let mut queries = Vec::new();
+    for id in 0..=last_id {
+        let query = DnsPacket::builder()
+            .transaction_id(id)
+            .add_question(DnsQuestion {
+                qname: format!("host{id}.example.org"),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build()
+            .serialize();
+        queries.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        queries.extend_from_slice(&query);
+    }
+    let started = std::time::Instant::now();
+    stream.write_all(&queries).await.unwrap();
+
+    let mut arrivals = Vec::new();
+    for _ in 0..=last_id {
+        let length = stream.read_u16().await.expect("Missing reply");
+        let mut data = vec![0u8; length as usize];
+        stream.read_exact(&mut data).await.unwrap();
+        let reply = parse_dns_query(&data).unwrap();
+        assert_eq!(reply.header.rcode, RCode::ServFail);
+        arrivals.push((reply.header.transaction_id, started.elapsed()));
+    }
+    // the last one is only read once the others time out, and then
+    // takes its own timeout on top
+    let (id, elapsed) = arrivals.pop().unwrap();
+    assert_eq!(id, last_id);
+    assert!(elapsed >= Duration::from_secs(2), "{elapsed:?}");
+    for (id, elapsed) in arrivals {
+        assert!(elapsed < Duration::from_secs(2), "{id}: {elapsed:?}");
+    }
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_tcp_keepalive() {
+    let server = TestServer::start(&[
+        "--config",
+        "tests/example_zone.yaml",
+        "--tcp-idle-timeout",
+        "1",
+    ]);
+    let mut stream =
+        TcpStream::connect(server.tcp_addr()).await.expect("Failed to connect");
+
+    let mut edns = EdnsOpt::default();
+    edns.options.push((TCP_KEEPALIVE_OPTION, Vec::new()));
+    let query = DnsPacket::builder()
+        .transaction_id(0x4b41)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .edns(Some(edns))
+        .build()
+        .serialize();
+    stream.write_u16(query.len() as u16).await.unwrap();
+    stream.write_all(&query).await.unwrap();
+    let length = stream.read_u16().await.expect("Missing reply");
+    let mut data = vec![0u8; length as usize];
+    stream.read_exact(&mut data).await.unwrap();
+    let reply = parse_dns_query(&data).unwrap();
+    let edns = reply.edns.expect("Reply without OPT");
+    assert_eq!(edns.keepalive_timeout(), Some(Duration::from_secs(1)));
+
+    // and the connection is closed once idle for that long
+    let read = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut buf = [0u8; 1];
+        stream.read(&mut buf).await
+    })
+    .await
+    .expect("Idle connection wasn't closed");
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+/// Sends an A query for example.com with the cookie option `cookie`
+/// over UDP, returning the reply.
+async fn query_with_cookie(server: &TestServer, cookie: Vec<u8>) -> DnsPacket {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut edns = EdnsOpt::default();
+    edns.options.push((COOKIE_OPTION, cookie));
+    let query = DnsPacket::builder()
+        .transaction_id(0xc00c)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .edns(Some(edns))
+        .build()
+        .serialize();
+    socket.send_to(&query, server.udp_addr()).await.unwrap();
+    let mut buf = vec![0; 1232];
+    let size =
+        tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .expect("No reply")
+            .unwrap();
+    parse_dns_query(&buf[..size]).unwrap()
+}
+
+#[tokio::test]
+async fn test_dns_cookies() {
+    let server = TestServer::start(&["--config", "tests/example_zone.yaml"]);
+    let client = *b"clientck";
+
+    // a client cookie alone gets a server cookie back
+    let reply = query_with_cookie(&server, client.to_vec()).await;
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    let cookie = reply.edns.unwrap().cookie().unwrap().expect("No cookie");
+    assert_eq!(cookie.client, client);
+    assert_eq!(cookie.server.len(), 16);
+
+    // which is accepted next time, and renewed
+    let reply = query_with_cookie(&server, cookie.to_option().1).await;
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    assert!(!reply.answers.is_empty());
+    let renewed = reply.edns.unwrap().cookie().unwrap().expect("No cookie");
+    assert_eq!(renewed.client, client);
+    assert_eq!(renewed.server.len(), 16);
+
+    // a forged one is answered too, but replaced
+    let forged = Cookie { client, server: vec![1; 16] };
+    let reply = query_with_cookie(&server, forged.to_option().1).await;
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    let replaced = reply.edns.unwrap().cookie().unwrap().expect("No cookie");
+    assert_ne!(replaced.server, forged.server);
+
+    let reply = query_with_cookie(&server, b"short".to_vec()).await;
+    assert_eq!(reply.header.rcode, RCode::FormErr);
+}
+
+#[tokio::test]
+async fn test_response_rate_limiting() {
+    let config = write_config(
+        "rate_limit",
+        "
+rate_limit:
+  responses_per_second: 5
+  slip: 2
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: '', type: A, address: 192.0.2.1}
+",
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let query = |id| {
+        DnsPacket::builder()
+            .transaction_id(id)
+            .add_question(DnsQuestion {
+                qname: "example.com".to_string(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build()
+            .serialize()
+    };
+
+    let flooder = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for id in 0..30 {
+        flooder.send_to(&query(id), server.udp_addr()).await.unwrap();
+    }
+    let (mut answered, mut truncated) = (0, 0);
+    let mut buf = vec![0; 512];
+    while let Ok(received) =
+        tokio::time::timeout(Duration::from_secs(1), flooder.recv(&mut buf))
+            .await
+    {
+        let reply = parse_dns_query(&buf[..received.unwrap()]).unwrap();
+        if reply.header.truncation {
+            assert!(reply.answers.is_empty());
+            truncated += 1;
+        } else {
+            answered += 1;
+        }
+    }
+    assert!(answered >= 5, "only {answered} answered");
+    assert!(truncated > 0, "none truncated");
+    assert!(answered + truncated < 30, "none dropped");
+
+    // another /24 is unaffected
+    let bystander = tokio::net::UdpSocket::bind("127.0.1.1:0").await.unwrap();
+    bystander.send_to(&query(30), server.udp_addr()).await.unwrap();
+    let size =
+        tokio::time::timeout(Duration::from_secs(5), bystander.recv(&mut buf))
+            .await
+            .expect("Bystander got no reply")
+            .unwrap();
+    let reply = parse_dns_query(&buf[..size]).unwrap();
+    assert!(!reply.header.truncation);
+    assert_eq!(reply.answers.len(), 1);
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_unencodable_reply_becomes_servfail() {
+    // a TXT string can't be longer than 255 octets, so the reply can't be
+    // sent as it is: standing in for any failure on the server's side
+    let config = write_config(
+        "servfail",
+        &format!(
+            "
+version: {}
+example.com:
+  records:
+  - {{name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}}
+  - {{name: '', type: NS, address: ns.example.com}}
+",
+            "x".repeat(300)
+        ),
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let query = DnsPacket::builder()
+        .transaction_id(0x5e4f)
+        .add_question(DnsQuestion {
+            qname: "version.bind".to_string(),
+            qtype: Type::TXT,
+            qclass: Class::CH,
+        })
+        .edns(Some(EdnsOpt::default()))
+        .build()
+        .serialize();
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.send_to(&query, server.udp_addr()).await.unwrap();
+    let mut buf = vec![0; 1232];
+    let size =
+        tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .expect("Failure was dropped instead of answered")
+            .unwrap();
+    let reply = parse_dns_query(&buf[..size]).unwrap();
+    assert_eq!(reply.header.transaction_id, 0x5e4f);
+    assert_eq!(reply.header.rcode, RCode::ServFail);
+    assert_eq!(reply.questions[0].qname, "version.bind");
+    assert!(reply.answers.is_empty());
+    assert!(reply.edns.is_some());
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_query_timeout() {
+    // a forwarder that never answers makes for a lookup taking forever,
+    // or as long as the resolver's own five second timeout anyway
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = write_config(
+        "query_timeout",
+        &format!("forwarders: ['{}']\n", silent.local_addr().unwrap()),
+    );
+    let server = TestServer::start(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--query-timeout",
+        "1",
+    ]);
+
+    let started = std::time::Instant::now();
+    let reply = Resolver::new(server.udp_addr())
+        .query("example.org", Type::A)
+        .await
+        .expect("No reply after the query timeout");
+    assert_eq!(reply.header.rcode, RCode::ServFail);
+    assert!(reply.answers.is_empty());
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(4), "{elapsed:?}");
+
+    std::fs::remove_file(config).ok();
+}
+
+/// Reads the messages of a zone transfer up to the closing SOA.
+async fn read_transfer(stream: &mut TcpStream) -> Vec<DnsPacket> {
+    let mut messages: Vec<DnsPacket> = Vec::new();
+    let mut soas = 0;
+    while soas < 2 {
+        let length = stream.read_u16().await.expect("Transfer cut short");
+        let mut data = vec![0u8; length as usize];
+        stream.read_exact(&mut data).await.unwrap();
+        let message = parse_dns_query(&data).unwrap();
+        if message.header.rcode != RCode::NoError {
+            return vec![message];
+        }
+        soas += message.answers.iter().filter(|a| a.rtype == Type::SOA).count();
+        messages.push(message);
+    }
+    messages
+}
+
+#[tokio::test]
+async fn test_zone_transfer() {
+    let zone = std::fs::read_to_string("tests/example_zone.yaml").unwrap();
+    let config = write_config(
+        "axfr",
+        &format!("allow_transfer: [127.0.0.1/32]\n{zone}"),
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let query = DnsPacket::builder()
+        .transaction_id(0xaf4)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::Other(AXFR_TYPE),
+            qclass: Class::IN,
+        })
+        .build()
+        .serialize();
+
+    let client = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let mut stream = connect_from(client, server.tcp_addr()).await;
+    stream.write_u16(query.len() as u16).await.unwrap();
+    stream.write_all(&query).await.unwrap();
+    let answers: Vec<_> = read_transfer(&mut stream)
+        .await
+        .into_iter()
+        .inspect(|message| assert_eq!(message.header.transaction_id, 0xaf4))
+        .flat_map(|message| message.answers)
+        .collect();
+    // the seven records, with the SOA again at the end
+    assert_eq!(answers.len(), 8);
+    assert_eq!(answers[0].rtype, Type::SOA);
+    assert_eq!(answers[7], answers[0]);
+    assert_eq!(answers.iter().filter(|a| a.rtype == Type::SOA).count(), 2);
+
+    // other secondaries are turned away
+    let other_client = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    let mut stream = connect_from(other_client, server.tcp_addr()).await;
+    stream.write_u16(query.len() as u16).await.unwrap();
+    stream.write_all(&query).await.unwrap();
+    let refused = read_transfer(&mut stream).await;
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0].header.rcode, RCode::Refused);
+    assert!(refused[0].answers.is_empty());
+}
+
+#[tokio::test]
+async fn test_views_by_client_address() {
+    let config = write_config(
+        "views",
+        "
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: '', type: A, address: 192.0.2.1}
+views:
+- name: internal
+  match_clients: [127.0.0.2/32]
+  example.com:
+    records:
+    - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+    - {name: '', type: NS, address: ns.example.com}
+    - {name: '', type: A, address: 10.0.0.1}
+",
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+
+    for (client, address) in [
+        (Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(192, 0, 2, 1)),
+        (Ipv4Addr::new(127, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1)),
+    ] {
+        let mut stream =
+            connect_from(IpAddr::V4(client), server.tcp_addr()).await;
+        let reply = tcp_exchange(&mut stream).await.expect("No TCP reply");
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(reply.answers[0].rdata, RData::A(address));
+    }
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_canary_answered_in_views() {
+    let config = write_config(
+        "views-canary",
+        "
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: '', type: A, address: 192.0.2.1}
+views:
+- name: internal
+  match_clients: [127.0.0.2/32]
+  example.com:
+    records:
+    - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+    - {name: '', type: NS, address: ns.example.com}
+    - {name: '', type: A, address: 10.0.0.1}
+",
+    );
+    let server = TestServer::start(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--canary-name",
+        "canary.monitoring",
+    ]);
+    let query = DnsPacket::builder()
+        .transaction_id(0xca4)
+        .add_question(DnsQuestion {
+            qname: "canary.monitoring".to_string(),
+            qtype: Type::TXT,
+            qclass: Class::IN,
+        })
+        .build()
+        .serialize();
+
+    for client in [Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(127, 0, 0, 2)] {
+        let mut stream =
+            connect_from(IpAddr::V4(client), server.tcp_addr()).await;
+        stream.write_u16(query.len() as u16).await.unwrap();
+        stream.write_all(&query).await.unwrap();
+        let length = stream.read_u16().await.unwrap();
+        let mut data = vec![0u8; length as usize];
+        stream.read_exact(&mut data).await.unwrap();
+        let reply = parse_dns_query(&data).unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError, "{client}");
+        assert_eq!(reply.answers.len(), 1, "{client}");
+        assert_eq!(reply.answers[0].rtype, Type::TXT);
+    }
+
+    std::fs::remove_file(config).ok();
+}
+
+/// The reply over TCP to a single question for `qname`.
+async fn tcp_question(
+    server: &TestServer,
+    qname: &str,
+    qtype: Type,
+) -> DnsPacket {
+    let query = DnsPacket::builder()
+        .transaction_id(0x5e1)
+        .add_question(DnsQuestion {
+            qname: qname.to_string(),
+            qtype,
+            qclass: Class::IN,
+        })
+        .build()
+        .serialize();
+    let mut stream = TcpStream::connect(server.tcp_addr()).await.unwrap();
+    stream.write_u16(query.len() as u16).await.unwrap();
+    stream.write_all(&query).await.unwrap();
+    let length = stream.read_u16().await.unwrap();
+    let mut data = vec![0u8; length as usize];
+    stream.read_exact(&mut data).await.unwrap();
+    parse_dns_query(&data).unwrap()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_reload_bumps_auto_serial() {
+    let zone = |address: &str| {
+        format!(
+            "
+example.com:
+  auto_serial: true
+  records:
+  - {{name: '', type: SOA, address: 'ns. host. 7 1 1 1 1'}}
+  - {{name: '', type: NS, address: ns.example.com}}
+  - {{name: '', type: A, address: {address}}}
+"
+        )
+    };
+    let config = write_config("auto-serial", &zone("192.0.2.1"));
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let served_serial = || async {
+        let reply = tcp_question(&server, "example.com", Type::SOA).await;
+        match reply.answers[0].rdata {
+            RData::SOA { serial, .. } => serial,
+            ref rdata => panic!("Not an SOA: {rdata}"),
+        }
+    };
+    assert_eq!(served_serial().await, 7);
+
+    // the records change, the serial in the file doesn't
+    std::fs::write(&config, zone("192.0.2.2")).unwrap();
+    server.signal(libc::SIGHUP);
+    let mut serial = served_serial().await;
+    for _ in 0..50 {
+        if serial != 7 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        serial = served_serial().await;
+    }
+    assert_eq!(serial, 8);
+    let reply = tcp_question(&server, "example.com", Type::A).await;
+    assert_eq!(reply.answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 2)));
+
+    // an unchanged zone keeps the serial it's served with
+    server.signal(libc::SIGHUP);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(served_serial().await, 8);
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_denied_query_carries_extended_error() {
+    let config = write_config(
+        "allow-query",
+        "
+allow_query: [127.0.0.2/32]
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: '', type: A, address: 192.0.2.1}
+",
+    );
+    let server = TestServer::start(&["--config", config.to_str().unwrap()]);
+    let query = DnsPacket::builder()
+        .transaction_id(0xede0)
+        .add_question(DnsQuestion {
+            qname: "example.com".to_string(),
+            qtype: Type::A,
+            qclass: Class::IN,
+        })
+        .edns(Some(EdnsOpt::default()))
+        .build()
+        .serialize();
+    let ask_from = async |client: Ipv4Addr| -> DnsPacket {
+        let socket = tokio::net::UdpSocket::bind((client, 0)).await.unwrap();
+        socket.send_to(&query, server.udp_addr()).await.unwrap();
+        let mut buf = vec![0; 512];
+        let size =
+            tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+                .await
+                .expect("No reply")
+                .unwrap();
+        parse_dns_query(&buf[..size]).unwrap()
+    };
+
+    let denied = ask_from(Ipv4Addr::new(127, 0, 0, 1)).await;
+    assert_eq!(denied.header.rcode, RCode::Refused);
+    assert!(denied.answers.is_empty());
+    let ede = denied.edns.unwrap().extended_error().unwrap().unwrap();
+    assert_eq!(ede.info_code, ExtendedError::PROHIBITED);
+
+    let allowed = ask_from(Ipv4Addr::new(127, 0, 0, 2)).await;
+    assert_eq!(allowed.header.rcode, RCode::NoError);
+    assert_eq!(allowed.answers.len(), 1);
+    assert_eq!(allowed.edns.unwrap().extended_error().unwrap(), None);
+
+    std::fs::remove_file(config).ok();
+}
+
+#[tokio::test]
+async fn test_multiple_config_files() {
+    let zone = |name: &str, address: &str| {
+        format!(
+            "
+{name}:
+  records:
+  - {{name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}}
+  - {{name: '', type: NS, address: ns.{name}}}
+  - {{name: 'www', type: A, address: {address}}}
+"
+        )
+    };
+    let com = write_config("merge-com", &zone("example.com", "192.0.2.1"));
+    let net = write_config("merge-net", &zone("example.net", "192.0.2.2"));
+    let server = TestServer::start(&[
+        "--config",
+        com.to_str().unwrap(),
+        "--config",
+        net.to_str().unwrap(),
+    ]);
+    let resolver = Resolver::new(server.udp_addr());
+    for (name, address) in [
+        ("www.example.com", Ipv4Addr::new(192, 0, 2, 1)),
+        ("www.example.net", Ipv4Addr::new(192, 0, 2, 2)),
+    ] {
+        let reply = resolver.query(name, Type::A).await.unwrap();
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert_eq!(reply.answers[0].rdata, RData::A(address));
+    }
+    drop(server);
+
+    // the same zone twice is refused at startup
+    let status =
+        std::process::Command::new(env!("CARGO_BIN_EXE_toy-dns-server"))
+            .args(["--listen", "127.0.0.1:0"])
+            .args(["--config", com.to_str().unwrap()])
+            .args(["--config", com.to_str().unwrap()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+    assert!(!status.success());
+
+    std::fs::remove_file(com).ok();
+    std::fs::remove_file(net).ok();
+}
+
+#[tokio::test]
+async fn test_query_repl() {
+    let server = TestServer::start(&["--config", "tests/example_zone.yaml"]);
+    let mut repl = std::process::Command::new(env!("CARGO_BIN_EXE_query"))
+        .arg(server.udp_addr().to_string())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    use std::io::Write as _;
+    let mut stdin = repl.stdin.take().unwrap();
+    stdin.write_all(b"subdomain.example.org\n\nexample.org BOGUS\n").unwrap();
+    drop(stdin); // the end of input ends the session
+    let output = repl.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("status: NOERROR"));
+    assert!(stdout.contains("subdomain.example.org.\t7\tIN\tA\t172.66.157.88"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Unknown record type: BOGUS"));
+}
+
+#[test]
+fn test_check_config() {
+    let check = |yaml: &str| {
+        let config = write_config("check-config", yaml);
+        // unbindable, so serving would fail where checking doesn't
+        let output =
+            std::process::Command::new(env!("CARGO_BIN_EXE_toy-dns-server"))
+                .args(["--listen", "192.0.2.1:53"])
+                .args(["--config", config.to_str().unwrap()])
+                .arg("--check-config")
+                .output()
+                .unwrap();
+        std::fs::remove_file(config).ok();
+        output
+    };
+    let good = check(
+        "
+example.com:
+  records:
+  - {name: '', type: SOA, address: 'ns. host. 1 1 1 1 1'}
+  - {name: '', type: NS, address: ns.example.com}
+  - {name: 'www', type: A, address: 192.0.2.1}
+",
+    );
+    assert!(good.status.success());
+    let stdout = String::from_utf8(good.stdout).unwrap();
+    assert_eq!(stdout, "example.com: 3 records\nConfig OK\n");
+
+    let bad = check(
+        "
+example.com:
+  records:
+  - {name: 'www', type: A, address: 192.0.2.1}
+",
+    );
+    assert!(!bad.status.success());
+    let stderr = String::from_utf8(bad.stderr).unwrap();
+    assert!(stderr.contains("example.com: no SOA record at the apex"));
+    assert!(bad.stdout.is_empty());
+}
+
+#[tokio::test]
+async fn test_malformed_datagrams_are_survived() {
+    let server = TestServer::start(&["--config", "tests/example_zone.yaml"]);
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = vec![0; 512];
+
+    // too short for a header: dropped without an answer
+    for datagram in [&b""[..], b"\x12\x34\x01"] {
+        socket.send_to(datagram, server.udp_addr()).await.unwrap();
+    }
+    // a header, but a question running past the end: FormErr
+    socket
+        .send_to(
+            b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07exa",
+            server.udp_addr(),
+        )
+        .await
+        .unwrap();
+    let size =
+        tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .expect("No FormErr reply")
+            .unwrap();
+    let reply = parse_dns_query(&buf[..size]).unwrap();
+    assert_eq!(reply.header.transaction_id, 0x1234);
+    assert_eq!(reply.header.rcode, RCode::FormErr);
+
+    let reply = Resolver::new(server.udp_addr())
+        .query("example.com", Type::A)
+        .await
+        .expect("Server stopped serving");
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    let mut stream =
+        connect_from(TEST_ADDR.parse().unwrap(), server.tcp_addr()).await;
+    assert!(tcp_exchange(&mut stream).await.is_some());
+}
+
+#[tokio::test]
+async fn test_systemd_socket_activation() {
+    use std::os::fd::AsRawFd as _;
+    use std::os::unix::process::CommandExt as _;
+
+    let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let (udp_fd, tcp_fd) = (udp.as_raw_fd(), tcp.as_raw_fd());
+
+    // like systemd: the sockets become fds 3 and 4, and LISTEN_PID is set
+    // to the pid of the server, which is the shell's pid after exec
+    let mut command = std::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg("export LISTEN_PID=$$ LISTEN_FDS=2; exec \"$@\"")
+        .arg("sh")
+        .arg(env!("CARGO_BIN_EXE_toy-dns-server"))
+        .args(["--listen", "127.0.0.1:0"])
+        .args(["--config", "tests/example_zone.yaml"]);
+    unsafe {
+        command.pre_exec(move || {
+            // move out of the way first in case they already are 3 or 4
+            let udp_fd = libc::fcntl(udp_fd, libc::F_DUPFD, 10);
+            let tcp_fd = libc::fcntl(tcp_fd, libc::F_DUPFD, 10);
+            if udp_fd < 0
+                || tcp_fd < 0
+                || libc::dup2(udp_fd, 3) < 0
+                || libc::dup2(tcp_fd, 4) < 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let server = TestServer::spawn(command, 1);
+    assert_eq!(server.udp_addr(), udp.local_addr().unwrap());
+    assert_eq!(server.tcp_addr(), tcp.local_addr().unwrap());
+    drop((udp, tcp));
+
+    let reply = Resolver::new(server.udp_addr())
+        .query("example.com", Type::A)
+        .await
+        .expect("Resolver query failed");
+    assert_eq!(reply.header.rcode, RCode::NoError);
+    let mut stream =
+        connect_from(TEST_ADDR.parse().unwrap(), server.tcp_addr()).await;
+    let reply = tcp_exchange(&mut stream).await.expect("No TCP reply");
+    assert_eq!(reply.header.rcode, RCode::NoError);
+}
+
+#[tokio::test]
+async fn test_multiple_listen_addresses() {
+    let server = TestServer::start(&[
+        "--listen",
+        "127.0.0.2:0",
+        "--config",
+        "tests/example_zone.yaml",
+    ]);
+    assert_eq!(server.udp_addrs.len(), 2);
+    assert_eq!(server.udp_addrs[1].ip(), Ipv4Addr::new(127, 0, 0, 2));
+
+    for (udp_addr, tcp_addr) in server.udp_addrs.iter().zip(&server.tcp_addrs) {
+        let reply = Resolver::new(*udp_addr)
+            .query("example.com", Type::A)
+            .await
+            .expect("Resolver query failed");
+        assert_eq!(reply.header.rcode, RCode::NoError);
+        assert!(!reply.answers.is_empty());
+
+        let mut stream =
+            connect_from(TEST_ADDR.parse().unwrap(), *tcp_addr).await;
+        let reply = tcp_exchange(&mut stream).await.expect("No TCP reply");
+        assert_eq!(reply.header.rcode, RCode::NoError);
+    }
+}
+
+#[tokio::test]
+async fn test_ipv6_only() {
+    for ipv6_only in [true, false] {
+        let server = TestServer::start(&[
+            "--listen",
+            "[::]:0",
+            "--ipv6-only",
+            &ipv6_only.to_string(),
+            "--config",
+            "tests/example_zone.yaml",
+        ]);
+        let port = server.tcp_addrs[1].port();
+
+        let ipv6 = TcpStream::connect((Ipv6Addr::LOCALHOST, port)).await;
+        assert!(ipv6.is_ok());
+        let ipv4 = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await;
+        assert_eq!(ipv4.is_ok(), !ipv6_only);
+    }
+}
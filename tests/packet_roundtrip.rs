@@ -0,0 +1,164 @@
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use toy_dns_server::{
+    Class, DnsAnswer, DnsHeader, DnsPacket, DnsQuestion, EdnsOpt, OpCode,
+    RCode, RData, Type, parse_dns_message,
+};
+
+/// Names of up to four labels, the root included. Case is kept as is,
+/// so it's mixed to catch anything folding it on one side only.
+fn name() -> impl Strategy<Value = String> {
+    vec("[a-zA-Z0-9_-]{1,50}", 0..=4).prop_map(|labels| labels.join("."))
+}
+
+fn opcode() -> impl Strategy<Value = OpCode> {
+    prop_oneof![
+        Just(OpCode::QUERY),
+        Just(OpCode::IQUERY),
+        Just(OpCode::STATUS),
+        Just(OpCode::NOTIFY),
+        Just(OpCode::UPDATE),
+        // the unassigned ones of the four bits
+        prop_oneof![Just(3), 6u8..16].prop_map(OpCode::Other),
+    ]
+}
+
+/// Those fitting the header, the rest needing an OPT record to carry them.
+fn rcode() -> impl Strategy<Value = RCode> {
+    prop::sample::select(vec![
+        RCode::NoError,
+        RCode::FormErr,
+        RCode::ServFail,
+        RCode::NXDomain,
+        RCode::NotImp,
+        RCode::Refused,
+        RCode::YXDomain,
+        RCode::YXRRSet,
+        RCode::NXRRSet,
+        RCode::NotAuth,
+        RCode::NotZone,
+    ])
+}
+
+/// A header with the section counts left at zero for the packet to fill,
+/// and the Z bit clear, as headers with it set aren't accepted.
+fn header() -> impl Strategy<Value = DnsHeader> {
+    (any::<u16>(), opcode(), rcode(), any::<[bool; 7]>()).prop_map(
+        |(transaction_id, opcode, rcode, flags)| DnsHeader {
+            transaction_id,
+            response: flags[0],
+            opcode,
+            authoritative_answer: flags[1],
+            truncation: flags[2],
+            recursion_desired: flags[3],
+            recursion_available: flags[4],
+            _reserved: false,
+            authenticated_data: flags[5],
+            checking_disabled: flags[6],
+            rcode,
+            qd_count: 0,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        },
+    )
+}
+
+fn question() -> impl Strategy<Value = DnsQuestion> {
+    (name(), any::<u16>(), any::<u16>()).prop_map(|(qname, qtype, qclass)| {
+        DnsQuestion {
+            qname,
+            qtype: Type::from(qtype),
+            qclass: Class::from(qclass),
+        }
+    })
+}
+
+fn rdata() -> impl Strategy<Value = (Type, RData)> {
+    prop_oneof![
+        any::<[u8; 4]>()
+            .prop_map(|octets| (Type::A, RData::A(Ipv4Addr::from(octets)))),
+        any::<[u8; 16]>().prop_map(|octets| {
+            (Type::AAAA, RData::AAAA(Ipv6Addr::from(octets)))
+        }),
+        name().prop_map(|name| (Type::NS, RData::NS(name))),
+        name().prop_map(|name| (Type::CNAME, RData::CNAME(name))),
+    ]
+}
+
+/// TTLs with the top bit set are read back as zero, so they're left out.
+fn answer() -> impl Strategy<Value = DnsAnswer> {
+    (name(), rdata(), any::<u16>(), 0..=i32::MAX as u32).prop_map(
+        |(name, (rtype, rdata), rclass, ttl)| DnsAnswer {
+            name,
+            rtype,
+            rclass: Class::from(rclass),
+            ttl,
+            rdata,
+        },
+    )
+}
+
+/// An OPT record with its extended RCODE left to the header's RCODE.
+fn edns() -> impl Strategy<Value = EdnsOpt> {
+    let options = vec((any::<u16>(), vec(any::<u8>(), 0..16)), 0..4);
+    (any::<u16>(), any::<u8>(), any::<u16>(), options).prop_map(
+        |(udp_payload_size, version, flags, options)| EdnsOpt {
+            udp_payload_size,
+            extended_rcode: 0,
+            version,
+            flags,
+            options,
+        },
+    )
+}
+
+fn packet() -> impl Strategy<Value = DnsPacket> {
+    (
+        header(),
+        vec(question(), 0..4),
+        vec(answer(), 0..8),
+        vec(answer(), 0..4),
+        vec(answer(), 0..4),
+        option::of(edns()),
+    )
+        .prop_map(
+            |(header, questions, answers, authorities, additionals, edns)| {
+                let mut packet = DnsPacket {
+                    header,
+                    questions,
+                    answers,
+                    authorities,
+                    additionals,
+                    unparsed: Vec::new(),
+                    edns,
+                };
+                packet.recount();
+                packet
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn packet_survives_roundtrip(packet in packet()) {
+        let serialized = packet.serialize();
+        let reparsed = parse_dns_message(&serialized).unwrap();
+        prop_assert_eq!(reparsed, packet);
+    }
+
+    #[test]
+    fn name_survives_roundtrip(name in name()) {
+        let packet = DnsPacket::builder()
+            .add_question(DnsQuestion {
+                qname: name.clone(),
+                qtype: Type::A,
+                qclass: Class::IN,
+            })
+            .build();
+        let reparsed = parse_dns_message(&packet.serialize()).unwrap();
+        prop_assert_eq!(&reparsed.questions[0].qname, &name);
+    }
+}